@@ -0,0 +1,4 @@
+fn main() {
+    // `embed_migrations!` reads the migrations at compile time
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,3 @@
+ALTER TABLE invites ALTER COLUMN kind TYPE VARCHAR USING kind::text;
+
+DROP TYPE invite_kind;
@@ -0,0 +1,6 @@
+CREATE TYPE invite_kind AS ENUM ('email', 'link');
+
+-- invites only holds pending invites, so rewriting it is quick. A new column would need the app
+-- to write both while deploying, for little gain.
+-- lint: allow column-type-change
+ALTER TABLE invites ALTER COLUMN kind TYPE invite_kind USING kind::invite_kind;
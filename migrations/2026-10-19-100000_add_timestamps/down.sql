@@ -0,0 +1,28 @@
+DROP TRIGGER set_updated_at ON posts;
+ALTER TABLE posts DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON books;
+ALTER TABLE books DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON pages;
+ALTER TABLE pages DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON authors;
+ALTER TABLE authors DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON books_authors;
+ALTER TABLE books_authors DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON address;
+ALTER TABLE address DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON items;
+ALTER TABLE items DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON reports;
+ALTER TABLE reports DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP TRIGGER set_updated_at ON invites;
+ALTER TABLE invites DROP COLUMN created_at, DROP COLUMN updated_at;
+
+DROP FUNCTION add_timestamps(regclass);
@@ -0,0 +1,28 @@
+-- Adds `created_at` and `updated_at` columns to the given table and keeps `updated_at` current
+-- with the trigger from `diesel_manage_updated_at`. Call this for every new table.
+--
+-- # Example
+--
+-- ```sql
+-- CREATE TABLE users (id SERIAL PRIMARY KEY);
+--
+-- SELECT add_timestamps('users');
+-- ```
+CREATE OR REPLACE FUNCTION add_timestamps(_tbl regclass) RETURNS VOID AS $$
+BEGIN
+    EXECUTE format('ALTER TABLE %s
+                    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                    ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now()', _tbl);
+    PERFORM diesel_manage_updated_at(_tbl);
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT add_timestamps('posts');
+SELECT add_timestamps('books');
+SELECT add_timestamps('pages');
+SELECT add_timestamps('authors');
+SELECT add_timestamps('books_authors');
+SELECT add_timestamps('address');
+SELECT add_timestamps('items');
+SELECT add_timestamps('reports');
+SELECT add_timestamps('invites');
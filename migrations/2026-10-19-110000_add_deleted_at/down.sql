@@ -0,0 +1,5 @@
+ALTER TABLE books DROP COLUMN deleted_at;
+ALTER TABLE pages DROP COLUMN deleted_at;
+ALTER TABLE authors DROP COLUMN deleted_at;
+ALTER TABLE address DROP COLUMN deleted_at;
+ALTER TABLE invites DROP COLUMN deleted_at;
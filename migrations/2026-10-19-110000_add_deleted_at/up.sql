@@ -0,0 +1,5 @@
+ALTER TABLE books ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE pages ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE authors ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE address ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE invites ADD COLUMN deleted_at TIMESTAMPTZ;
@@ -0,0 +1,6 @@
+DROP TRIGGER audit_history ON books;
+DROP TRIGGER audit_history ON authors;
+DROP TRIGGER audit_history ON posts;
+DROP TRIGGER audit_history ON invites;
+
+DROP SCHEMA audit CASCADE;
@@ -0,0 +1,66 @@
+CREATE SCHEMA audit;
+
+CREATE TABLE audit.history (
+    id BIGSERIAL PRIMARY KEY,
+    table_name TEXT NOT NULL,
+    row_id TEXT NOT NULL,
+    operation TEXT NOT NULL CHECK (operation IN ('INSERT', 'UPDATE', 'DELETE')),
+    old_row JSONB,
+    new_row JSONB,
+    actor_id TEXT,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX history_table_name_row_id_idx ON audit.history (table_name, row_id);
+
+-- Records the old and new row of every change. The actor is read from the `app.actor_id`
+-- setting, which the application sets per request (see `audit::with_actor`).
+CREATE OR REPLACE FUNCTION audit.log_change() RETURNS trigger AS $$
+DECLARE
+    _old JSONB;
+    _new JSONB;
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        _old := to_jsonb(OLD);
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        _new := to_jsonb(NEW);
+    END IF;
+
+    -- Updates that don't change anything (apart from the timestamp trigger) are not interesting
+    IF TG_OP = 'UPDATE' AND (_old - 'updated_at') = (_new - 'updated_at') THEN
+        RETURN NULL;
+    END IF;
+
+    INSERT INTO audit.history (table_name, row_id, operation, old_row, new_row, actor_id)
+    VALUES (
+        TG_TABLE_NAME,
+        coalesce(_new, _old) ->> 'id',
+        TG_OP,
+        _old,
+        _new,
+        nullif(current_setting('app.actor_id', true), '')
+    );
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Starts recording the history of the given table, which must have an `id` column.
+--
+-- # Example
+--
+-- ```sql
+-- SELECT audit.enable('books');
+-- ```
+CREATE OR REPLACE FUNCTION audit.enable(_tbl regclass) RETURNS VOID AS $$
+BEGIN
+    EXECUTE format('CREATE TRIGGER audit_history AFTER INSERT OR UPDATE OR DELETE ON %s
+                    FOR EACH ROW EXECUTE PROCEDURE audit.log_change()', _tbl);
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT audit.enable('books');
+SELECT audit.enable('authors');
+SELECT audit.enable('posts');
+SELECT audit.enable('invites');
@@ -0,0 +1,3 @@
+-- Incremented on every update by `Versioned::update_with_version`, see src/versioned.rs
+ALTER TABLE posts ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE books ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,33 @@
+ALTER TABLE pages DROP COLUMN search, DROP COLUMN language;
+ALTER TABLE posts DROP COLUMN search, DROP COLUMN language;
+
+CREATE OR REPLACE FUNCTION audit.log_change() RETURNS trigger AS $$
+DECLARE
+    _old JSONB;
+    _new JSONB;
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        _old := to_jsonb(OLD);
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        _new := to_jsonb(NEW);
+    END IF;
+
+    -- Updates that don't change anything (apart from the timestamp trigger) are not interesting
+    IF TG_OP = 'UPDATE' AND (_old - 'updated_at') = (_new - 'updated_at') THEN
+        RETURN NULL;
+    END IF;
+
+    INSERT INTO audit.history (table_name, row_id, operation, old_row, new_row, actor_id)
+    VALUES (
+        TG_TABLE_NAME,
+        coalesce(_new, _old) ->> 'id',
+        TG_OP,
+        _old,
+        _new,
+        nullif(current_setting('app.actor_id', true), '')
+    );
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
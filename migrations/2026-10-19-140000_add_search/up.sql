@@ -0,0 +1,62 @@
+-- The text search configuration (e.g. 'english', 'german') decides how words are stemmed, so it is
+-- stored per row and searches filter on it, see src/search.rs. 'simple' only lowercases.
+-- `search` is never null since its inputs aren't, declared so it matches `Tsvector` in schema.rs.
+-- A stored generated column can only be added by rewriting the table. pages and posts are small
+-- enough for that to be quick; a big table would get a plain column kept up to date by a trigger,
+-- filled in batches.
+-- lint: allow table-rewrite
+ALTER TABLE pages
+    ADD COLUMN language regconfig NOT NULL DEFAULT 'simple',
+    ADD COLUMN search tsvector NOT NULL GENERATED ALWAYS AS (to_tsvector(language, content)) STORED;
+
+-- lint: allow table-rewrite
+ALTER TABLE posts
+    ADD COLUMN language regconfig NOT NULL DEFAULT 'simple',
+    ADD COLUMN search tsvector NOT NULL GENERATED ALWAYS AS (
+        setweight(to_tsvector(language, title), 'A') || setweight(to_tsvector(language, body), 'B')
+    ) STORED;
+
+-- Adding the generated columns above rewrites both tables under an exclusive lock anyway, so
+-- building the indexes concurrently wouldn't shorten the time writes are blocked
+-- lint: allow index-not-concurrent
+CREATE INDEX pages_search_idx ON pages USING GIN (search);
+-- lint: allow index-not-concurrent
+CREATE INDEX posts_search_idx ON posts USING GIN (search);
+
+-- Generated columns like `search` are derived from the other columns, leave them out of the
+-- audit history
+CREATE OR REPLACE FUNCTION audit.log_change() RETURNS trigger AS $$
+DECLARE
+    _generated TEXT[];
+    _old JSONB;
+    _new JSONB;
+BEGIN
+    _generated := ARRAY(
+        SELECT attname FROM pg_attribute WHERE attrelid = TG_RELID AND attgenerated <> ''
+    );
+
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        _old := to_jsonb(OLD) - _generated;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        _new := to_jsonb(NEW) - _generated;
+    END IF;
+
+    -- Updates that don't change anything (apart from the timestamp trigger) are not interesting
+    IF TG_OP = 'UPDATE' AND (_old - 'updated_at') = (_new - 'updated_at') THEN
+        RETURN NULL;
+    END IF;
+
+    INSERT INTO audit.history (table_name, row_id, operation, old_row, new_row, actor_id)
+    VALUES (
+        TG_TABLE_NAME,
+        coalesce(_new, _old) ->> 'id',
+        TG_OP,
+        _old,
+        _new,
+        nullif(current_setting('app.actor_id', true), '')
+    );
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,4 @@
+DROP INDEX books_title_trgm_idx;
+DROP INDEX authors_name_trgm_idx;
+
+-- pg_trgm stays, up.sql may not have created it and something else may use it
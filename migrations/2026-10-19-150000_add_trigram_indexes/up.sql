@@ -0,0 +1,8 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- GiST rather than GIN, so the indexes also serve `ORDER BY title <-> 'query'` (see src/fuzzy.rs)
+-- books and authors are small catalog tables, written by editors rather than readers
+-- lint: allow index-not-concurrent
+CREATE INDEX books_title_trgm_idx ON books USING GIST (title gist_trgm_ops);
+-- lint: allow index-not-concurrent
+CREATE INDEX authors_name_trgm_idx ON authors USING GIST (name gist_trgm_ops);
@@ -0,0 +1,13 @@
+DROP TABLE post_revisions;
+
+ALTER TABLE posts ADD COLUMN published BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE posts SET published = status = 'published';
+
+ALTER TABLE posts
+    DROP COLUMN status,
+    DROP COLUMN publish_at,
+    DROP COLUMN published_at,
+    DROP COLUMN slug;
+
+DROP TYPE post_status;
@@ -0,0 +1,49 @@
+CREATE TYPE post_status AS ENUM ('draft', 'scheduled', 'published', 'archived');
+
+ALTER TABLE posts
+    ADD COLUMN status post_status NOT NULL DEFAULT 'draft',
+    ADD COLUMN publish_at TIMESTAMPTZ,
+    ADD COLUMN published_at TIMESTAMPTZ,
+    ADD COLUMN slug TEXT;
+
+UPDATE posts SET status = 'published', published_at = updated_at WHERE published;
+
+-- Existing posts get the id appended, so the slugs are unique without checking for collisions.
+-- Folds the same accents as `slugify` in src/repo/posts.rs, before lower() so it doesn't depend on
+-- the database's locale.
+UPDATE posts SET slug = coalesce(
+    nullif(trim(BOTH '-' FROM regexp_replace(
+        lower(replace(replace(translate(title,
+            'ÀÁÂÃÄÅàáâãäåÇçÈÉÊËèéêëÌÍÎÏìíîïÑñÒÓÔÕÖØòóôõöøÙÚÛÜùúûüÝŸýÿ',
+            'aaaaaaaaaaaacceeeeeeeeiiiiiiiinnoooooooooooouuuuuuuuyyyy'),
+            'ß', 'ss'), 'ẞ', 'ss')),
+        '[^a-z0-9]+', '-', 'g')), ''),
+    'post') || '-' || id;
+
+-- The ALTER TABLE at the top locks posts exclusively until this migration commits, and the
+-- UPDATEs rewrite every row, so the scan and index build here don't block it much longer
+-- lint: allow set-not-null, index-not-concurrent
+ALTER TABLE posts
+    DROP COLUMN published,
+    ALTER COLUMN slug SET NOT NULL,
+    ADD CONSTRAINT posts_slug_key UNIQUE (slug),
+    ADD CONSTRAINT posts_scheduled_publish_at CHECK (status <> 'scheduled' OR publish_at IS NOT NULL);
+
+-- Scheduled posts due for publishing, see `PostRepository::publish_due`. posts is still locked, see
+-- above.
+-- lint: allow index-not-concurrent
+CREATE INDEX posts_publish_at_idx ON posts (publish_at) WHERE status = 'scheduled';
+
+CREATE TABLE post_revisions (
+    post_id INTEGER NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    title VARCHAR NOT NULL,
+    body TEXT NOT NULL,
+    PRIMARY KEY (post_id, revision)
+);
+
+SELECT add_timestamps('post_revisions');
+
+-- Every post starts with its current content as the first revision
+INSERT INTO post_revisions (post_id, revision, title, body)
+SELECT id, 1, title, body FROM posts;
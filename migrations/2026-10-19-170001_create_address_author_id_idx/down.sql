@@ -0,0 +1 @@
+DROP INDEX CONCURRENTLY IF EXISTS address_author_id_idx;
@@ -0,0 +1,2 @@
+-- For the foreign key address.(author_id) -> authors (address_author_id_fkey), generated by `index_advisor`
+CREATE INDEX CONCURRENTLY address_author_id_idx ON address (author_id);
@@ -0,0 +1 @@
+DROP INDEX CONCURRENTLY IF EXISTS books_authors_author_id_idx;
@@ -0,0 +1,2 @@
+-- For the foreign key books_authors.(author_id) -> authors (books_authors_author_id_fkey), generated by `index_advisor`
+CREATE INDEX CONCURRENTLY books_authors_author_id_idx ON books_authors (author_id);
@@ -0,0 +1 @@
+DROP INDEX CONCURRENTLY IF EXISTS pages_book_id_idx;
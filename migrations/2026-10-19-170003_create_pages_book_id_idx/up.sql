@@ -0,0 +1,2 @@
+-- For the foreign key pages.(book_id) -> books (pages_book_id_fkey), generated by `index_advisor`
+CREATE INDEX CONCURRENTLY pages_book_id_idx ON pages (book_id);
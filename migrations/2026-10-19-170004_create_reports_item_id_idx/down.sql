@@ -0,0 +1 @@
+DROP INDEX CONCURRENTLY IF EXISTS reports_item_id_idx;
@@ -0,0 +1,2 @@
+-- For the foreign key reports.(item_id) -> items (reports_item_id_fkey), generated by `index_advisor`
+CREATE INDEX CONCURRENTLY reports_item_id_idx ON reports (item_id);
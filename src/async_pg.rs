@@ -0,0 +1,227 @@
+use std::future::Future;
+
+use diesel_async::pooled_connection::bb8::{self, PooledConnection};
+use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use tokio_postgres::{CancelToken, NoTls};
+
+use crate::config::{connection_failed, Backoff, ConnectError, DbConfig, SslMode};
+use crate::pool::PoolConfig;
+
+/// Like `PgPool`, for handlers that await their queries instead of running them on the blocking
+/// thread pool.
+pub type AsyncPgPool = bb8::Pool<AsyncPgConnection>;
+pub type AsyncPooledPgConnection<'a> = PooledConnection<'a, AsyncPgConnection>;
+
+/// `connect` for async code. Async connections don't support TLS, so `sslmode` must be `disable`
+/// or `prefer` (tokio-postgres has no `allow`), and only understand the `params` in `ASYNC_PARAMS`.
+pub async fn connect_async(config: &DbConfig) -> Result<AsyncPgConnection, ConnectError> {
+    let connection_string = async_connection_string(config)?;
+
+    AsyncPgConnection::establish(&connection_string)
+        .await
+        .map_err(|e| connection_failed(config, &e.to_string()))
+}
+
+/// `connect_async`, with a token that cancels the query the connection is running through the
+/// Postgres cancel protocol. Dropping a query's future doesn't stop it on the server, so cancel it
+/// when the result isn't needed anymore, e.g. the client went away.
+///
+/// ```ignore
+/// let (mut conn, token) = connect_async_cancellable(&config).await?;
+/// match tokio::time::timeout(deadline, query.load::<Report>(&mut conn)).await {
+///     Ok(reports) => reports?,
+///     Err(_) => {
+///         cancel_query(&token).await;
+///         return Err(TimedOut);
+///     }
+/// }
+/// ```
+pub async fn connect_async_cancellable(
+    config: &DbConfig,
+) -> Result<(AsyncPgConnection, CancelToken), ConnectError> {
+    let connection_string = async_connection_string(config)?;
+
+    let (client, connection) = tokio_postgres::connect(&connection_string, NoTls)
+        .await
+        .map_err(|e| connection_failed(config, &e.to_string()))?;
+    let cancel_token = client.cancel_token();
+
+    let conn = AsyncPgConnection::try_from_client_and_connection(client, connection)
+        .await
+        .map_err(|e| connection_failed(config, &e.to_string()))?;
+
+    Ok((conn, cancel_token))
+}
+
+/// Cancels the query running on the connection of `token`, if any. Errors are only logged, the
+/// query may have finished in the meantime anyway.
+pub async fn cancel_query(token: &CancelToken) {
+    if let Err(e) = token.cancel_query(NoTls).await {
+        log::warn!("Cancelling the query failed: {}", e);
+    }
+}
+
+/// A guard that cancels the query of `token` when it's dropped before `disarm` is called, like
+/// `CancelHandle::cancel_on_drop`. Get the token of a pooled connection with
+/// `AsyncPgConnection::cancel_token`.
+pub fn cancel_on_drop(token: CancelToken) -> CancelQueryOnDrop {
+    CancelQueryOnDrop { token: Some(token) }
+}
+
+/// See `cancel_on_drop`.
+pub struct CancelQueryOnDrop {
+    token: Option<CancelToken>,
+}
+
+impl CancelQueryOnDrop {
+    pub fn disarm(mut self) {
+        self.token = None;
+    }
+
+    /// Cancels now and waits for the cancel request to be sent, e.g. before handing the
+    /// connection back to its pool.
+    pub async fn cancel(mut self) {
+        if let Some(token) = self.token.take() {
+            cancel_query(&token).await;
+        }
+    }
+}
+
+impl Drop for CancelQueryOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.token.take() {
+            // Can't await in drop
+            tokio::spawn(async move { cancel_query(&token).await });
+        }
+    }
+}
+
+/// `establish_connection` for async code.
+pub async fn establish_async_connection() -> Result<AsyncPgConnection, ConnectError> {
+    connect_async(&DbConfig::load()?).await
+}
+
+/// `establish_connection_with_retry` for async code.
+pub async fn establish_async_connection_with_retry(
+    backoff: &Backoff,
+) -> Result<AsyncPgConnection, ConnectError> {
+    let config = DbConfig::load()?;
+    retry_async(backoff, || connect_async(&config)).await
+}
+
+/// `connect_pool` for async code. Connections are checked with `SELECT 1` on checkout if
+/// `pool_config.test_on_check_out` is set.
+pub async fn connect_async_pool(
+    config: &DbConfig,
+    pool_config: &PoolConfig,
+) -> Result<AsyncPgPool, ConnectError> {
+    let manager = AsyncDieselConnectionManager::new(async_connection_string(config)?);
+
+    bb8::Pool::builder()
+        .max_size(pool_config.max_size)
+        .min_idle(pool_config.min_idle)
+        .connection_timeout(pool_config.connection_timeout)
+        .idle_timeout(pool_config.idle_timeout)
+        .max_lifetime(pool_config.max_lifetime)
+        .test_on_check_out(pool_config.test_on_check_out)
+        .build(manager)
+        .await
+        .map_err(|e| connection_failed(config, &e.to_string()))
+}
+
+/// `establish_pool` for async code.
+pub async fn establish_async_pool() -> Result<AsyncPgPool, ConnectError> {
+    let config = DbConfig::load()?;
+    let pool_config = PoolConfig::from_env()?;
+
+    retry_async(&Backoff::default(), || {
+        connect_async_pool(&config, &pool_config)
+    })
+    .await
+}
+
+/// The libpq parameters tokio-postgres understands, besides the ones `DbConfig` has fields for.
+const ASYNC_PARAMS: &[&str] = &[
+    "options",
+    "hostaddr",
+    "tcp_user_timeout",
+    "keepalives",
+    "keepalives_idle",
+    "keepalives_interval",
+    "keepalives_retries",
+    "target_session_attrs",
+    "channel_binding",
+    "load_balance_hosts",
+];
+
+fn async_connection_string(config: &DbConfig) -> Result<String, ConnectError> {
+    // tokio-postgres fails on them too, but as a connection error that would be retried
+    if let Some(key) = config
+        .params
+        .keys()
+        .find(|key| !ASYNC_PARAMS.contains(&key.as_str()))
+    {
+        return Err(ConnectError::Config(format!(
+            "{} isn't supported by async connections",
+            key
+        )));
+    }
+
+    match config.sslmode {
+        SslMode::Disable | SslMode::Prefer => config.connection_string(),
+        sslmode => Err(ConnectError::Config(format!(
+            "sslmode {} isn't supported by async connections",
+            sslmode.as_str()
+        ))),
+    }
+}
+
+/// `config::retry` without blocking the executor while waiting.
+pub async fn retry_async<T, F, Fut>(backoff: &Backoff, mut f: F) -> Result<T, ConnectError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ConnectError>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        match f().await {
+            Err(e @ ConnectError::Connection { .. }) if attempt < backoff.attempts => {
+                let delay = backoff.delay(attempt);
+                log::warn!("Retrying in {:?}: {}", delay, e);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_what_tokio_postgres_cant_connect_with() {
+        for sslmode in [SslMode::Allow, SslMode::Require, SslMode::VerifyFull] {
+            let config = DbConfig {
+                sslmode,
+                ..DbConfig::default()
+            };
+            assert!(matches!(
+                async_connection_string(&config),
+                Err(ConnectError::Config(_))
+            ));
+        }
+
+        let config = DbConfig::from_url("postgres://db/books?sslrootcert=/etc/ca.pem").unwrap();
+        assert!(matches!(
+            async_connection_string(&config),
+            Err(ConnectError::Config(_))
+        ));
+
+        let config = DbConfig::from_url("postgres://db/books?target_session_attrs=any").unwrap();
+        assert!(async_connection_string(&config).is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::pg::{Pg, PgValue};
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use serde::Serialize;
+use serde_json::Value;
+
+diesel::table! {
+    /// Filled by the `audit.log_change()` trigger, see the `create_audit_history` migration.
+    audit.history (id) {
+        id -> Int8,
+        table_name -> Text,
+        row_id -> Text,
+        operation -> Text,
+        old_row -> Nullable<Jsonb>,
+        new_row -> Nullable<Jsonb>,
+        actor_id -> Nullable<Text>,
+        changed_at -> Timestamptz,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, FromSqlRow)]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl FromSql<Text, Pg> for Operation {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "INSERT" => Ok(Operation::Insert),
+            "UPDATE" => Ok(Operation::Update),
+            "DELETE" => Ok(Operation::Delete),
+            other => Err(format!("Unknown audit operation: {}", other).into()),
+        }
+    }
+}
+
+/// One change to a row. `old_row` is `None` for inserts and `new_row` is `None` for deletes.
+#[derive(Debug, Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = history)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub table_name: String,
+    pub row_id: String,
+    pub operation: Operation,
+    pub old_row: Option<Value>,
+    pub new_row: Option<Value>,
+    pub actor_id: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl HistoryEntry {
+    /// The row as it was after this change, or right before it was deleted.
+    pub fn row(&self) -> &Value {
+        self.new_row
+            .as_ref()
+            .or(self.old_row.as_ref())
+            .unwrap_or(&Value::Null)
+    }
+
+    /// The columns changed by this entry.
+    pub fn changes(&self) -> Vec<FieldChange> {
+        diff(
+            self.old_row.as_ref().unwrap_or(&Value::Null),
+            self.new_row.as_ref().unwrap_or(&Value::Null),
+        )
+    }
+}
+
+/// A column that differs between two versions of a row. `None` means the column is missing from
+/// that version, e.g. every column of an insert has `old: None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "-".to_string(),
+        };
+        write!(f, "{}: {} -> {}", self.field, show(&self.old), show(&self.new))
+    }
+}
+
+/// Compares two versions of a row column by column, in column name order. Use `HistoryEntry::row`
+/// to diff versions that are further apart than a single change.
+pub fn diff(old: &Value, new: &Value) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+
+    old.keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|field| old.get(*field) != new.get(*field))
+        .map(|field| FieldChange {
+            field: field.clone(),
+            old: old.get(field).cloned(),
+            new: new.get(field).cloned(),
+        })
+        .collect()
+}
+
+/// The history of a row, oldest change first.
+///
+/// ```ignore
+/// for entry in audit::history(conn, "books", book.id)? {
+///     println!("{:?} by {:?}: {:?}", entry.operation, entry.actor_id, entry.changes());
+/// }
+/// ```
+pub fn history(
+    conn: &mut PgConnection,
+    table: &str,
+    row_id: impl ToString,
+) -> QueryResult<Vec<HistoryEntry>> {
+    history::table
+        .filter(history::table_name.eq(table))
+        .filter(history::row_id.eq(row_id.to_string()))
+        .order(history::id)
+        .select(HistoryEntry::as_select())
+        .load(conn)
+}
+
+/// Runs `f` in a transaction in which changes are recorded as made by `actor_id`. The setting is
+/// local to the transaction, so it doesn't leak to the next user of the connection.
+pub fn with_actor<T, E, F>(conn: &mut PgConnection, actor_id: &str, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut PgConnection) -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    conn.transaction(|conn| {
+        diesel::sql_query("SELECT set_config('app.actor_id', $1, true)")
+            .bind::<Text, _>(actor_id)
+            .execute(conn)?;
+
+        f(conn)
+    })
+}
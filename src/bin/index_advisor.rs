@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use rust_pg::establish_connection;
+use rust_pg::index_advisor::{analyze_indexes, write_index_migrations};
+
+// Reports foreign keys without an index and indexes that are redundant or never used, and writes
+// migrations creating the missing indexes.
+//
+//   cargo run --bin index_advisor
+//   cargo run --bin index_advisor -- --write-migrations
+#[derive(Parser)]
+#[command(
+    name = "index_advisor",
+    about = "Find missing, redundant and unused indexes"
+)]
+struct Cli {
+    /// Write a migration creating the index of each foreign key without one
+    #[arg(long)]
+    write_migrations: bool,
+
+    #[arg(long, default_value = "migrations")]
+    migrations_dir: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let conn = &mut establish_connection()?;
+    let report = analyze_indexes(conn)?;
+
+    if report.is_empty() {
+        println!("No index problems found");
+        return Ok(());
+    }
+
+    if !report.unindexed_foreign_keys.is_empty() {
+        println!("Foreign keys without an index:");
+        for fk in &report.unindexed_foreign_keys {
+            println!("  {}\n    {}", fk, fk.create_index_sql());
+        }
+    }
+    if !report.redundant_indexes.is_empty() {
+        println!("Redundant indexes:");
+        for index in &report.redundant_indexes {
+            println!("  {}", index);
+        }
+    }
+    if !report.unused_indexes.is_empty() {
+        println!("Indexes not scanned since the statistics were reset (check the replicas too):");
+        for index in &report.unused_indexes {
+            println!("  {}", index);
+        }
+    }
+
+    if cli.write_migrations {
+        for dir in write_index_migrations(&cli.migrations_dir, &report.unindexed_foreign_keys)? {
+            println!("Wrote {}", dir.display());
+        }
+    }
+
+    Ok(())
+}
@@ -1,8 +1,7 @@
-use std::collections::HashMap;
-
 use diesel::{debug_query, pg::Pg, prelude::*, result::Error};
-use diesel::sql_types::{Bool, Integer};
+use diesel::sql_types::{Bool, Bytea, Integer};
 use rand::Rng;
+use serde::Deserialize;
 
 use rust_pg::{
     *,
@@ -12,14 +11,25 @@ use rust_pg::{
         items, pages,
     },
 };
+use rust_pg::audit;
+use rust_pg::fuzzy::{self, TrigramExpressionMethods};
+use rust_pg::search;
 use rust_pg::debug_query::DebugQuery;
+use rust_pg::json_agg::JsonQuery;
+use rust_pg::preload::{load_nested, LoadWith};
+use rust_pg::encrypted::{set_keyring, Deterministic, Encrypted, Keyring};
 use rust_pg::pagination::{Paginate, PaginateWithTotal};
-use rust_pg::schema::{invites, reports};
+use rust_pg::repo::{
+    AddressRepository, AuthorRepository, BookFilter, BookRepository, PageRepository,
+    PostRepository, RepoResult,
+};
+use rust_pg::schema::{invites, posts, reports};
+use rust_pg::versioned::Versioned;
 
 use self::models::*;
 
-fn main() -> Result<(), Error> {
-    let conn = &mut establish_connection();
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = &mut establish_connection()?;
 
     setup_data(conn)?;
 
@@ -45,21 +55,30 @@ fn main() -> Result<(), Error> {
     json_testing(conn)?;
     println!("-----------------");
 
-    delete_all(conn)?;
+    soft_delete_testing(conn)?;
+    println!("-----------------");
 
-    Ok(())
-}
+    audit_testing(conn)?;
+    println!("-----------------");
 
-#[derive(Debug, Hash, Eq, PartialEq)]
-struct AddressAuthors<'a> {
-    address: &'a Address,
-    authors: Vec<AuthorBooks<'a>>,
-}
+    versioning_testing(conn)?;
+    println!("-----------------");
+
+    publishing_testing(conn)?;
+    println!("-----------------");
+
+    search_testing(conn)?;
+    println!("-----------------");
+
+    autocomplete_testing(conn)?;
+    println!("-----------------");
 
-#[derive(Debug, Hash, Eq, PartialEq)]
-struct AuthorBooks<'a> {
-    author: &'a Author,
-    books: Vec<&'a Book>,
+    encryption_testing(conn)?;
+    println!("-----------------");
+
+    delete_all(conn)?;
+
+    Ok(())
 }
 
 fn pagination_testing(conn: &mut PgConnection) -> Result<(), Error> {
@@ -156,7 +175,10 @@ fn pagination_testing(conn: &mut PgConnection) -> Result<(), Error> {
                 books::id.desc(),
                 pages::id.desc(),
             ))
-            .select((books::all_columns, pages::all_columns))
+            .select((
+                <Book as Selectable<Pg>>::construct_selection(),
+                <Page as Selectable<Pg>>::construct_selection(),
+            ))
             .paginate_with_total(page)
             .per_page(3)
             .debug_query()
@@ -210,7 +232,7 @@ fn pagination_testing(conn: &mut PgConnection) -> Result<(), Error> {
                 books::id.desc(),
                 pages::id.desc(),
             ))
-            .select((books::all_columns, pages::all_columns))
+            .select((Book::as_select(), Page::as_select()))
             .limit(3)
             // .debug_query()
             .load::<(Book, Page)>(conn)?;
@@ -260,7 +282,7 @@ fn reports_testing(conn: &mut PgConnection) -> Result<(), Error> {
         let result = reports::table
             .inner_join(items::table)
             .order_by((items::num_plays.desc(), reports::id.desc()))
-            .select((reports::all_columns, items::all_columns))
+            .select(<(Report, Item)>::as_select())
             .paginate_with_total(page)
             .load_and_count_pages::<(Report, Item)>(conn)?;
 
@@ -310,58 +332,50 @@ fn play_with_joins(conn: &mut PgConnection) -> Result<(), Error> {
     Ok(())
 }
 
-fn nested_join(conn: &mut PgConnection) -> Result<(), Error> {
-    let query = authors::table
-        .inner_join(books_authors::table.inner_join(books::table))
-        .inner_join(address::table)
-        //        .filter(address::value.eq("sverige"))
-        .select((Address::as_select(), Author::as_select(), Book::as_select()));
-
-    println!("{}", debug_query::<Pg, _>(&query));
-
-    let data = query.get_results::<(Address, Author, Book)>(conn)?;
-
-    println!("XXXX: {data:?}");
-
-    let mut m = HashMap::new();
-    for (addr, auth, book) in data {
-        m.entry(addr).or_insert_with(Vec::new).push((auth, book))
-    }
+#[derive(Debug, Deserialize)]
+struct AuthorWithBooks {
+    #[serde(flatten)]
+    author: Author,
+    books: Vec<Book>,
+    addresses: Vec<Address>,
+}
 
-    let mut mm = HashMap::new();
-    for (addr, g) in m.iter() {
-        let mut mmm = HashMap::new();
-        for (auth, book) in g {
-            mmm.entry(auth).or_insert_with(Vec::new).push(book)
+fn nested_join(conn: &mut PgConnection) -> Result<(), Error> {
+    // Authors with their books and addresses, nested by Postgres and loaded in a single query
+    let query = JsonQuery::new("authors")
+        .columns(&["id", "name", "created_at", "updated_at"])
+        .filter_is_null("deleted_at")
+        .has_many_through(
+            "books",
+            JsonQuery::new("books")
+                .columns(&["id", "title", "created_at", "updated_at", "version"])
+                .filter_is_null("deleted_at"),
+            "books_authors",
+            "author_id",
+            "book_id",
+        )
+        .has_many(
+            "addresses",
+            JsonQuery::new("address")
+                .columns(&["id", "value", "author_id", "created_at", "updated_at"])
+                .filter_is_null("deleted_at"),
+            "author_id",
+        );
+
+    println!("{}", query.to_sql().0);
+
+    let authors = query.load::<AuthorWithBooks>(conn)?;
+
+    for AuthorWithBooks { author, books, addresses } in &authors {
+        println!("{}", author.name);
+        for address in addresses {
+            println!("  address: {}", address.value);
+        }
+        for book in books {
+            println!("  book: {}", book.title);
         }
-
-        mm.entry(addr).or_insert_with(Vec::new).push(mmm);
     }
 
-    println!("____ {mm:?}");
-    println!("____ {}", mm.len());
-
-    let rs = mm
-        .into_iter()
-        .map(|(adr, rest)| AddressAuthors {
-            address: adr,
-            authors: rest
-                .into_iter()
-                .map(|xx| {
-                    xx.into_iter()
-                        .map(|(author, books)| AuthorBooks { author, books })
-                        .collect::<Vec<_>>()
-                })
-                .flatten()
-                .collect::<Vec<_>>(),
-        })
-        .collect::<Vec<_>>();
-
-    println!(":::::: {:?}", rs);
-
-    //    let x = data.iter().grouping_by(|(address, author, book)| address);
-    //    println!("XXXX: {x:?}");
-
     Ok(())
 }
 
@@ -380,6 +394,155 @@ fn json_testing(conn: &mut PgConnection) -> Result<(), Error> {
 
     println!("JSON: {:?}", result);
 
+    println!("---------------");
+
+    // Round-trip through bytea using the MessagePack + zstd encoding from `diesel_binary!`
+    let invite = InviteData::Link { url: "http://test.com".to_string() };
+    let result = diesel::select(invite.into_sql::<Bytea>())
+        .get_result::<InviteData>(conn)?;
+
+    println!("BINARY: {:?}", result);
+
+    Ok(())
+}
+
+fn soft_delete_testing(conn: &mut PgConnection) -> RepoResult<()> {
+    let mut books = BookRepository::new(conn);
+    let filter = BookFilter {
+        title: Some("Momo"),
+        ..Default::default()
+    };
+    let momo = books.list(&filter, 1)?.remove(0);
+
+    books.delete(momo.id)?;
+    println!("Momo after delete: {:?}", books.get(momo.id));
+
+    let momo = books.restore(momo.id)?;
+    println!("Momo after restore: {:?}", momo);
+
+    let pages = PageRepository::new(conn).list_for_book(momo.id)?;
+    println!("Momo pages after restore: {}", pages.len());
+
+    Ok(())
+}
+
+fn audit_testing(conn: &mut PgConnection) -> RepoResult<()> {
+    let book = audit::with_actor(conn, "kjell", |conn| {
+        let mut books = BookRepository::new(conn);
+        let book = books.create(&NewBook { title: "Audited" })?;
+        books.update(
+            book.id,
+            &BookChanges {
+                title: Some("Audited, 2nd edition"),
+            },
+        )
+    })?;
+
+    for entry in audit::history(conn, "books", book.id)? {
+        println!("{:?} by {:?} at {}", entry.operation, entry.actor_id, entry.changed_at);
+        for change in entry.changes() {
+            println!("  {}", change);
+        }
+    }
+
+    Ok(())
+}
+
+fn versioning_testing(conn: &mut PgConnection) -> RepoResult<()> {
+    let post = PostRepository::new(conn).create("Versioned", "First draft")?;
+
+    let changes = PostChanges {
+        body: Some("Second draft"),
+        ..Default::default()
+    };
+    let version = posts::table::update_with_version(conn, post.id, post.version, &changes)?;
+    println!("Post updated to version {}", version);
+
+    // Another editor still holding the first version
+    let result = posts::table::update_with_version(conn, post.id, post.version, &changes);
+    println!("Stale update: {:?}", result);
+
+    diesel::delete(posts::table.find(post.id)).execute(conn)?;
+
+    Ok(())
+}
+
+fn publishing_testing(conn: &mut PgConnection) -> RepoResult<()> {
+    let mut posts = PostRepository::new(conn);
+
+    let post = posts.create("Pippi Långstrump!", "Draft")?;
+    let again = posts.create("Pippi Långstrump!", "Another one")?;
+    println!("Slugs: {} {}", post.slug, again.slug);
+
+    let post = posts.edit(
+        post.id,
+        post.version,
+        &PostChanges {
+            body: Some("Final"),
+            ..Default::default()
+        },
+    )?;
+    println!("Revisions: {}", posts.revisions(post.id)?.len());
+
+    let post = posts.schedule(post.id, chrono::Utc::now())?;
+    println!("{:?} at {:?}", post.status, post.publish_at);
+
+    let published = posts.publish_due()?;
+    println!("Published when due: {:?}", published.iter().map(|p| &p.slug).collect::<Vec<_>>());
+
+    println!("Publish again: {:?}", posts.publish(post.id).map(|p| p.status));
+
+    posts.delete(post.id)?;
+    posts.delete(again.id)?;
+
+    Ok(())
+}
+
+fn search_testing(conn: &mut PgConnection) -> Result<(), Error> {
+    for node in search::search_books(conn, "prachtvollen theatern", search::DEFAULT_CONFIG)? {
+        println!("{}:", node.item.title);
+        for excerpt in node.children {
+            println!("  page {} ({}): {}", excerpt.page_number, excerpt.rank, excerpt.headline);
+        }
+    }
+
+    Ok(())
+}
+
+fn autocomplete_testing(conn: &mut PgConnection) -> Result<(), Error> {
+    for query in ["pipi langs", "lindgrn", "mom"] {
+        let suggestions = fuzzy::autocomplete(conn, query, 5)?;
+        println!("{:?}: {:?}", query, suggestions);
+    }
+
+    // The closest titles, however far off
+    let titles = books::table
+        .order(books::title.trigram_distance("Pipi and Mommo"))
+        .limit(2)
+        .select(books::title)
+        .load::<String>(conn)?;
+    println!("Closest titles: {:?}", titles);
+
+    Ok(())
+}
+
+fn encryption_testing(conn: &mut PgConnection) -> Result<(), Error> {
+    // Use a throwaway key instead of reading KEYRING_FILE
+    let _ = set_keyring(Keyring::generate());
+
+    let email = Encrypted::<String>::new("kjell@test.com".to_string());
+    let result = diesel::select(email.into_sql::<Bytea>())
+        .get_result::<Encrypted<String>>(conn)?;
+
+    println!("ENCRYPTED: {:?} -> {}", result, *result);
+
+    // Deterministic values encrypt to the same bytes, so they can be compared in SQL
+    let a = Encrypted::<String, Deterministic>::new("kjell@test.com".to_string());
+    let b = Encrypted::<String, Deterministic>::new("kjell@test.com".to_string());
+    let equal = diesel::select(a.into_sql::<Bytea>().eq(b)).get_result::<bool>(conn)?;
+
+    println!("DETERMINISTIC EQUAL: {}", equal);
+
     Ok(())
 }
 
@@ -419,6 +582,7 @@ fn joins(conn: &mut PgConnection) -> Result<(), Error> {
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 struct BookWithPages {
     book: Book,
     pages: Vec<Page>,
@@ -442,33 +606,16 @@ fn one_to_n_relations(conn: &mut PgConnection) -> Result<(), Error> {
 
     let all_books = books::table.select(Book::as_select()).load(conn)?;
 
-    // get all pages for all books
-    let pages = Page::belonging_to(&all_books)
-        .select(Page::as_select())
-        .load(conn)?;
-
-    // group the pages per book
-    let pages_per_book = pages
-        .grouped_by(&all_books)
-        .into_iter()
-        .zip(all_books)
-        .map(|(pages, book)| (book, pages))
-        .collect::<Vec<(Book, Vec<Page>)>>();
+    // get all pages for all books, grouped per book
+    let pages_per_book = Book::load_with::<Page>(conn, all_books)?;
 
     println!("Pages per book: \n {pages_per_book:?}\n");
 
     let all_books = books::table.select(Book::as_select()).load(conn)?;
 
-    // get all pages for all books
-    let pages = Page::belonging_to(&all_books)
-        .select(Page::as_select())
-        .load(conn)?;
-
-    let pages_per_book = pages
-        .grouped_by(&all_books)
+    let pages_per_book = Book::load_with::<Page>(conn, all_books)?
         .into_iter()
-        .zip(all_books)
-        .map(|(pages, book)| BookWithPages { book, pages })
+        .map(|(book, pages)| BookWithPages { book, pages })
         .collect::<Vec<BookWithPages>>();
 
     println!("Pages per book: \n {pages_per_book:?}\n");
@@ -508,123 +655,108 @@ fn m_to_n_relations(conn: &mut PgConnection) -> Result<(), Error> {
     // get a list of authors with all their books
     let all_authors = authors::table.select(Author::as_select()).load(conn)?;
 
-    let books = BookAuthor::belonging_to(&authors)
-        .inner_join(books::table)
-        .select((BookAuthor::as_select(), Book::as_select()))
-        .load(conn)?;
-
-    let books_per_author: Vec<(Author, Vec<Book>)> = books
-        .grouped_by(&all_authors)
-        .into_iter()
-        .zip(authors)
-        .map(|(b, author)| (author, b.into_iter().map(|(_, book)| book).collect()))
-        .collect();
+    let books_per_author = Author::load_with_through::<BookAuthor, Book>(conn, all_authors)?;
 
     println!("All authors including their books: {books_per_author:?}");
 
-    Ok(())
-}
-
-fn new_author(conn: &mut PgConnection, name: &str) -> Result<Author, Error> {
-    let author = diesel::insert_into(authors::table)
-        .values(authors::name.eq(name))
-        .returning(Author::as_returning())
-        .get_result(conn)?;
-    Ok(author)
-}
-
-fn new_book(conn: &mut PgConnection, title: &str) -> Result<Book, Error> {
-    let book = diesel::insert_into(books::table)
-        .values(books::title.eq(title))
-        .returning(Book::as_returning())
-        .get_result(conn)?;
-    Ok(book)
-}
-
-fn new_books_author(
-    conn: &mut PgConnection,
-    book_id: i32,
-    author_id: i32,
-) -> Result<BookAuthor, Error> {
-    let book_author = diesel::insert_into(books_authors::table)
-        .values((
-            books_authors::book_id.eq(book_id),
-            books_authors::author_id.eq(author_id),
-        ))
-        .returning(BookAuthor::as_returning())
-        .get_result(conn)?;
-    Ok(book_author)
-}
+    // ... and the pages of those books, one more query for all of them
+    let pages_per_author_book = load_nested::<_, _, Page>(conn, books_per_author)?;
 
-fn new_page(
-    conn: &mut PgConnection,
-    page_number: i32,
-    content: &str,
-    book_id: i32,
-) -> Result<Page, Error> {
-    let page = diesel::insert_into(pages::table)
-        .values((
-            pages::page_number.eq(page_number),
-            pages::content.eq(content),
-            pages::book_id.eq(book_id),
-        ))
-        .returning(Page::as_returning())
-        .get_result(conn)?;
-    Ok(page)
-}
+    println!("All authors including their books and pages: {pages_per_author_book:?}");
 
-fn new_address(conn: &mut PgConnection, address: &str, author_id: i32) -> Result<Address, Error> {
-    let page = diesel::insert_into(address::table)
-        .values((address::value.eq(address), address::author_id.eq(author_id)))
-        .returning(Address::as_returning())
-        .get_result(conn)?;
-    Ok(page)
+    Ok(())
 }
 
-fn setup_data(conn: &mut PgConnection) -> Result<(), Error> {
+fn setup_data(conn: &mut PgConnection) -> RepoResult<()> {
     // create a book
-    let momo = new_book(conn, "Momo")?;
+    let momo = BookRepository::new(conn).create(&NewBook { title: "Momo" })?;
 
     // a page in that book
-    new_page(conn, 1, "In alten, alten Zeiten ...", momo.id)?;
+    let mut pages = PageRepository::new(conn);
+    pages.create(&NewPage {
+        page_number: 1,
+        content: "In alten, alten Zeiten ...",
+        book_id: momo.id,
+    })?;
     // a second page
-    new_page(conn, 2, "den prachtvollen Theatern...", momo.id)?;
+    pages.create(&NewPage {
+        page_number: 2,
+        content: "den prachtvollen Theatern...",
+        book_id: momo.id,
+    })?;
 
     // create an author
-    let michael_ende = new_author(conn, "Michael Ende")?;
+    let mut authors = AuthorRepository::new(conn);
+    let michael_ende = authors.create(&NewAuthor {
+        name: "Michael Ende",
+    })?;
 
     // let's add the author to the already created book
-    new_books_author(conn, momo.id, michael_ende.id)?;
+    authors.add_book(michael_ende.id, momo.id)?;
 
-    new_address(conn, "Derp road", michael_ende.id)?;
+    AddressRepository::new(conn).create(&NewAddress {
+        value: "Derp road",
+        author_id: michael_ende.id,
+    })?;
 
     // create a second author
-    let astrid_lindgren = new_author(conn, "Astrid Lindgren")?;
-    new_address(conn, "sverige", astrid_lindgren.id)?;
-
-    let pippi = new_book(conn, "Pippi Långstrump")?;
-    new_books_author(conn, pippi.id, astrid_lindgren.id)?;
-
-    new_page(conn, 1, "pipp1", pippi.id)?;
-    new_page(conn, 2, "pipp1", pippi.id)?;
+    let astrid_lindgren = AuthorRepository::new(conn).create(&NewAuthor {
+        name: "Astrid Lindgren",
+    })?;
+    AddressRepository::new(conn).create(&NewAddress {
+        value: "sverige",
+        author_id: astrid_lindgren.id,
+    })?;
+
+    let pippi = BookRepository::new(conn).create(&NewBook {
+        title: "Pippi Långstrump",
+    })?;
+    AuthorRepository::new(conn).add_book(astrid_lindgren.id, pippi.id)?;
+
+    let mut pages = PageRepository::new(conn);
+    pages.create(&NewPage {
+        page_number: 1,
+        content: "pipp1",
+        book_id: pippi.id,
+    })?;
+    pages.create(&NewPage {
+        page_number: 2,
+        content: "pipp1",
+        book_id: pippi.id,
+    })?;
 
     // now that both have a single book, let's add a third book, an imaginary collaboration
-    let collaboration = new_book(conn, "Pippi and Momo")?;
-    new_books_author(conn, collaboration.id, astrid_lindgren.id)?;
-    new_books_author(conn, collaboration.id, michael_ende.id)?;
-
-    new_page(conn, 1, "momopipp1", collaboration.id)?;
-    new_page(conn, 2, "momopipp2", collaboration.id)?;
-
+    let collaboration = BookRepository::new(conn).create(&NewBook {
+        title: "Pippi and Momo",
+    })?;
+    let mut authors = AuthorRepository::new(conn);
+    authors.add_book(astrid_lindgren.id, collaboration.id)?;
+    authors.add_book(michael_ende.id, collaboration.id)?;
+
+    let mut pages = PageRepository::new(conn);
+    pages.create(&NewPage {
+        page_number: 1,
+        content: "momopipp1",
+        book_id: collaboration.id,
+    })?;
+    pages.create(&NewPage {
+        page_number: 2,
+        content: "momopipp2",
+        book_id: collaboration.id,
+    })?;
+
+    let mut books = BookRepository::new(conn);
     for i in 1..20 {
-        new_book(conn, &format!("Book {}", i))?;
+        books.create(&NewBook {
+            title: &format!("Book {}", i),
+        })?;
     }
 
-    new_invite(conn, "email", serde_json::from_str("{\"kind\": \"Email\", \"name\": \"kjell\"}").unwrap())?;
-    new_invite(conn, "link", serde_json::from_str("{\"kind\": \"Link\", \"url\": \"http://test.com\"}").unwrap())?;
+    new_invite(conn, InviteKind::Email, serde_json::from_str("{\"kind\": \"Email\", \"name\": \"kjell\"}").unwrap())?;
+    new_invite(conn, InviteKind::Link, serde_json::from_str("{\"kind\": \"Link\", \"url\": \"http://test.com\"}").unwrap())?;
 
     let invite = NewInviteJson {
-        kind: "email".to_string(),
+        kind: InviteKind::Email,
         json: InviteData::Email { name: "ronnie".to_string() },
     };
 
@@ -653,7 +785,7 @@ fn new_report(conn: &mut PgConnection, title: &str, item_id: i32) -> Result<Repo
 }
 
 
-fn new_invite(conn: &mut PgConnection, kind: &str, json: serde_json::Value) -> Result<Invite, Error> {
+fn new_invite(conn: &mut PgConnection, kind: InviteKind, json: serde_json::Value) -> Result<Invite, Error> {
     let item = diesel::insert_into(invites::table)
         .values((invites::kind.eq(kind), invites::json.eq(json)))
         .returning(Invite::as_returning())
@@ -672,10 +804,10 @@ fn new_invite_serde(conn: &mut PgConnection, new_invite: NewInviteJson) -> Resul
 }
 
 fn setup_items(conn: &mut PgConnection) -> Result<(), Error> {
-    let mut rng = rand::thread_rng();
+    let mut rng = rand::rng();
 
     for i in 1..100 {
-        let num_plays = rng.gen_range(0..1000);
+        let num_plays = rng.random_range(0..1000);
 
         let item = new_item(conn, &format!("item {}", i), num_plays)?;
 
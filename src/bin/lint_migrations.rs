@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+use std::process;
+
+use clap::Parser;
+
+use rust_pg::migration_lint::lint_migrations;
+
+// Checks migrations for operations that lock or rewrite tables, before deploying them.
+//
+//   cargo run --bin lint_migrations
+//   cargo run --bin lint_migrations -- --since 2026-10-19-170000
+#[derive(Parser)]
+#[command(
+    name = "lint_migrations",
+    about = "Check migrations for zero-downtime deploys"
+)]
+struct Cli {
+    /// Only report migrations from this version on, e.g. the ones not deployed yet. Earlier
+    /// migrations are still read for the tables and indexes they create
+    #[arg(long)]
+    since: Option<String>,
+
+    #[arg(default_value = "migrations")]
+    migrations_dir: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    let findings = lint_migrations(&cli.migrations_dir)?
+        .into_iter()
+        .filter(|finding| {
+            cli.since
+                .as_ref()
+                .is_none_or(|since| finding.migration.as_str() >= since.as_str())
+        })
+        .collect::<Vec<_>>();
+
+    for finding in &findings {
+        println!("{}", finding);
+    }
+
+    if !findings.is_empty() {
+        eprintln!(
+            "\n{} finding(s). Allow one with a `-- lint: allow <rule>` comment before the statement",
+            findings.len()
+        );
+        process::exit(1);
+    }
+
+    Ok(())
+}
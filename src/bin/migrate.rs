@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::process;
+
+use clap::{Parser, Subcommand};
+use env_logger::Env;
+
+use rust_pg::config::Backoff;
+use rust_pg::{establish_connection, establish_connection_with_retry};
+use rust_pg::migrations::{
+    migration_status, redo_last_migration, revert_last_migration, run_pending_migrations,
+};
+
+// Runs the migrations embedded in the binary, for deploys without the Diesel CLI.
+//
+//   cargo run --bin migrate -- up
+//   cargo run --bin migrate -- status
+//   cargo run --bin migrate -- --wait up     (while the database is still starting)
+#[derive(Parser)]
+#[command(name = "migrate", about = "Run database migrations")]
+struct Cli {
+    /// Retry for about 30 seconds while the database can't be reached
+    #[arg(long)]
+    wait: bool,
+
+    #[command(subcommand)]
+    command: MigrateCommand,
+}
+
+#[derive(Subcommand)]
+enum MigrateCommand {
+    /// Run all pending migrations
+    Up,
+    /// Revert the last applied migration
+    Down,
+    /// Revert the last applied migration and run it again
+    Redo,
+    /// List migrations and whether they are applied
+    Status,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    // Shows the retries of --wait, and waiting for another instance that is migrating
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
+
+    if let Err(e) = run(cli) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let connection = &mut match cli.wait {
+        true => establish_connection_with_retry(&Backoff::default())?,
+        false => establish_connection()?,
+    };
+
+    match cli.command {
+        MigrateCommand::Up => {
+            let applied = run_pending_migrations(connection)?;
+            if applied.is_empty() {
+                println!("No pending migrations");
+            }
+            for version in applied {
+                println!("Applied {}", version);
+            }
+        }
+        MigrateCommand::Down => {
+            println!("Reverted {}", revert_last_migration(connection)?);
+        }
+        MigrateCommand::Redo => {
+            println!("Redid {}", redo_last_migration(connection)?);
+        }
+        MigrateCommand::Status => {
+            for status in migration_status(connection)? {
+                println!(
+                    "[{}] {}",
+                    if status.applied { "x" } else { " " },
+                    status
+                        .name
+                        .unwrap_or_else(|| format!("{} (not in migrations/)", status.version))
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
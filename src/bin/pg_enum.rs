@@ -0,0 +1,50 @@
+use std::env;
+use std::process::exit;
+
+use rust_pg::diesel_pg_enum::{write_add_values_migration, write_create_type_migration, PgEnum};
+use rust_pg::models::{InviteKind, PostStatus};
+
+const MIGRATIONS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+
+// Generates migrations for the Postgres enums mapped with `diesel_pg_enum!`.
+//
+//   cargo run --bin pg_enum -- create invite_kind
+//   cargo run --bin pg_enum -- add invite_kind sms
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let result = match args.as_slice() {
+        ["create", type_name] => match *type_name {
+            InviteKind::TYPE_NAME => write_create_type_migration::<InviteKind>(MIGRATIONS_DIR),
+            PostStatus::TYPE_NAME => write_create_type_migration::<PostStatus>(MIGRATIONS_DIR),
+            _ => unknown_type(type_name),
+        },
+        ["add", type_name, values @ ..] if !values.is_empty() => match *type_name {
+            InviteKind::TYPE_NAME => {
+                write_add_values_migration::<InviteKind>(MIGRATIONS_DIR, values)
+            }
+            PostStatus::TYPE_NAME => {
+                write_add_values_migration::<PostStatus>(MIGRATIONS_DIR, values)
+            }
+            _ => unknown_type(type_name),
+        },
+        _ => {
+            eprintln!("usage: pg_enum create <type> | pg_enum add <type> <value>...");
+            exit(2);
+        }
+    };
+
+    match result {
+        Ok(dir) => println!("Created migration {}", dir.display()),
+        Err(e) => {
+            eprintln!("Could not create migration: {e}");
+            exit(1);
+        }
+    }
+}
+
+fn unknown_type(type_name: &str) -> ! {
+    eprintln!("Unknown enum type: {type_name}");
+    exit(2);
+}
@@ -0,0 +1,462 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::{env, fs};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use tempfile::Builder;
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use rust_pg::diesel_pg_enum::PgEnum;
+use rust_pg::models::{Post, PostChanges, PostRevision, PostStatus};
+use rust_pg::repo::{PostFilter, PostRepository, RepoResult};
+use rust_pg::search::{self, PostMatch};
+use rust_pg::*;
+
+// Manages posts without psql.
+//
+//   cargo run --bin posts -- list --status published
+//   cargo run --bin posts -- create "Hello" --file hello.md
+//   cargo run --bin posts -- edit --slug hello   (opens $EDITOR)
+//   cargo run --bin posts -- publish --slug hello --at 2026-11-01T09:00:00Z
+//   cargo run --bin posts -- --format json search "hello world"
+#[derive(Parser)]
+#[command(name = "posts", about = "Manage posts")]
+struct Cli {
+    #[arg(long, short, value_enum, default_value_t = Format::Table, global = true)]
+    format: Format,
+
+    #[command(subcommand)]
+    command: PostCommand,
+}
+
+/// Exactly one of --id and --slug, so a numeric slug can't be taken for an id.
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct PostRef {
+    #[arg(long)]
+    id: Option<i32>,
+    #[arg(long)]
+    slug: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Table,
+    Json,
+    Markdown,
+}
+
+/// Posts are given with --id or --slug.
+#[derive(Subcommand)]
+enum PostCommand {
+    /// List posts, most recently updated first
+    List {
+        #[arg(long, value_parser = parse_status)]
+        status: Option<PostStatus>,
+        /// Only posts with this in the title
+        #[arg(long)]
+        title: Option<String>,
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(i64).range(1..))]
+        page: i64,
+        /// At most 10
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(i64).range(1..=10))]
+        per_page: i64,
+    },
+    /// Show a post
+    Show {
+        #[command(flatten)]
+        post: PostRef,
+    },
+    /// Create a draft, with the body read from --file ("-" for stdin) or written in $EDITOR
+    Create {
+        title: String,
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Change the title and/or body. Opens the body in $EDITOR unless --title or --file is given
+    Edit {
+        #[command(flatten)]
+        post: PostRef,
+        #[arg(long)]
+        title: Option<String>,
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Publish now, or schedule publishing with --at
+    Publish {
+        #[command(flatten)]
+        post: PostRef,
+        #[arg(long)]
+        at: Option<DateTime<Utc>>,
+    },
+    /// Take a published or scheduled post back to draft
+    Unpublish {
+        #[command(flatten)]
+        post: PostRef,
+    },
+    Archive {
+        #[command(flatten)]
+        post: PostRef,
+    },
+    /// Delete a post and its revisions for good
+    Delete {
+        #[command(flatten)]
+        post: PostRef,
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Full-text search over titles and bodies
+    Search {
+        query: String,
+        /// Text search configuration of the posts to search
+        #[arg(long, default_value = search::DEFAULT_CONFIG)]
+        lang: String,
+    },
+    /// Show the revision history of a post
+    Revisions {
+        #[command(flatten)]
+        post: PostRef,
+    },
+    /// Publish scheduled posts that are due, for running from cron
+    PublishDue,
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Err(e) = run(cli) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let format = cli.format;
+    let connection = &mut establish_connection()?;
+    let mut posts = PostRepository::new(connection);
+
+    match cli.command {
+        PostCommand::List {
+            status,
+            title,
+            page,
+            per_page,
+        } => {
+            let filter = PostFilter {
+                status,
+                title: title.as_deref(),
+                recent_first: true,
+            };
+            let result = posts.list_with_total(&filter, page, per_page)?;
+            print_posts(format, &result.data, page, result.total_pages)?;
+        }
+        PostCommand::Show { post } => print_post(format, &find(&mut posts, &post)?)?,
+        PostCommand::Create { title, file } => {
+            let body = match file {
+                Some(file) => read_body(&file)?,
+                None => edit_in_editor("")?,
+            };
+            if body.trim().is_empty() {
+                return Err("Empty body, not creating the post".into());
+            }
+            print_post(format, &posts.create(&title, &body)?)?;
+        }
+        PostCommand::Edit { post, title, file } => {
+            let post = find(&mut posts, &post)?;
+            let body = match (&title, file) {
+                (_, Some(file)) => Some(read_body(&file)?),
+                (None, None) => Some(edit_in_editor(&post.body)?),
+                (Some(_), None) => None,
+            };
+
+            let changes = PostChanges {
+                title: title.as_deref(),
+                body: body.as_deref().filter(|body| *body != post.body),
+            };
+            // Fails if someone else changed the post while it was open in the editor
+            print_post(format, &posts.edit(post.id, post.version, &changes)?)?;
+        }
+        PostCommand::Publish { post, at } => {
+            let id = find(&mut posts, &post)?.id;
+            let post = match at {
+                Some(at) => posts.schedule(id, at)?,
+                None => posts.publish(id)?,
+            };
+            print_post(format, &post)?;
+        }
+        PostCommand::Unpublish { post } => {
+            let id = find(&mut posts, &post)?.id;
+            print_post(format, &posts.unpublish(id)?)?;
+        }
+        PostCommand::Archive { post } => {
+            let id = find(&mut posts, &post)?.id;
+            print_post(format, &posts.archive(id)?)?;
+        }
+        PostCommand::Delete { post, yes } => {
+            let post = find(&mut posts, &post)?;
+            if !yes && !confirm(&format!("Delete \"{}\" ({})?", post.title, post.slug))? {
+                return Ok(());
+            }
+            posts.delete(post.id)?;
+            println!("Deleted {}", post.slug);
+        }
+        PostCommand::Search { query, lang } => {
+            let matches = posts.search(&query, &lang)?;
+            print_matches(format, &matches)?;
+        }
+        PostCommand::Revisions { post } => {
+            let id = find(&mut posts, &post)?.id;
+            print_revisions(format, &posts.revisions(id)?)?;
+        }
+        PostCommand::PublishDue => {
+            let published = posts.publish_due()?;
+            print_posts(format, &published, 1, 1)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_status(value: &str) -> Result<PostStatus, String> {
+    PostStatus::from_pg_str(value)
+        .ok_or_else(|| format!("expected one of {}", PostStatus::VARIANTS.join(", ")))
+}
+
+fn find(posts: &mut PostRepository, post: &PostRef) -> RepoResult<Post> {
+    match (post.id, &post.slug) {
+        (Some(id), _) => posts.get(id),
+        (None, Some(slug)) => posts.get_by_slug(slug),
+        (None, None) => unreachable!("clap requires --id or --slug"),
+    }
+}
+
+fn read_body(file: &PathBuf) -> io::Result<String> {
+    if file.as_os_str() == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(file)
+    }
+}
+
+/// Opens `initial` in `$VISUAL` or `$EDITOR` (`vi` if neither is set) and returns the saved text.
+fn edit_in_editor(initial: &str) -> io::Result<String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // Created with a random name and only readable by us, and removed when dropped
+    let mut file = Builder::new().prefix("post-").suffix(".md").tempfile()?;
+    file.write_all(initial.as_bytes())?;
+    file.flush()?;
+    let path = file.path();
+
+    // Through the shell, so editors with arguments like `code --wait` work
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status();
+
+    // Read by path, since editors often replace the file instead of writing to it
+    let body = fs::read_to_string(path);
+
+    match status? {
+        status if status.success() => body,
+        status => Err(io::Error::other(format!(
+            "{} exited with {}",
+            editor, status
+        ))),
+    }
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn print_posts(format: Format, posts: &[Post], page: i64, total_pages: i64) -> io::Result<()> {
+    match format {
+        Format::Table => {
+            let rows = posts
+                .iter()
+                .map(|post| {
+                    vec![
+                        post.id.to_string(),
+                        post.status.as_pg_str().to_string(),
+                        post.slug.clone(),
+                        post.title.clone(),
+                        post.timestamps.updated_at.format("%Y-%m-%d %H:%M").to_string(),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            print_table(&["ID", "STATUS", "SLUG", "TITLE", "UPDATED"], &rows);
+            println!("\nPage {}/{}", page, total_pages.max(1));
+        }
+        Format::Json => print_json(&json!({
+            "page": page,
+            "total_pages": total_pages,
+            "posts": posts,
+        }))?,
+        Format::Markdown => {
+            println!("| ID | Status | Slug | Title | Updated |");
+            println!("|---|---|---|---|---|");
+            for post in posts {
+                println!(
+                    "| {} | {} | {} | {} | {} |",
+                    post.id,
+                    post.status.as_pg_str(),
+                    post.slug,
+                    escape_cell(&post.title),
+                    post.timestamps.updated_at.format("%Y-%m-%d %H:%M")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_post(format: Format, post: &Post) -> io::Result<()> {
+    match format {
+        Format::Table => {
+            println!("id:      {}", post.id);
+            println!("slug:    {}", post.slug);
+            println!("title:   {}", post.title);
+            println!("status:  {}", status_line(post));
+            println!("version: {}", post.version);
+            println!("updated: {}", post.timestamps.updated_at);
+            println!("\n{}", post.body);
+        }
+        Format::Json => print_json(post)?,
+        Format::Markdown => {
+            println!("# {}\n", post.title);
+            println!("_{} · {}_\n", status_line(post), post.slug);
+            println!("{}", post.body);
+        }
+    }
+
+    Ok(())
+}
+
+fn status_line(post: &Post) -> String {
+    match (post.status, post.publish_at, post.published_at) {
+        (PostStatus::Scheduled, Some(at), _) => format!("scheduled for {}", at),
+        (PostStatus::Published, _, Some(at)) => format!("published {}", at),
+        (status, _, _) => status.as_pg_str().to_string(),
+    }
+}
+
+fn print_matches(format: Format, matches: &[PostMatch]) -> io::Result<()> {
+    match format {
+        Format::Table => {
+            let rows = matches
+                .iter()
+                .map(|m| {
+                    vec![
+                        m.post.id.to_string(),
+                        format!("{:.3}", m.rank),
+                        m.post.title.clone(),
+                        m.headline.replace("<b>", "*").replace("</b>", "*"),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            print_table(&["ID", "RANK", "TITLE", "EXCERPT"], &rows);
+        }
+        Format::Json => print_json(&matches)?,
+        Format::Markdown => {
+            for m in matches {
+                println!("- **{}** ({})", m.post.title, m.post.slug);
+                println!(
+                    "  > {}",
+                    m.headline.replace("<b>", "**").replace("</b>", "**")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_revisions(format: Format, revisions: &[PostRevision]) -> io::Result<()> {
+    match format {
+        Format::Table => {
+            let rows = revisions
+                .iter()
+                .map(|r| {
+                    vec![
+                        r.revision.to_string(),
+                        r.timestamps.created_at.format("%Y-%m-%d %H:%M").to_string(),
+                        r.title.clone(),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            print_table(&["REVISION", "CREATED", "TITLE"], &rows);
+        }
+        Format::Json => print_json(&revisions)?,
+        Format::Markdown => {
+            for r in revisions {
+                println!("## Revision {} ({})\n", r.revision, r.timestamps.created_at);
+                println!("# {}\n\n{}\n", r.title, r.body);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    // One line per row
+    let rows = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| cell.replace('\n', " "))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut widths = headers
+        .iter()
+        .map(|h| h.chars().count())
+        .collect::<Vec<_>>();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", line(headers.to_vec()));
+    for row in &rows {
+        println!("{}", line(row.iter().map(String::as_str).collect()));
+    }
+}
+
+fn print_json<T: serde::Serialize + ?Sized>(value: &T) -> io::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn escape_cell(value: &str) -> String {
+    value.replace('|', "\\|")
+}
@@ -0,0 +1,29 @@
+use std::env;
+use std::time::Duration;
+
+use rust_pg::establish_connection;
+use rust_pg::soft_delete::purge;
+
+const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+// Hard-deletes rows that have been soft deleted for longer than the retention window.
+//
+//   cargo run --bin purge -- [retention in days]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let days = match env::args().nth(1) {
+        Some(days) => days.parse()?,
+        None => DEFAULT_RETENTION_DAYS,
+    };
+
+    let retention = days
+        .checked_mul(24 * 60 * 60)
+        .map(Duration::from_secs)
+        .ok_or("The retention is too large")?;
+
+    let conn = &mut establish_connection()?;
+    let stats = purge(conn, retention)?;
+
+    println!("Purged rows deleted more than {days} days ago: {stats:?}");
+
+    Ok(())
+}
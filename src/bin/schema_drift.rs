@@ -0,0 +1,24 @@
+use std::process;
+
+use rust_pg::establish_connection;
+use rust_pg::schema_drift::check_schema;
+
+// Compares src/schema.rs with the database, e.g. in CI after running the migrations. Exits with 1
+// if they differ.
+//
+//   cargo run --bin schema_drift
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = &mut establish_connection()?;
+    let drift = check_schema(conn)?;
+
+    if drift.is_empty() {
+        println!("schema.rs matches the database");
+        return Ok(());
+    }
+
+    println!("schema.rs (-) differs from the database (+):");
+    for difference in drift {
+        println!("{}", difference);
+    }
+    process::exit(1);
+}
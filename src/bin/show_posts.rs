@@ -1,24 +0,0 @@
-use diesel::prelude::*;
-
-use rust_pg::*;
-
-use self::models::*;
-
-fn main() {
-    use self::schema::posts::dsl::*;
-
-    let connection = &mut establish_connection();
-    let results = posts
-        .filter(published.eq(true))
-        .limit(5)
-        .select(Post::as_select())
-        .load(connection)
-        .expect("Error loading posts");
-
-    println!("Displaying {} posts", results.len());
-    for post in results {
-        println!("{}", post.title);
-        println!("-----------\n");
-        println!("{}", post.body);
-    }
-}
@@ -1,12 +1,58 @@
-use actix_web::dev::Service;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::{pending, ready, Ready};
+use std::io;
+use std::net::TcpStream;
+use std::os::fd::AsFd;
+use std::pin::pin;
+use std::sync::Arc;
+
+use actix_web::dev::{Extensions, Payload, Service};
+use actix_web::http::header::{EntityTag, ETag, AUTHORIZATION, IF_MATCH};
 use actix_web::middleware::Logger;
 use actix_web::web::{Json, Path, Query};
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{
+    get, patch, post, web, App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use chrono::{DateTime, Utc};
+use diesel::r2d2::PoolError;
+use diesel::PgConnection;
 use env_logger::Env;
+use futures_util::future::{select, Either};
 use futures_util::FutureExt;
+use rust_pg::async_pg::{cancel_on_drop, establish_async_pool, AsyncPgPool};
+use rust_pg::audit;
+use rust_pg::cancel::CancelHandle;
+use rust_pg::fuzzy;
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{BookChanges, PostChanges, PostStatus};
+use rust_pg::pool::PgPool;
+use rust_pg::{establish_router, DbRouter};
+use rust_pg::repo::{
+    list_posts_async, BookRepository, PostFilter, PostRepository, RepoError, RepoResult,
+    ReportRepository,
+};
+use rust_pg::search;
+use rust_pg::timeouts::Timeouts;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::time::Duration;
-use tokio::time::{sleep, Instant};
+use tokio::time::{sleep, timeout, Instant};
+
+/// How long a request may take in the database before its query is cancelled.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a query may wait for a lock, e.g. on a row another request is updating.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reports join and sort every item, so they get less time than other requests.
+const REPORTS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a request waiting for the database checks whether its client is still there.
+const DISCONNECT_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often `publish_worker` checks for scheduled posts that are due.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(30);
 
 #[get("/")]
 async fn hello(data: web::Data<AppState>) -> impl Responder {
@@ -62,28 +108,718 @@ async fn manual_hello() -> impl Responder {
     HttpResponse::Ok().body("Hey there!")
 }
 
+/// The editors, from `EDITOR_TOKENS`: comma-separated `name=token` pairs. Tokens are looked up
+/// by their SHA-256, so comparing them doesn't leak how much of a token was right.
+struct EditorTokens(HashMap<[u8; 32], String>);
+
+impl EditorTokens {
+    fn from_env() -> io::Result<Self> {
+        let value = std::env::var("EDITOR_TOKENS").unwrap_or_default();
+
+        let mut tokens = HashMap::new();
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                Some((name, token)) if !name.trim().is_empty() && !token.trim().is_empty() => {
+                    tokens.insert(token_hash(token.trim()), name.trim().to_string());
+                }
+                _ => {
+                    return Err(io::Error::other(
+                        "EDITOR_TOKENS must be comma-separated name=token pairs",
+                    ))
+                }
+            }
+        }
+        if tokens.is_empty() {
+            log::warn!("EDITOR_TOKENS isn't set, nobody can change posts or books");
+        }
+
+        Ok(EditorTokens(tokens))
+    }
+}
+
+fn token_hash(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// An editor, authenticated by `Authorization: Bearer <token>` with a token from `EDITOR_TOKENS`.
+/// Handlers that change data take one, so other requests get a 401. Handlers that only show
+/// unpublished posts to editors take an `Option<Editor>`. Changes made with `audit::with_actor`
+/// are recorded as made by the editor's name.
+struct Editor(String);
+
+impl FromRequest for Editor {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let name = req.app_data::<web::Data<EditorTokens>>().and_then(|tokens| {
+            let token = req
+                .headers()
+                .get(AUTHORIZATION)?
+                .to_str()
+                .ok()?
+                .strip_prefix("Bearer ")?;
+            tokens.0.get(&token_hash(token.trim())).cloned()
+        });
+
+        ready(match name {
+            Some(name) => Ok(Editor(name)),
+            None => Err(actix_web::error::ErrorUnauthorized("Editors only")),
+        })
+    }
+}
+
+/// Runs `f` with a connection to the primary on the blocking thread pool, so Diesel doesn't block
+/// the executor. Responds with 503 if no connection frees up within the pool's timeout.
+async fn with_conn<T, F>(db: &web::Data<DbRouter>, f: F) -> actix_web::Result<RepoResult<T>>
+where
+    F: FnOnce(&mut PgConnection) -> RepoResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    run_in_session(db, Access::Write, request_timeouts(), f).await
+}
+
+/// Like `with_conn`, in a read-only transaction on a replica if there is one that's caught up.
+async fn read_conn<T, F>(db: &web::Data<DbRouter>, f: F) -> actix_web::Result<RepoResult<T>>
+where
+    F: FnOnce(&mut PgConnection) -> RepoResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    run_in_session(db, Access::Read, request_timeouts(), f).await
+}
+
+enum Access {
+    Read,
+    Write,
+}
+
+/// The limits for the queries of a request: nothing may run longer than the request, or wait for
+/// locks or sit idle in a transaction for long.
+fn request_timeouts() -> Timeouts {
+    Timeouts {
+        statement: Some(REQUEST_TIMEOUT),
+        lock: Some(LOCK_TIMEOUT),
+        idle_in_transaction: Some(REQUEST_TIMEOUT),
+    }
+}
+
+/// Runs `f` in a session with `timeouts`, and cancels it if it's still running after
+/// `REQUEST_TIMEOUT` (responding with 504) or the client disconnects, which drops this future.
+async fn run_in_session<T, F>(
+    db: &web::Data<DbRouter>,
+    access: Access,
+    timeouts: Timeouts,
+    f: F,
+) -> actix_web::Result<RepoResult<T>>
+where
+    F: FnOnce(&mut PgConnection) -> RepoResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let db = db.clone();
+    let handle = CancelHandle::new();
+    let guard = handle.cancel_on_drop();
+
+    let work = web::block(move || {
+        let mut session = db.session().with_timeouts(timeouts).cancellable_by(handle);
+        match access {
+            Access::Read => session.read(f),
+            Access::Write => session.write(f),
+        }
+    });
+
+    let client = CLIENT.try_with(Clone::clone).ok().flatten();
+
+    // Returning early drops the guard, which cancels the query
+    match select(pin!(timeout(REQUEST_TIMEOUT, work)), pin!(client_gone(client))).await {
+        Either::Left((Ok(result), _)) => {
+            guard.disarm();
+            result?.map_err(unavailable)
+        }
+        Either::Left((Err(_), _)) => {
+            Err(actix_web::error::ErrorGatewayTimeout("Database timed out"))
+        }
+        Either::Right(_) => Err(actix_web::error::ErrorBadRequest("Client disconnected")),
+    }
+}
+
+tokio::task_local! {
+    /// The client of the request being handled, set by the middleware in `main`.
+    static CLIENT: Option<ClientSocket>;
+}
+
+/// A duplicate of the client's socket, to notice when the client hangs up while its request waits
+/// for the database. Actix itself only notices when it writes the response.
+#[derive(Clone)]
+struct ClientSocket(Arc<TcpStream>);
+
+impl ClientSocket {
+    /// Registered with `HttpServer::on_connect`.
+    fn on_connect(conn: &dyn Any, extensions: &mut Extensions) {
+        let Some(tcp) = conn.downcast_ref::<actix_web::rt::net::TcpStream>() else {
+            return;
+        };
+
+        match tcp.as_fd().try_clone_to_owned() {
+            Ok(fd) => {
+                extensions.insert(ClientSocket(Arc::new(TcpStream::from(fd))));
+            }
+            Err(e) => log::warn!("Can't watch the client socket: {}", e),
+        }
+    }
+
+    /// Peeks without blocking, since the socket is shared with Actix and in non-blocking mode.
+    fn is_closed(&self) -> bool {
+        match self.0.peek(&mut [0; 1]) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(e) => e.kind() != io::ErrorKind::WouldBlock,
+        }
+    }
+}
+
+/// Resolves when `client` hangs up, never if it's unknown.
+async fn client_gone(client: Option<ClientSocket>) {
+    let Some(client) = client else {
+        return pending().await;
+    };
+
+    while !client.is_closed() {
+        sleep(DISCONNECT_CHECK_INTERVAL).await;
+    }
+}
+
+fn unavailable(e: PoolError) -> actix_web::Error {
+    log::error!("No database connection available: {}", e);
+    actix_web::error::ErrorServiceUnavailable("Database unavailable")
+}
+
+fn json_response<T: Serialize>(result: RepoResult<T>) -> HttpResponse {
+    match result {
+        Ok(value) => HttpResponse::Ok().json(value),
+        Err(e) => repo_error_response(e),
+    }
+}
+
+/// Responds with the row and its version as ETag, to be sent back in `If-Match`.
+fn versioned_response<T: Serialize>(version: i32, value: T) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ETag(EntityTag::new_strong(version.to_string())))
+        .json(value)
+}
+
+/// What `If-Match` asks to update from.
+enum IfMatch {
+    /// `*`, whatever the current version is.
+    Any,
+    Version(i32),
+}
+
+impl IfMatch {
+    /// The version to update from, `current` for `*`.
+    fn version(&self, current: impl FnOnce() -> RepoResult<i32>) -> RepoResult<i32> {
+        match self {
+            IfMatch::Any => current(),
+            IfMatch::Version(version) => Ok(*version),
+        }
+    }
+}
+
+/// `If-Match`, or the response to send if it's missing (428) or can't match one of our ETags
+/// (412).
+fn if_match(req: &HttpRequest) -> Result<IfMatch, HttpResponse> {
+    let Some(if_match) = req.headers().get(IF_MATCH) else {
+        return Err(HttpResponse::PreconditionRequired().body("If-Match header required"));
+    };
+
+    if_match
+        .to_str()
+        .ok()
+        .and_then(parse_if_match)
+        .ok_or_else(|| HttpResponse::PreconditionFailed().finish())
+}
+
+fn parse_if_match(value: &str) -> Option<IfMatch> {
+    match value.trim() {
+        "*" => Some(IfMatch::Any),
+        // If-Match compares strongly, so a weak ETag never matches (RFC 9110 13.1.1)
+        tag if tag.starts_with("W/") => None,
+        tag => parse_etag(tag).map(IfMatch::Version),
+    }
+}
+
+/// The version in an ETag made by `versioned_response`, e.g. `"3"`.
+fn parse_etag(value: &str) -> Option<i32> {
+    value.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+}
+
+#[derive(Deserialize)]
+struct BookUpdate {
+    title: String,
+}
+
+#[get("/books/{id}")]
+async fn get_book(db: web::Data<DbRouter>, path: Path<i32>) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+
+    let result = read_conn(&db, move |conn| BookRepository::new(conn).get(id)).await?;
+
+    Ok(match result {
+        Ok(book) => versioned_response(book.version, book),
+        Err(e) => repo_error_response(e),
+    })
+}
+
+/// Requires `If-Match` with the ETag from `GET /books/{id}`, and fails with 412 if the book was
+/// changed since.
+#[patch("/books/{id}")]
+async fn update_book(
+    db: web::Data<DbRouter>,
+    req: HttpRequest,
+    path: Path<i32>,
+    body: Json<BookUpdate>,
+    editor: Editor,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+    let if_match = match if_match(&req) {
+        Ok(if_match) => if_match,
+        Err(response) => return Ok(response),
+    };
+
+    let result = with_conn(&db, move |conn| {
+        audit::with_actor(conn, &editor.0, |conn| {
+            let mut books = BookRepository::new(conn);
+            let version = if_match.version(|| Ok(books.get(id)?.version))?;
+            let changes = BookChanges {
+                title: Some(&body.title),
+            };
+            books.update_with_version(id, version, &changes)
+        })
+    })
+    .await?;
+
+    Ok(match result {
+        Ok(book) => versioned_response(book.version, book),
+        Err(e) => repo_error_response(e),
+    })
+}
+
+#[get("/books/{id}/history")]
+async fn book_history(
+    db: web::Data<DbRouter>,
+    path: Path<i32>,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+
+    let history = read_conn(&db, move |conn| Ok(audit::history(conn, "books", id)?)).await?;
+
+    Ok(json_response(history))
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    /// Text search configuration of the pages to search, e.g. `german`.
+    lang: Option<String>,
+}
+
+/// Books with pages matching `q`, with highlighted excerpts of the matching pages.
+#[get("/search")]
+async fn search_books(
+    db: web::Data<DbRouter>,
+    query: Query<SearchQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let SearchQuery { q, lang } = query.into_inner();
+    let lang = lang.unwrap_or_else(|| search::DEFAULT_CONFIG.to_string());
+
+    let result = read_conn(&db, move |conn| {
+        if !search::config_exists(conn, &lang)? {
+            return Ok(None);
+        }
+        Ok(Some(search::search_books(conn, &q, &lang)?))
+    })
+    .await?;
+
+    Ok(match result {
+        Ok(Some(books)) => HttpResponse::Ok().json(books),
+        Ok(None) => HttpResponse::BadRequest().body("Unknown lang"),
+        Err(e) => repo_error_response(e),
+    })
+}
+
+#[derive(Deserialize)]
+struct AutocompleteQuery {
+    q: String,
+    limit: Option<i64>,
+}
+
+/// Book titles and author names matching what was typed so far, best match first.
+#[get("/autocomplete")]
+async fn autocomplete(
+    db: web::Data<DbRouter>,
+    query: Query<AutocompleteQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let AutocompleteQuery { q, limit } = query.into_inner();
+    let limit = limit.unwrap_or(10).clamp(1, 50);
+
+    let result = read_conn(&db, move |conn| Ok(fuzzy::autocomplete(conn, &q, limit)?)).await?;
+
+    Ok(json_response(result))
+}
+
+#[derive(Deserialize)]
+struct PostsQuery {
+    status: Option<PostStatus>,
+    page: Option<i64>,
+}
+
+/// Awaits its queries on an async connection instead of taking a thread from the blocking pool.
+/// Only editors can list posts that aren't published.
+#[get("/posts")]
+async fn list_posts(
+    pool: web::Data<AsyncPgPool>,
+    query: Query<PostsQuery>,
+    editor: Option<Editor>,
+) -> actix_web::Result<HttpResponse> {
+    let PostsQuery { status, page } = query.into_inner();
+    let status = match status {
+        _ if editor.is_some() => status,
+        None | Some(PostStatus::Published) => Some(PostStatus::Published),
+        Some(_) => return Ok(HttpResponse::Forbidden().body("Only published posts are public")),
+    };
+
+    let mut conn = pool.get().await.map_err(|e| {
+        log::error!("No database connection available: {}", e);
+        actix_web::error::ErrorServiceUnavailable("Database unavailable")
+    })?;
+    // Like `run_in_session`, but cancelled through the connection's cancel key
+    let guard = cancel_on_drop(conn.cancel_token());
+
+    let filter = PostFilter {
+        status,
+        recent_first: true,
+        ..Default::default()
+    };
+    let work = pin!(timeout(
+        REQUEST_TIMEOUT,
+        list_posts_async(&mut conn, &filter, page.unwrap_or(1)),
+    ));
+    let client = CLIENT.try_with(Clone::clone).ok().flatten();
+
+    // Cancels before the connection goes back to the pool
+    let response = match select(work, pin!(client_gone(client))).await {
+        Either::Left((Ok(result), _)) => {
+            guard.disarm();
+            Ok(json_response(result))
+        }
+        Either::Left((Err(_), _)) => {
+            guard.cancel().await;
+            Err(actix_web::error::ErrorGatewayTimeout("Database timed out"))
+        }
+        Either::Right(_) => {
+            guard.cancel().await;
+            Err(actix_web::error::ErrorBadRequest("Client disconnected"))
+        }
+    };
+    response
+}
+
+#[derive(Deserialize)]
+struct NewPostBody {
+    title: String,
+    body: String,
+}
+
+/// Creates a draft.
+#[post("/posts")]
+async fn create_post(
+    db: web::Data<DbRouter>,
+    body: Json<NewPostBody>,
+    editor: Editor,
+) -> actix_web::Result<HttpResponse> {
+    let result = with_conn(&db, move |conn| {
+        audit::with_actor(conn, &editor.0, |conn| {
+            PostRepository::new(conn).create(&body.title, &body.body)
+        })
+    })
+    .await?;
+
+    Ok(match result {
+        Ok(created) => HttpResponse::Created()
+            .insert_header(ETag(EntityTag::new_strong(created.version.to_string())))
+            .json(created),
+        Err(e) => repo_error_response(e),
+    })
+}
+
+/// Drafts, scheduled and archived posts are only found for editors.
+#[get("/posts/slug/{slug}")]
+async fn get_post_by_slug(
+    db: web::Data<DbRouter>,
+    path: Path<String>,
+    editor: Option<Editor>,
+) -> actix_web::Result<HttpResponse> {
+    let slug = path.into_inner();
+
+    let result = read_conn(&db, move |conn| {
+        let mut posts = PostRepository::new(conn);
+        if editor.is_some() {
+            posts.get_by_slug(&slug)
+        } else {
+            posts.get_published_by_slug(&slug)
+        }
+    })
+    .await?;
+
+    Ok(match result {
+        Ok(found) => versioned_response(found.version, found),
+        Err(e) => repo_error_response(e),
+    })
+}
+
+/// Like `GET /posts/slug/{slug}`.
+#[get("/posts/{id}")]
+async fn get_post(
+    db: web::Data<DbRouter>,
+    path: Path<i32>,
+    editor: Option<Editor>,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+
+    let result = read_conn(&db, move |conn| {
+        let mut posts = PostRepository::new(conn);
+        if editor.is_some() {
+            posts.get(id)
+        } else {
+            posts.get_published(id)
+        }
+    })
+    .await?;
+
+    Ok(match result {
+        Ok(found) => versioned_response(found.version, found),
+        Err(e) => repo_error_response(e),
+    })
+}
+
+#[derive(Deserialize)]
+struct PostUpdate {
+    title: Option<String>,
+    body: Option<String>,
+}
+
+/// Edits the title and/or body, recorded as a new revision. Requires `If-Match` like
+/// `PATCH /books/{id}`.
+#[patch("/posts/{id}")]
+async fn edit_post(
+    db: web::Data<DbRouter>,
+    req: HttpRequest,
+    path: Path<i32>,
+    body: Json<PostUpdate>,
+    editor: Editor,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+    let if_match = match if_match(&req) {
+        Ok(if_match) => if_match,
+        Err(response) => return Ok(response),
+    };
+
+    let result = with_conn(&db, move |conn| {
+        audit::with_actor(conn, &editor.0, |conn| {
+            let mut posts = PostRepository::new(conn);
+            let version = if_match.version(|| Ok(posts.get(id)?.version))?;
+            let changes = PostChanges {
+                title: body.title.as_deref(),
+                body: body.body.as_deref(),
+            };
+            posts.edit(id, version, &changes)
+        })
+    })
+    .await?;
+
+    Ok(match result {
+        Ok(updated) => versioned_response(updated.version, updated),
+        Err(e) => repo_error_response(e),
+    })
+}
+
+#[derive(Deserialize)]
+struct Schedule {
+    publish_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct PostAction {
+    id: i32,
+    action: String,
+}
+
+/// `publish`, `unpublish`, `archive`, or `schedule` with a `{"publish_at": ...}` body. Responds
+/// with 409 if the post can't make that transition from its current status.
+#[post("/posts/{id}/{action}")]
+async fn post_action(
+    db: web::Data<DbRouter>,
+    path: Path<PostAction>,
+    body: Option<Json<Schedule>>,
+    editor: Editor,
+) -> actix_web::Result<HttpResponse> {
+    let PostAction { id, action } = path.into_inner();
+
+    let schedule_at = match (action.as_str(), body) {
+        ("publish" | "unpublish" | "archive", _) => None,
+        ("schedule", Some(body)) => Some(body.publish_at),
+        ("schedule", None) => {
+            return Ok(HttpResponse::BadRequest().body("publish_at required"));
+        }
+        _ => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let result = with_conn(&db, move |conn| {
+        audit::with_actor(conn, &editor.0, |conn| {
+            let mut posts = PostRepository::new(conn);
+            match (action.as_str(), schedule_at) {
+                ("publish", _) => posts.publish(id),
+                ("unpublish", _) => posts.unpublish(id),
+                ("archive", _) => posts.archive(id),
+                (_, Some(at)) => posts.schedule(id, at),
+                _ => unreachable!(),
+            }
+        })
+    })
+    .await?;
+
+    Ok(match result {
+        Ok(updated) => versioned_response(updated.version, updated),
+        Err(e) => repo_error_response(e),
+    })
+}
+
+/// For editors only, earlier revisions can hold text that was never published.
+#[get("/posts/{id}/revisions")]
+async fn post_revisions(
+    db: web::Data<DbRouter>,
+    path: Path<i32>,
+    _editor: Editor,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+
+    let result = read_conn(&db, move |conn| PostRepository::new(conn).revisions(id)).await?;
+
+    Ok(json_response(result))
+}
+
+#[derive(Deserialize)]
+struct ReportsQuery {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+/// Reports with their items, most played first.
+#[get("/reports")]
+async fn list_reports(
+    db: web::Data<DbRouter>,
+    query: Query<ReportsQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let ReportsQuery { page, per_page } = query.into_inner();
+    let timeouts = Timeouts {
+        statement: Some(REPORTS_TIMEOUT),
+        ..request_timeouts()
+    };
+
+    let result = run_in_session(&db, Access::Read, timeouts, move |conn| {
+        ReportRepository::new(conn).list_with_items(page.unwrap_or(1), per_page.unwrap_or(10))
+    })
+    .await?;
+
+    Ok(json_response(result))
+}
+
+fn repo_error_response(e: RepoError) -> HttpResponse {
+    match e {
+        RepoError::NotFound => HttpResponse::NotFound().finish(),
+        RepoError::Conflict(message) => HttpResponse::Conflict().body(message),
+        RepoError::StaleObject { actual, .. } => HttpResponse::PreconditionFailed()
+            .insert_header(ETag(EntityTag::new_strong(actual.to_string())))
+            .finish(),
+        RepoError::Database(e) if is_cancelled(&e) => {
+            HttpResponse::GatewayTimeout().body("Database timed out")
+        }
+        RepoError::Database(e) => {
+            log::error!("Database error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Whether a statement was cancelled by `statement_timeout` or a `CancelHandle`.
+fn is_cancelled(e: &diesel::result::Error) -> bool {
+    match e {
+        diesel::result::Error::DatabaseError(_, info) => {
+            info.message().starts_with("canceling statement")
+        }
+        _ => false,
+    }
+}
+
+/// Publishes scheduled posts once their `publish_at` has passed.
+async fn publish_worker(pool: PgPool) {
+    loop {
+        let pool = pool.clone();
+        let result = web::block(move || -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+            let mut conn = pool.get()?;
+            Ok(PostRepository::new(&mut conn).publish_due()?)
+        });
+
+        match result.await {
+            Ok(Ok(posts)) => {
+                for published in posts {
+                    log::info!("Published scheduled post {} ({})", published.slug, published.id);
+                }
+            }
+            Ok(Err(e)) => log::error!("Publishing scheduled posts failed: {}", e),
+            Err(e) => log::error!("Publishing scheduled posts failed: {}", e),
+        }
+
+        sleep(PUBLISH_INTERVAL).await;
+    }
+}
+
 struct AppState {
     app_name: String,
 }
 
+/// Off by default, so instances only migrate when the deploy asks for it: pass `--migrate` or set
+/// `RUN_MIGRATIONS=true`. Instances starting together take turns, see `with_migration_lock`.
+fn migrate_on_boot() -> bool {
+    std::env::args().skip(1).any(|arg| arg == "--migrate")
+        || std::env::var("RUN_MIGRATIONS").is_ok_and(|value| matches!(value.trim(), "1" | "true"))
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    tokio::spawn(async {
-        let mut i = 0;
-        loop {
-            println!("Background task: {}", i);
-            i += 1;
+    // First, so the retries while connecting are logged
+    env_logger::init_from_env(Env::default().default_filter_or("info"));
 
-            sleep(Duration::from_secs(1)).await;
+    let db = establish_router().map_err(std::io::Error::other)?;
+    if migrate_on_boot() {
+        let mut conn = db.writer().map_err(std::io::Error::other)?;
+        for version in run_pending_migrations(&mut conn).map_err(std::io::Error::other)? {
+            log::info!("Applied migration {}", version);
         }
-    });
+    }
+    tokio::spawn(publish_worker(db.primary().clone()));
+    let db = web::Data::new(db);
+    let async_pool = web::Data::new(
+        establish_async_pool()
+            .await
+            .map_err(std::io::Error::other)?,
+    );
+
+    let editor_tokens = web::Data::new(EditorTokens::from_env()?);
 
     let app_state = web::Data::new(AppState {
         app_name: String::from("Actix LOLLOL"),
     });
 
-    env_logger::init_from_env(Env::default().default_filter_or("info"));
-
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
@@ -96,22 +832,104 @@ async fn main() -> std::io::Result<()> {
                     req.match_pattern().unwrap_or("unknown".to_string())
                 );
 
-                srv.call(req).map(move |res| {
+                let client = req.conn_data::<ClientSocket>().cloned();
+
+                CLIENT.scope(client, srv.call(req)).map(move |res| {
                     let dur = Instant::now().duration_since(start);
 
                     println!("datadog: {} ({:?})", path, dur);
-                    return res;
+                    res
                 })
             })
             .app_data(app_state.clone())
+            .app_data(editor_tokens.clone())
+            .app_data(db.clone())
+            .app_data(async_pool.clone())
             .service(hello)
             .service(test)
             .service(test2)
             .service(post)
             .service(echo)
+            .service(get_book)
+            .service(update_book)
+            .service(book_history)
+            .service(search_books)
+            .service(autocomplete)
+            .service(list_posts)
+            .service(create_post)
+            .service(get_post_by_slug)
+            .service(get_post)
+            .service(edit_post)
+            .service(post_action)
+            .service(post_revisions)
+            .service(list_reports)
             .route("/hey", web::get().to(manual_hello))
     })
+    .on_connect(ClientSocket::on_connect)
     .bind(("127.0.0.1", 8080))?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::ETAG;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn if_match_header(value: &str) -> Result<IfMatch, StatusCode> {
+        let req = TestRequest::default()
+            .insert_header((IF_MATCH, value))
+            .to_http_request();
+        if_match(&req).map_err(|response| response.status())
+    }
+
+    #[test]
+    fn if_match_takes_our_strong_etags_and_star() {
+        assert!(matches!(if_match_header("\"3\""), Ok(IfMatch::Version(3))));
+        assert!(matches!(if_match_header(" * "), Ok(IfMatch::Any)));
+
+        assert_eq!(
+            if_match_header("W/\"3\"").err(),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(
+            if_match_header("3").err(),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(
+            if_match_header("\"3\", \"4\"").err(),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(
+            if_match(&req).err().map(|response| response.status()),
+            Some(StatusCode::PRECONDITION_REQUIRED)
+        );
+    }
+
+    #[test]
+    fn stale_versions_fail_the_precondition_with_the_current_etag() {
+        let response = repo_error_response(RepoError::StaleObject {
+            expected: 1,
+            actual: 2,
+        });
+
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(response.headers().get(ETAG).unwrap(), "\"2\"");
+    }
+
+    #[test]
+    fn star_updates_from_the_current_version() {
+        assert_eq!(IfMatch::Any.version(|| Ok(7)).unwrap(), 7);
+        assert_eq!(
+            IfMatch::Version(3)
+                .version(|| panic!("looked up the current version"))
+                .unwrap(),
+            3
+        );
+    }
+}
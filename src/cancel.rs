@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::sql_types::{Bool, Integer};
+
+use crate::config::{self, DbConfig};
+
+/// Cancels the query a connection is running from another thread, e.g. when the HTTP client went
+/// away or a deadline passed. The query fails with a `query_canceled` `DatabaseError`.
+///
+/// Diesel doesn't expose libpq's cancel key, so this cancels with `pg_cancel_backend` on a
+/// connection of its own, which interrupts the backend the same way a cancel request does. It
+/// doesn't come from a pool, so cancelling works while the pool is exhausted. For async
+/// connections, see `async_pg::connect_async_cancellable`.
+#[derive(Clone, Default)]
+pub struct CancelHandle {
+    running: Arc<Mutex<Option<Running>>>,
+    /// Held while a cancel is in flight, see `run`.
+    cancelling: Arc<Mutex<()>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[derive(Clone)]
+struct Running {
+    pid: i32,
+    config: DbConfig,
+}
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        CancelHandle::default()
+    }
+
+    /// Runs `f` on `conn`, which connects with `config`, so `cancel` can interrupt it. Fails right
+    /// away if `cancel` was called before.
+    pub fn run<T, E, F>(&self, config: &DbConfig, conn: &mut PgConnection, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut PgConnection) -> Result<T, E>,
+        E: From<DieselError>,
+    {
+        let pid = diesel::select(diesel::dsl::sql::<Integer>("pg_backend_pid()"))
+            .get_result::<i32>(conn)?;
+
+        {
+            let mut running = self.running.lock().unwrap();
+            if self.is_cancelled() {
+                return Err(cancelled_error().into());
+            }
+            *running = Some(Running {
+                pid,
+                config: config.clone(),
+            });
+        }
+
+        let result = f(conn);
+
+        // Waits for a cancel in flight, so it can't hit the next user of the connection
+        let _cancelling = self.cancelling.lock().unwrap();
+        *self.running.lock().unwrap() = None;
+
+        result
+    }
+
+    /// Cancels the query running in `run`, and fails any later `run`. Returns whether there was a
+    /// query to cancel.
+    pub fn cancel(&self) -> bool {
+        let _cancelling = self.cancelling.lock().unwrap();
+        let running = {
+            let running = self.running.lock().unwrap();
+            self.cancelled.store(true, Ordering::SeqCst);
+            running.clone()
+        };
+
+        let Some(Running { pid, config }) = running else {
+            return false;
+        };
+
+        let cancelled = config::connect(&config)
+            .map_err(|e| e.to_string())
+            .and_then(|mut conn| {
+                diesel::select(
+                    diesel::dsl::sql::<Bool>("pg_cancel_backend(")
+                        .bind::<Integer, _>(pid)
+                        .sql(")"),
+                )
+                .get_result::<bool>(&mut conn)
+                .map_err(|e| e.to_string())
+            });
+
+        match cancelled {
+            Ok(cancelled) => cancelled,
+            Err(e) => {
+                log::warn!("Cancelling the query of backend {} failed: {}", pid, e);
+                false
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// A guard that cancels when it's dropped before `disarm` is called. Keep it in an async
+    /// handler while it waits for the query, and it cancels the query if the handler is dropped
+    /// because the client disconnected or a timeout ran out.
+    pub fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop {
+            handle: Some(self.clone()),
+        }
+    }
+}
+
+fn cancelled_error() -> DieselError {
+    DieselError::DatabaseError(
+        DatabaseErrorKind::Unknown,
+        Box::new("canceling statement due to user request".to_string()),
+    )
+}
+
+/// See `CancelHandle::cancel_on_drop`.
+pub struct CancelOnDrop {
+    handle: Option<CancelHandle>,
+}
+
+impl CancelOnDrop {
+    pub fn disarm(mut self) {
+        self.handle = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            // Cancelling takes a round trip, don't block the executor dropping this
+            thread::spawn(move || handle.cancel());
+        }
+    }
+}
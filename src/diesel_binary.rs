@@ -0,0 +1,166 @@
+use std::error::Error;
+use std::io::Write;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+// Header byte layout: the low nibble is the format, the high nibble holds flags.
+const FORMAT_MASK: u8 = 0x0f;
+const FLAG_ZSTD: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFormat {
+    MessagePack = 1,
+    Cbor = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd(i32),
+}
+
+/// Writes `value` as a header byte followed by the (optionally compressed) payload.
+pub fn encode<T: Serialize, W: Write>(
+    value: &T,
+    format: BinaryFormat,
+    compression: Compression,
+    out: &mut W,
+) -> Result<(), BoxError> {
+    let payload = match format {
+        BinaryFormat::MessagePack => rmp_serde::to_vec_named(value)?,
+        BinaryFormat::Cbor => {
+            let mut buf = Vec::new();
+            ciborium::into_writer(value, &mut buf)?;
+            buf
+        }
+    };
+
+    match compression {
+        Compression::None => {
+            out.write_all(&[format as u8])?;
+            out.write_all(&payload)?;
+        }
+        Compression::Zstd(level) => {
+            out.write_all(&[format as u8 | FLAG_ZSTD])?;
+            out.write_all(&zstd::encode_all(payload.as_slice(), level)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a value written by `encode`. The format and compression are taken from the header, so
+/// rows written with older settings stay readable after a type switches format.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BoxError> {
+    let (&header, payload) = bytes.split_first().ok_or("Empty binary value")?;
+
+    let decompressed;
+    let payload = if header & FLAG_ZSTD != 0 {
+        decompressed = zstd::decode_all(payload)?;
+        decompressed.as_slice()
+    } else {
+        payload
+    };
+
+    match header & FORMAT_MASK {
+        1 => Ok(rmp_serde::from_slice(payload)?),
+        2 => Ok(ciborium::from_reader(payload)?),
+        format => Err(format!("Unsupported binary encoding format: {}", format).into()),
+    }
+}
+
+#[macro_export]
+macro_rules! diesel_binary {
+    ($type: ty, $format: ident) => {
+        $crate::diesel_binary!(@impl $type, $format, $crate::diesel_binary::Compression::None);
+    };
+    ($type: ty, $format: ident, zstd($level: literal)) => {
+        $crate::diesel_binary!(@impl $type, $format, $crate::diesel_binary::Compression::Zstd($level));
+    };
+    (@impl $type: ty, $format: ident, $compression: expr) => {
+        impl ::diesel::deserialize::FromSql<::diesel::sql_types::Binary, ::diesel::pg::Pg> for $type {
+            fn from_sql(value: ::diesel::pg::PgValue<'_>) -> ::diesel::deserialize::Result<Self> {
+                $crate::diesel_binary::decode(value.as_bytes())
+            }
+        }
+
+        impl ::diesel::serialize::ToSql<::diesel::sql_types::Binary, ::diesel::pg::Pg> for $type {
+            fn to_sql(
+                &self,
+                out: &mut ::diesel::serialize::Output<::diesel::pg::Pg>,
+            ) -> ::diesel::serialize::Result {
+                $crate::diesel_binary::encode(
+                    self,
+                    $crate::diesel_binary::BinaryFormat::$format,
+                    $compression,
+                    out,
+                )?;
+                Ok(::diesel::serialize::IsNull::No)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        name: String,
+        tags: Vec<String>,
+        count: Option<u32>,
+    }
+
+    fn sample() -> Sample {
+        Sample {
+            name: "kjell".to_string(),
+            tags: vec!["a".to_string(), "b".to_string()],
+            count: Some(3),
+        }
+    }
+
+    fn encoded(format: BinaryFormat, compression: Compression) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        encode(&sample(), format, compression, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trips_every_format_and_compression() {
+        for format in [BinaryFormat::MessagePack, BinaryFormat::Cbor] {
+            for compression in [Compression::None, Compression::Zstd(3)] {
+                let bytes = encoded(format, compression);
+                assert_eq!(decode::<Sample>(&bytes).unwrap(), sample());
+            }
+        }
+    }
+
+    #[test]
+    fn header_holds_format_and_compression() {
+        assert_eq!(encoded(BinaryFormat::MessagePack, Compression::None)[0], 0x01);
+        assert_eq!(encoded(BinaryFormat::Cbor, Compression::None)[0], 0x02);
+        assert_eq!(encoded(BinaryFormat::Cbor, Compression::Zstd(1))[0], 0x12);
+    }
+
+    #[test]
+    fn rejects_empty_and_unknown_formats() {
+        assert!(decode::<Sample>(&[]).is_err());
+
+        let mut bytes = encoded(BinaryFormat::MessagePack, Compression::None);
+        bytes[0] = 0x07;
+        let error = decode::<Sample>(&bytes).unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported binary encoding format: 7");
+    }
+
+    #[test]
+    fn rejects_corrupt_payloads() {
+        let mut bytes = encoded(BinaryFormat::Cbor, Compression::Zstd(3));
+        bytes.truncate(bytes.len() / 2);
+        assert!(decode::<Sample>(&bytes).is_err());
+    }
+}
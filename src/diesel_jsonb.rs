@@ -1,49 +1,92 @@
+use std::fmt;
+use std::io::Write;
+
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{IsNull, Output};
+use diesel::{deserialize, serialize};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Reads a `jsonb` value in the binary protocol: a version byte followed by the JSON text.
+pub fn from_jsonb<T: DeserializeOwned>(value: PgValue<'_>) -> deserialize::Result<T> {
+    let bytes = value.as_bytes();
+    match bytes.split_first() {
+        Some((1, json)) => {
+            serde_json::from_slice(json).map_err(|e| format!("Invalid JSON: {}", e).into())
+        }
+        Some(_) => Err("Unsupported JSONB encoding version".into()),
+        None => Err("Empty JSONB value".into()),
+    }
+
+    // This version uses Diesel's internal translation to serde_json::Value instead,
+    // which is simpler but involves an intermediate step of materializing the value as a
+    // serde_json::Value.
+    // let value = <serde_json::Value as FromSql<Jsonb, Pg>>::from_sql(value)?;
+    // Ok(serde_json::from_value(value)?)
+}
+
+/// Writes `value` as `jsonb` in the binary protocol, see `from_jsonb`.
+pub fn to_jsonb<T: Serialize>(value: &T, out: &mut Output<Pg>) -> serialize::Result {
+    out.write_all(&[1])?;
+    serde_json::to_writer(out, value)
+        .map(|_| IsNull::No)
+        .map_err(Into::into)
+
+    // Same as above, using Diesel's internal serde_json::Value support to do most of the work.
+    // let value = serde_json::to_value(value)?;
+    // <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&value, &mut out.reborrow())
+}
+
 #[macro_export]
 macro_rules! diesel_jsonb {
     ($type: ty) => {
-        impl ::diesel::deserialize::FromSql<::diesel::sql_types::Jsonb, diesel::pg::Pg> for $type {
+        impl ::diesel::deserialize::FromSql<::diesel::sql_types::Jsonb, ::diesel::pg::Pg> for $type {
             fn from_sql(
-                value: diesel::backend::RawValue<'_, diesel::pg::Pg>,
-            ) -> diesel::deserialize::Result<Self> {
-                let bytes = value.as_bytes();
-                if bytes[0] != 1 {
-                    return Err("Unsupported JSONB encoding version".into());
-                }
-
-                ::serde_json::from_slice(&bytes[1..])
-                    .map_err(|e| format!("Invalid JSON: {}", e).into())
-
-                // This version uses Diesel's internal translation to serde_json::Value instead,
-                // which is simpler but involves an intermediate step of materializing the value as a
-                // serde_json::Value.
-                // let value = <serde_json::Value as ::diesel::deserialize::FromSql<
-                //     ::diesel::sql_types::Jsonb,
-                //     ::diesel::pg::Pg,
-                // >>::from_sql(value)?;
-                // Ok(serde_json::from_value(value)?)
+                value: ::diesel::pg::PgValue<'_>,
+            ) -> ::diesel::deserialize::Result<Self> {
+                $crate::diesel_jsonb::from_jsonb(value)
             }
         }
 
         impl ::diesel::serialize::ToSql<::diesel::sql_types::Jsonb, ::diesel::pg::Pg> for $type {
             fn to_sql(
                 &self,
-                out: &mut ::diesel::serialize::Output<diesel::pg::Pg>,
-            ) -> diesel::serialize::Result {
-                use std::io::Write;
-
-                out.write_all(&[1])?;
-                serde_json::to_writer(out, self)
-                    .map(|_| diesel::serialize::IsNull::No)
-                    .map_err(Into::into)
-
-                // Same as above, using Diesel's internal serde_json::Value support to do most of the work.
-                // let value = serde_json::to_value(self)?;
-                // <serde_json::Value as diesel::serialize::ToSql<
-                //     diesel::sql_types::Jsonb,
-                //     diesel::pg::Pg,
-                // >>::to_sql(&value, &mut out.reborrow())
+                out: &mut ::diesel::serialize::Output<::diesel::pg::Pg>,
+            ) -> ::diesel::serialize::Result {
+                $crate::diesel_jsonb::to_jsonb(self, out)
             }
         }
     };
 }
 
+/// `diesel_jsonb!` for any serde type, as a wrapper. Useful for ad-hoc JSON results (e.g.
+/// `jsonb_build_object` / `jsonb_agg` queries) where implementing the traits on the type itself
+/// is not worth it.
+///
+/// Like `diesel_jsonb!`, this is implemented for the `Pg` backend, so it loads the same on an
+/// `AsyncPgConnection`.
+#[derive(Clone, PartialEq, ::diesel::AsExpression, ::diesel::FromSqlRow)]
+#[diesel(sql_type = ::diesel::sql_types::Jsonb)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> deserialize::FromSql<::diesel::sql_types::Jsonb, Pg> for Json<T> {
+    fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
+        from_jsonb(value).map(Json)
+    }
+}
+
+impl<T: Serialize> serialize::ToSql<::diesel::sql_types::Jsonb, Pg> for Json<T> {
+    fn to_sql(&self, out: &mut Output<Pg>) -> serialize::Result {
+        to_jsonb(&self.0, out)
+    }
+}
+
+// As the JSON it stands for, so writing a value doesn't also need `T: Debug`
+impl<T: Serialize> fmt::Debug for Json<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_string(&self.0) {
+            Ok(json) => write!(f, "Json({})", json),
+            Err(_) => f.write_str("Json(<unserializable>)"),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::migrations::write_migration;
+
+/// A Rust enum backed by a Postgres `CREATE TYPE ... AS ENUM`. Implemented by `diesel_pg_enum!`.
+///
+/// `VARIANTS` is in declaration order, which is also the order of the labels in Postgres.
+pub trait PgEnum: Sized + 'static {
+    const TYPE_NAME: &'static str;
+    const VARIANTS: &'static [&'static str];
+
+    fn as_pg_str(&self) -> &'static str;
+    fn from_pg_str(value: &str) -> Option<Self>;
+}
+
+#[macro_export]
+macro_rules! diesel_pg_enum {
+    ($type: ty, $sql_type: ty, $type_name: literal, { $($variant: ident => $value: literal),+ $(,)? }) => {
+        impl $crate::diesel_pg_enum::PgEnum for $type {
+            const TYPE_NAME: &'static str = $type_name;
+            const VARIANTS: &'static [&'static str] = &[$($value),+];
+
+            fn as_pg_str(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $value,)+
+                }
+            }
+
+            fn from_pg_str(value: &str) -> Option<Self> {
+                match value {
+                    $($value => Some(Self::$variant),)+
+                    _ => None,
+                }
+            }
+        }
+
+        impl ::diesel::deserialize::FromSql<$sql_type, ::diesel::pg::Pg> for $type {
+            fn from_sql(value: ::diesel::pg::PgValue<'_>) -> ::diesel::deserialize::Result<Self> {
+                // Enum labels are sent as plain text in both the text and binary protocol
+                let label = ::std::str::from_utf8(value.as_bytes())?;
+
+                <Self as $crate::diesel_pg_enum::PgEnum>::from_pg_str(label)
+                    .ok_or_else(|| format!("Unrecognized {} variant: {}", $type_name, label).into())
+            }
+        }
+
+        impl ::diesel::serialize::ToSql<$sql_type, ::diesel::pg::Pg> for $type {
+            fn to_sql(
+                &self,
+                out: &mut ::diesel::serialize::Output<::diesel::pg::Pg>,
+            ) -> ::diesel::serialize::Result {
+                use std::io::Write;
+
+                out.write_all(<Self as $crate::diesel_pg_enum::PgEnum>::as_pg_str(self).as_bytes())?;
+                Ok(::diesel::serialize::IsNull::No)
+            }
+        }
+    };
+}
+
+pub fn create_type_sql<T: PgEnum>() -> String {
+    let labels = T::VARIANTS
+        .iter()
+        .map(|v| quote(v))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("CREATE TYPE {} AS ENUM ({});\n", T::TYPE_NAME, labels)
+}
+
+pub fn drop_type_sql<T: PgEnum>() -> String {
+    format!("DROP TYPE {};\n", T::TYPE_NAME)
+}
+
+/// Why `add_values_sql` can't add the values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddValuesError {
+    /// The value is not a variant of the enum.
+    UnknownVariant {
+        type_name: &'static str,
+        value: String,
+    },
+    /// Every variant is new, the type should be created with `create_type_sql` instead.
+    NoExistingVariants { type_name: &'static str },
+}
+
+impl fmt::Display for AddValuesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddValuesError::UnknownVariant { type_name, value } => {
+                write!(f, "{} is not a variant of {}", value, type_name)
+            }
+            AddValuesError::NoExistingVariants { type_name } => {
+                write!(f, "{} has no existing variants to add to", type_name)
+            }
+        }
+    }
+}
+
+impl Error for AddValuesError {}
+
+impl From<AddValuesError> for io::Error {
+    fn from(e: AddValuesError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// `ALTER TYPE ... ADD VALUE` statements for `new_values`, positioned so that the label order in
+/// Postgres matches the declaration order of `T`.
+///
+/// Fails if a value is not a variant of `T`, or if every variant of `T` is new (use
+/// `create_type_sql` instead).
+pub fn add_values_sql<T: PgEnum>(new_values: &[&str]) -> Result<String, AddValuesError> {
+    if let Some(value) = new_values.iter().find(|v| !T::VARIANTS.contains(v)) {
+        return Err(AddValuesError::UnknownVariant {
+            type_name: T::TYPE_NAME,
+            value: value.to_string(),
+        });
+    }
+
+    let first_existing = T::VARIANTS
+        .iter()
+        .find(|v| !new_values.contains(v))
+        .ok_or(AddValuesError::NoExistingVariants {
+            type_name: T::TYPE_NAME,
+        })?;
+
+    let mut sql = String::new();
+
+    // Added in declaration order, so the previous variant always exists by the time we get to it
+    for (i, value) in T::VARIANTS.iter().enumerate() {
+        if !new_values.contains(value) {
+            continue;
+        }
+
+        let position = match i {
+            0 => format!("BEFORE {}", quote(first_existing)),
+            _ => format!("AFTER {}", quote(T::VARIANTS[i - 1])),
+        };
+
+        sql.push_str(&format!(
+            "ALTER TYPE {} ADD VALUE IF NOT EXISTS {} {};\n",
+            T::TYPE_NAME,
+            quote(value),
+            position
+        ));
+    }
+
+    Ok(sql)
+}
+
+pub fn write_create_type_migration<T: PgEnum>(
+    migrations_dir: impl AsRef<Path>,
+) -> io::Result<PathBuf> {
+    write_migration(
+        migrations_dir,
+        &format!("create_{}", T::TYPE_NAME),
+        &create_type_sql::<T>(),
+        &drop_type_sql::<T>(),
+    )
+}
+
+/// Fails with `io::ErrorKind::InvalidInput` wrapping an `AddValuesError` if `add_values_sql` does.
+pub fn write_add_values_migration<T: PgEnum>(
+    migrations_dir: impl AsRef<Path>,
+    new_values: &[&str],
+) -> io::Result<PathBuf> {
+    let up = add_values_sql::<T>(new_values)?;
+    let down = format!(
+        "-- Postgres cannot drop values from an enum. To revert, recreate {} without {} and\n\
+         -- convert the columns using it with `ALTER COLUMN ... TYPE ... USING col::text::...`.\n",
+        T::TYPE_NAME,
+        new_values.join(", ")
+    );
+
+    write_migration(
+        migrations_dir,
+        &format!("add_{}_values", T::TYPE_NAME),
+        &up,
+        &down,
+    )
+}
+
+fn quote(label: &str) -> String {
+    format!("'{}'", label.replace('\'', "''"))
+}
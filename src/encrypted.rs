@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::prelude::*;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Binary;
+use dotenvy::dotenv;
+use hmac::{Hmac, Mac};
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Sha256;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+// Envelope layout: [version][mode][key id (u32, big endian)][nonce][ciphertext + tag]
+// The version, mode and key id are authenticated as associated data, so they can't be swapped
+// without failing decryption. Version 1 envelopes left them out, they are only read.
+const ENVELOPE_VERSION: u8 = 2;
+const UNAUTHENTICATED_HEADER_VERSION: u8 = 1;
+const AAD_LEN: usize = 2 + 4;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = AAD_LEN + NONCE_LEN;
+
+/// AES-256-GCM keys by id. New values are always encrypted with the active key, older keys are
+/// kept around so existing rows can still be decrypted until they have been rotated.
+///
+/// Stored as JSON: `{"active": 2, "keys": {"1": "<base64>", "2": "<base64>"}}`
+pub struct Keyring {
+    active: u32,
+    keys: HashMap<u32, Key<Aes256Gcm>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyringFile {
+    active: u32,
+    keys: HashMap<u32, String>,
+}
+
+impl Keyring {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        let file: KeyringFile = serde_json::from_slice(&fs::read(path)?)?;
+
+        let mut keys = HashMap::new();
+        for (id, key) in file.keys {
+            let key = BASE64_STANDARD.decode(key)?;
+            if key.len() != 32 {
+                return Err(format!("Key {} is not a 256 bit key", id).into());
+            }
+            keys.insert(id, *Key::<Aes256Gcm>::from_slice(&key));
+        }
+
+        if !keys.contains_key(&file.active) {
+            return Err(format!("Active key {} is missing from the keyring", file.active).into());
+        }
+
+        Ok(Keyring {
+            active: file.active,
+            keys,
+        })
+    }
+
+    /// Writes the keyring readable by the owner only, also when the file already exists.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BoxError> {
+        let file = KeyringFile {
+            active: self.active,
+            keys: self
+                .keys
+                .iter()
+                .map(|(id, key)| (*id, BASE64_STANDARD.encode(key)))
+                .collect(),
+        };
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut out = options.open(path)?;
+
+        // The mode only applies to a new file, tighten an existing one before writing the keys
+        #[cfg(unix)]
+        out.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+
+        out.write_all(&serde_json::to_vec_pretty(&file)?)?;
+        Ok(())
+    }
+
+    /// A keyring with a single freshly generated key.
+    pub fn generate() -> Self {
+        Keyring {
+            active: 1,
+            keys: HashMap::from([(1, Aes256Gcm::generate_key(OsRng))]),
+        }
+    }
+
+    /// Generates a new key and makes it the active one, returning its id.
+    pub fn add_active_key(&mut self) -> u32 {
+        let id = self.keys.keys().max().copied().unwrap_or(0) + 1;
+        self.keys.insert(id, Aes256Gcm::generate_key(OsRng));
+        self.active = id;
+        id
+    }
+
+    pub fn active_key_id(&self) -> u32 {
+        self.active
+    }
+
+    fn key(&self, id: u32) -> Result<&Key<Aes256Gcm>, BoxError> {
+        self.keys
+            .get(&id)
+            .ok_or_else(|| format!("Unknown encryption key {}", id).into())
+    }
+}
+
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+/// Installs the process wide keyring. Fails if one has already been set or loaded.
+pub fn set_keyring(keyring: Keyring) -> Result<(), Keyring> {
+    KEYRING.set(keyring)
+}
+
+/// The process wide keyring, loaded from `KEYRING_FILE` on first use unless `set_keyring` was
+/// called first.
+pub fn keyring() -> Result<&'static Keyring, BoxError> {
+    if let Some(keyring) = KEYRING.get() {
+        return Ok(keyring);
+    }
+
+    dotenv().ok();
+
+    let path = env::var("KEYRING_FILE").map_err(|_| "KEYRING_FILE must be set")?;
+    let keyring = Keyring::from_file(path)?;
+
+    Ok(KEYRING.get_or_init(|| keyring))
+}
+
+pub trait EncryptionMode {
+    const MODE: u8;
+}
+
+/// A random nonce per write. The default, and the only safe choice unless lookups are needed.
+#[derive(Debug, Clone, Copy)]
+pub struct Randomized;
+
+/// The nonce is derived from the plaintext, so equal values encrypt to equal bytes (with the same
+/// key) and can be compared in SQL. This leaks which rows share a value.
+///
+/// Maps are serialized with their keys sorted, so equal maps encrypt the same whatever their
+/// iteration order. Sequences keep their order: a `HashSet` doesn't encrypt the same every time,
+/// use a `BTreeSet` or a sorted `Vec`.
+#[derive(Debug, Clone, Copy)]
+pub struct Deterministic;
+
+impl EncryptionMode for Randomized {
+    const MODE: u8 = 1;
+}
+
+impl EncryptionMode for Deterministic {
+    const MODE: u8 = 2;
+}
+
+/// A value stored encrypted, either in a `bytea` column or as a base64 string inside a serde
+/// type (e.g. one mapped with `diesel_jsonb!`). The plaintext is serialized as JSON before it is
+/// encrypted.
+#[derive(AsExpression, FromSqlRow)]
+#[diesel(sql_type = Binary)]
+pub struct Encrypted<T, M = Randomized> {
+    value: T,
+    key_id: Option<u32>,
+    mode: PhantomData<M>,
+}
+
+impl<T, M: EncryptionMode> Encrypted<T, M> {
+    pub fn new(value: T) -> Self {
+        Encrypted {
+            value,
+            key_id: None,
+            mode: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// The key this value was decrypted with, `None` if it has not been read from the database.
+    pub fn key_id(&self) -> Option<u32> {
+        self.key_id
+    }
+
+    /// Whether this value was encrypted with a key other than the active one.
+    pub fn needs_rotation(&self, keyring: &Keyring) -> bool {
+        self.key_id.is_some_and(|id| id != keyring.active_key_id())
+    }
+}
+
+impl<T: Serialize, M: EncryptionMode> Encrypted<T, M> {
+    fn encrypt(&self) -> Result<Vec<u8>, BoxError> {
+        self.encrypt_with(keyring()?)
+    }
+
+    /// The bytes stored in the database, encrypted with the active key of `keyring` instead of
+    /// the process wide one.
+    pub fn encrypt_with(&self, keyring: &Keyring) -> Result<Vec<u8>, BoxError> {
+        let key_id = keyring.active_key_id();
+        let key = keyring.key(key_id)?;
+
+        let (plaintext, nonce) = match M::MODE {
+            Deterministic::MODE => {
+                // Through `Value`, whose objects keep their keys sorted
+                let plaintext = serde_json::to_vec(&serde_json::to_value(&self.value)?)?;
+                let nonce = synthetic_nonce(key, &plaintext);
+                (plaintext, nonce)
+            }
+            _ => (
+                serde_json::to_vec(&self.value)?,
+                Aes256Gcm::generate_nonce(&mut OsRng),
+            ),
+        };
+
+        let mut envelope = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        envelope.push(ENVELOPE_VERSION);
+        envelope.push(M::MODE);
+        envelope.extend_from_slice(&key_id.to_be_bytes());
+
+        let payload = Payload {
+            msg: &plaintext,
+            aad: &envelope[..AAD_LEN],
+        };
+        let ciphertext = Aes256Gcm::new(key)
+            .encrypt(&nonce, payload)
+            .map_err(|_| "Encryption failed")?;
+
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&ciphertext);
+
+        Ok(envelope)
+    }
+}
+
+impl<T: DeserializeOwned, M: EncryptionMode> Encrypted<T, M> {
+    fn decrypt(envelope: &[u8]) -> Result<Self, BoxError> {
+        Self::decrypt_with(keyring()?, envelope)
+    }
+
+    /// Reads bytes written by `encrypt_with` or `ToSql` with the keys of `keyring` instead of the
+    /// process wide one.
+    pub fn decrypt_with(keyring: &Keyring, envelope: &[u8]) -> Result<Self, BoxError> {
+        if envelope.len() < HEADER_LEN {
+            return Err("Encrypted value is truncated".into());
+        }
+        let aad = match envelope[0] {
+            ENVELOPE_VERSION => &envelope[..AAD_LEN],
+            UNAUTHENTICATED_HEADER_VERSION => &[],
+            _ => return Err("Unsupported encryption envelope version".into()),
+        };
+        if envelope[1] != M::MODE {
+            return Err("Encrypted value was written with a different encryption mode".into());
+        }
+
+        let key_id = u32::from_be_bytes(envelope[2..AAD_LEN].try_into()?);
+        let nonce = Nonce::from_slice(&envelope[AAD_LEN..HEADER_LEN]);
+
+        let payload = Payload {
+            msg: &envelope[HEADER_LEN..],
+            aad,
+        };
+        let plaintext = Aes256Gcm::new(keyring.key(key_id)?)
+            .decrypt(nonce, payload)
+            .map_err(|_| "Decryption failed")?;
+
+        Ok(Encrypted {
+            value: serde_json::from_slice(&plaintext)?,
+            key_id: Some(key_id),
+            mode: PhantomData,
+        })
+    }
+}
+
+fn synthetic_nonce(
+    key: &Key<Aes256Gcm>,
+    plaintext: &[u8],
+) -> Nonce<<Aes256Gcm as AeadCore>::NonceSize> {
+    // Derive a separate MAC key so the AES key is never used directly for anything but AES
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(b"rust-pg deterministic nonce");
+    let nonce_key = mac.finalize().into_bytes();
+
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(&nonce_key).expect("HMAC accepts any key length");
+    mac.update(plaintext);
+    let digest = mac.finalize().into_bytes();
+
+    *Nonce::from_slice(&digest[..NONCE_LEN])
+}
+
+impl<T, M> Deref for Encrypted<T, M> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone, M> Clone for Encrypted<T, M> {
+    fn clone(&self) -> Self {
+        Encrypted {
+            value: self.value.clone(),
+            key_id: self.key_id,
+            mode: PhantomData,
+        }
+    }
+}
+
+impl<T: PartialEq, M> PartialEq for Encrypted<T, M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+// Never print the plaintext, these end up in logs
+impl<T, M> fmt::Debug for Encrypted<T, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encrypted")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, M> FromSql<Binary, Pg> for Encrypted<T, M>
+where
+    T: DeserializeOwned,
+    M: EncryptionMode,
+{
+    fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
+        Self::decrypt(value.as_bytes())
+    }
+}
+
+impl<T, M> ToSql<Binary, Pg> for Encrypted<T, M>
+where
+    T: Serialize,
+    M: EncryptionMode,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(&self.encrypt()?)?;
+        Ok(IsNull::No)
+    }
+}
+
+impl<T: Serialize, M: EncryptionMode> Serialize for Encrypted<T, M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let envelope = self.encrypt().map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&BASE64_STANDARD.encode(envelope))
+    }
+}
+
+impl<'de, T: DeserializeOwned, M: EncryptionMode> Deserialize<'de> for Encrypted<T, M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        let envelope = BASE64_STANDARD.decode(encoded).map_err(de::Error::custom)?;
+        Self::decrypt(&envelope).map_err(de::Error::custom)
+    }
+}
+
+/// Re-encrypts rows written with an old key, one transaction per batch.
+///
+/// `load_batch` returns up to `batch_size` rows with an id greater than the given one, ordered by
+/// id. `stale` picks the rows that need to be rewritten and `update` writes them back, which
+/// encrypts them with the active key. Returns the number of rows rewritten. Fails without loading
+/// anything if `batch_size` isn't positive.
+pub fn rotate_keys<R, L, S, U>(
+    conn: &mut PgConnection,
+    batch_size: i64,
+    mut load_batch: L,
+    stale: S,
+    mut update: U,
+) -> QueryResult<usize>
+where
+    L: FnMut(&mut PgConnection, i64, i64) -> QueryResult<Vec<(i64, R)>>,
+    S: Fn(&R) -> bool,
+    U: FnMut(&mut PgConnection, i64, R) -> QueryResult<()>,
+{
+    if batch_size <= 0 {
+        return Err(diesel::result::Error::QueryBuilderError(
+            format!("batch_size must be positive, got {}", batch_size).into(),
+        ));
+    }
+
+    let mut last_id = i64::MIN;
+    let mut rotated = 0;
+
+    loop {
+        let batch_len = conn.transaction(|conn| {
+            let rows = load_batch(conn, last_id, batch_size)?;
+            let batch_len = rows.len();
+
+            for (id, row) in rows {
+                last_id = id;
+
+                if stale(&row) {
+                    update(conn, id, row)?;
+                    rotated += 1;
+                }
+            }
+
+            QueryResult::Ok(batch_len)
+        })?;
+
+        if batch_len < batch_size as usize {
+            return Ok(rotated);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt<M: EncryptionMode>(keyring: &Keyring, value: &str) -> Vec<u8> {
+        Encrypted::<String, M>::new(value.to_string())
+            .encrypt_with(keyring)
+            .unwrap()
+    }
+
+    fn decrypt<M: EncryptionMode>(
+        keyring: &Keyring,
+        envelope: &[u8],
+    ) -> Result<Encrypted<String, M>, BoxError> {
+        Encrypted::decrypt_with(keyring, envelope)
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("rust_pg_{}_{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn round_trips_in_both_modes() {
+        let keyring = Keyring::generate();
+
+        let randomized = encrypt::<Randomized>(&keyring, "kjell@test.com");
+        let value = decrypt::<Randomized>(&keyring, &randomized).unwrap();
+        assert_eq!(*value, "kjell@test.com");
+        assert_eq!(value.key_id(), Some(1));
+
+        let deterministic = encrypt::<Deterministic>(&keyring, "kjell@test.com");
+        let value = decrypt::<Deterministic>(&keyring, &deterministic).unwrap();
+        assert_eq!(*value, "kjell@test.com");
+    }
+
+    #[test]
+    fn deterministic_mode_repeats_ciphertexts_and_randomized_does_not() {
+        let keyring = Keyring::generate();
+
+        assert_eq!(
+            encrypt::<Deterministic>(&keyring, "same"),
+            encrypt::<Deterministic>(&keyring, "same")
+        );
+        assert_ne!(
+            encrypt::<Deterministic>(&keyring, "same"),
+            encrypt::<Deterministic>(&keyring, "other")
+        );
+        assert_ne!(
+            encrypt::<Randomized>(&keyring, "same"),
+            encrypt::<Randomized>(&keyring, "same")
+        );
+    }
+
+    #[test]
+    fn fails_with_the_wrong_key() {
+        let envelope = encrypt::<Randomized>(&Keyring::generate(), "secret");
+
+        // Also key 1, but a different one
+        assert!(decrypt::<Randomized>(&Keyring::generate(), &envelope).is_err());
+
+        let mut rotated = Keyring::generate();
+        rotated.add_active_key();
+        let envelope = encrypt::<Randomized>(&rotated, "secret");
+        let error = decrypt::<Randomized>(&Keyring::generate(), &envelope).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown encryption key 2");
+    }
+
+    #[test]
+    fn fails_on_tampered_or_truncated_values() {
+        let keyring = Keyring::generate();
+        let envelope = encrypt::<Randomized>(&keyring, "secret");
+
+        for i in [HEADER_LEN - 1, HEADER_LEN, envelope.len() - 1] {
+            let mut tampered = envelope.clone();
+            tampered[i] ^= 1;
+            assert!(decrypt::<Randomized>(&keyring, &tampered).is_err());
+        }
+
+        assert!(decrypt::<Randomized>(&keyring, &envelope[..HEADER_LEN - 1]).is_err());
+        assert!(decrypt::<Deterministic>(&keyring, &envelope).is_err());
+    }
+
+    #[test]
+    fn authenticates_the_header() {
+        let keyring = Keyring::generate();
+        let envelope = encrypt::<Randomized>(&keyring, "secret");
+
+        // Read as the other mode, the header matches but the associated data doesn't
+        let mut other_mode = envelope.clone();
+        other_mode[1] = Deterministic::MODE;
+        let error = decrypt::<Deterministic>(&keyring, &other_mode).unwrap_err();
+        assert_eq!(error.to_string(), "Decryption failed");
+
+        let mut downgraded = envelope.clone();
+        downgraded[0] = UNAUTHENTICATED_HEADER_VERSION;
+        assert!(decrypt::<Randomized>(&keyring, &downgraded).is_err());
+    }
+
+    #[test]
+    fn reads_envelopes_without_an_authenticated_header() {
+        let keyring = Keyring::generate();
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(keyring.key(1).unwrap())
+            .encrypt(&nonce, b"\"secret\"".as_slice())
+            .unwrap();
+
+        let mut envelope = vec![UNAUTHENTICATED_HEADER_VERSION, Randomized::MODE];
+        envelope.extend_from_slice(&1u32.to_be_bytes());
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&ciphertext);
+
+        let value = decrypt::<Randomized>(&keyring, &envelope).unwrap();
+        assert_eq!(*value, "secret");
+        assert_eq!(encrypt::<Randomized>(&keyring, &value)[0], ENVELOPE_VERSION);
+    }
+
+    #[test]
+    fn deterministic_mode_sorts_map_keys() {
+        let keyring = Keyring::generate();
+        let entries = (0..20).map(|i| (i.to_string(), i)).collect::<Vec<_>>();
+        let forward = entries.iter().cloned().collect::<HashMap<_, _>>();
+        let backward = entries.iter().rev().cloned().collect::<HashMap<_, _>>();
+
+        let encrypt_map = |map: &HashMap<String, i32>| {
+            Encrypted::<_, Deterministic>::new(map.clone())
+                .encrypt_with(&keyring)
+                .unwrap()
+        };
+        assert_eq!(encrypt_map(&forward), encrypt_map(&backward));
+
+        let envelope = encrypt_map(&forward);
+        let decrypted = Encrypted::<HashMap<String, i32>, Deterministic>::decrypt_with(
+            &keyring, &envelope,
+        )
+        .unwrap();
+        assert_eq!(*decrypted, forward);
+    }
+
+    #[test]
+    fn old_keys_keep_decrypting_after_a_new_one_is_added() {
+        let mut keyring = Keyring::generate();
+        let old = encrypt::<Randomized>(&keyring, "secret");
+
+        assert_eq!(keyring.add_active_key(), 2);
+        let value = decrypt::<Randomized>(&keyring, &old).unwrap();
+        assert!(value.needs_rotation(&keyring));
+
+        let new = decrypt::<Randomized>(&keyring, &value.encrypt_with(&keyring).unwrap()).unwrap();
+        assert_eq!(new.key_id(), Some(2));
+        assert!(!new.needs_rotation(&keyring));
+    }
+
+    #[test]
+    fn saves_and_loads_the_keyring_for_the_owner_only() {
+        let path = temp_path("keyring");
+        fs::write(&path, "").unwrap();
+        #[cfg(unix)]
+        fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o644)).unwrap();
+
+        let mut keyring = Keyring::generate();
+        keyring.add_active_key();
+        keyring.save(&path).unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let loaded = Keyring::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.active_key_id(), 2);
+        let envelope = encrypt::<Randomized>(&keyring, "secret");
+        assert_eq!(*decrypt::<Randomized>(&loaded, &envelope).unwrap(), "secret");
+    }
+}
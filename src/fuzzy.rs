@@ -0,0 +1,158 @@
+use diesel::expression::AsExpression;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Float, Text};
+use diesel::{define_sql_function, infix_operator};
+use serde::Serialize;
+
+use crate::schema::{authors, books};
+use crate::soft_delete::SoftDelete;
+
+define_sql_function! {
+    /// How similar two strings are, from 0 to 1, by the trigrams they share.
+    fn similarity(a: Text, b: Text) -> Float;
+}
+
+define_sql_function! {
+    /// How well `a` matches the most similar part of `b`, from 0 to 1. Unlike `similarity`, a
+    /// short query isn't penalized for all the text it doesn't cover, so this suits autocomplete.
+    fn word_similarity(a: Text, b: Text) -> Float;
+}
+
+infix_operator!(TrgmMatches, " % ", Bool, backend: Pg);
+infix_operator!(TrgmDistance, " <-> ", Float, backend: Pg);
+infix_operator!(TrgmWordMatches, " %> ", Bool, backend: Pg);
+infix_operator!(TrgmWordDistance, " <->> ", Float, backend: Pg);
+
+/// `pg_trgm` operators on text columns. The trigram indexes on `books.title` and `authors.name`
+/// serve both the filters and the distance ordering.
+///
+/// ```ignore
+/// books::table
+///     .filter(books::title.fuzzy_matches("Pipi Langstrump"))
+///     .order(books::title.trigram_distance("Pipi Langstrump"))
+/// ```
+pub trait TrigramExpressionMethods: Expression<SqlType = Text> + Sized {
+    /// `self % other`: `similarity` is above `pg_trgm.similarity_threshold` (0.3 by default).
+    fn fuzzy_matches<T: AsExpression<Text>>(self, other: T) -> TrgmMatches<Self, T::Expression> {
+        TrgmMatches::new(self, other.as_expression())
+    }
+
+    /// `self <-> other`: 1 - `similarity`, for ordering by closest match.
+    fn trigram_distance<T: AsExpression<Text>>(
+        self,
+        other: T,
+    ) -> TrgmDistance<Self, T::Expression> {
+        TrgmDistance::new(self, other.as_expression())
+    }
+
+    /// `self %> other`: `word_similarity(other, self)` is above
+    /// `pg_trgm.word_similarity_threshold` (0.6 by default), i.e. `other` is close to some part of
+    /// `self`.
+    fn fuzzy_contains<T: AsExpression<Text>>(
+        self,
+        other: T,
+    ) -> TrgmWordMatches<Self, T::Expression> {
+        TrgmWordMatches::new(self, other.as_expression())
+    }
+
+    /// `self <->> other`: 1 - `word_similarity(other, self)`.
+    fn word_distance<T: AsExpression<Text>>(
+        self,
+        other: T,
+    ) -> TrgmWordDistance<Self, T::Expression> {
+        TrgmWordDistance::new(self, other.as_expression())
+    }
+}
+
+impl<T: Expression<SqlType = Text>> TrigramExpressionMethods for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionKind {
+    Book,
+    Author,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub id: i32,
+    pub text: String,
+    /// `word_similarity` of the query and `text`, from 0 to 1.
+    pub score: f32,
+}
+
+/// The `word_similarity` a suggestion needs. Lower than the `pg_trgm` default of 0.6, which
+/// misses a typo or two in a short query.
+pub const AUTOCOMPLETE_THRESHOLD: f32 = 0.3;
+
+/// Book titles and author names close to what the user typed so far, best match first. Tolerates
+/// typos, e.g. "pipi langs" suggests "Pippi Långstrump" and "lindgrn" suggests "Astrid Lindgren".
+pub fn autocomplete(
+    conn: &mut PgConnection,
+    query: &str,
+    limit: i64,
+) -> QueryResult<Vec<Suggestion>> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    conn.transaction(|conn| autocomplete_in_transaction(conn, query, limit))
+}
+
+fn autocomplete_in_transaction(
+    conn: &mut PgConnection,
+    query: &str,
+    limit: i64,
+) -> QueryResult<Vec<Suggestion>> {
+    // Local to the transaction, `%>` has no way to pass the threshold directly
+    diesel::sql_query("SELECT set_config('pg_trgm.word_similarity_threshold', $1, true)")
+        .bind::<Text, _>(AUTOCOMPLETE_THRESHOLD.to_string())
+        .execute(conn)?;
+
+    let books = books::table
+        .active()
+        .filter(books::title.fuzzy_contains(query))
+        .order((books::title.word_distance(query), books::id))
+        .limit(limit)
+        .select((
+            books::id,
+            books::title,
+            word_similarity(query, books::title),
+        ))
+        .load::<(i32, String, f32)>(conn)?;
+
+    let authors = authors::table
+        .active()
+        .filter(authors::name.fuzzy_contains(query))
+        .order((authors::name.word_distance(query), authors::id))
+        .limit(limit)
+        .select((
+            authors::id,
+            authors::name,
+            word_similarity(query, authors::name),
+        ))
+        .load::<(i32, String, f32)>(conn)?;
+
+    let suggestion = |kind| {
+        move |(id, text, score)| Suggestion {
+            kind,
+            id,
+            text,
+            score,
+        }
+    };
+
+    let mut suggestions = books
+        .into_iter()
+        .map(suggestion(SuggestionKind::Book))
+        .chain(authors.into_iter().map(suggestion(SuggestionKind::Author)))
+        .collect::<Vec<_>>();
+
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions.truncate(limit.max(0) as usize);
+
+    Ok(suggestions)
+}
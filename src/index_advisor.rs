@@ -0,0 +1,256 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use diesel::prelude::*;
+use diesel::sql_types::{Array, Text};
+
+use crate::migrations::{write_migrations, NewMigration};
+
+/// Index problems found by `analyze_indexes`.
+#[derive(Debug, Default)]
+pub struct IndexReport {
+    pub unindexed_foreign_keys: Vec<UnindexedForeignKey>,
+    pub redundant_indexes: Vec<RedundantIndex>,
+    pub unused_indexes: Vec<UnusedIndex>,
+}
+
+impl IndexReport {
+    pub fn is_empty(&self) -> bool {
+        self.unindexed_foreign_keys.is_empty()
+            && self.redundant_indexes.is_empty()
+            && self.unused_indexes.is_empty()
+    }
+}
+
+/// A foreign key without an index starting with its columns, so deleting a referenced row and
+/// loading the rows that reference it (`belonging_to`) scan the whole table.
+#[derive(Debug, Clone, QueryableByName)]
+pub struct UnindexedForeignKey {
+    #[diesel(sql_type = Text)]
+    pub table: String,
+    #[diesel(sql_type = Array<Text>)]
+    pub columns: Vec<String>,
+    #[diesel(sql_type = Text)]
+    pub references: String,
+    #[diesel(sql_type = Text)]
+    pub constraint: String,
+}
+
+/// An index that another index makes unnecessary: one on the same columns, or on more columns
+/// starting with the same ones.
+#[derive(Debug, Clone, QueryableByName)]
+pub struct RedundantIndex {
+    #[diesel(sql_type = Text)]
+    pub table: String,
+    #[diesel(sql_type = Text)]
+    pub index: String,
+    #[diesel(sql_type = Text)]
+    pub covered_by: String,
+    #[diesel(sql_type = Text)]
+    pub definition: String,
+}
+
+/// An index that hasn't been scanned since the statistics were reset, which costs on every write.
+/// Only counts scans on this server, check the replicas before dropping it.
+#[derive(Debug, Clone, QueryableByName)]
+pub struct UnusedIndex {
+    #[diesel(sql_type = Text)]
+    pub table: String,
+    #[diesel(sql_type = Text)]
+    pub index: String,
+    #[diesel(sql_type = Text)]
+    pub size: String,
+}
+
+impl UnindexedForeignKey {
+    /// Named like Postgres names indexes, e.g. `pages_book_id_idx`.
+    pub fn index_name(&self) -> String {
+        let table = self.table.rsplit('.').next().unwrap_or(&self.table);
+        format!("{}_{}_idx", table, self.columns.join("_"))
+    }
+
+    /// Without `IF NOT EXISTS`, which would skip an invalid index left by a failed build.
+    pub fn create_index_sql(&self) -> String {
+        format!(
+            "CREATE INDEX CONCURRENTLY {} ON {} ({});",
+            quote_ident(&self.index_name()),
+            self.table,
+            self.columns
+                .iter()
+                .map(|column| quote_ident(column))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    pub fn drop_index_sql(&self) -> String {
+        let schema = match self.table.rsplit_once('.') {
+            Some((schema, _)) => format!("{}.", schema),
+            None => String::new(),
+        };
+        format!(
+            "DROP INDEX CONCURRENTLY IF EXISTS {}{};",
+            schema,
+            quote_ident(&self.index_name())
+        )
+    }
+}
+
+impl fmt::Display for UnindexedForeignKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.({}) -> {} ({})",
+            self.table,
+            self.columns.join(", "),
+            self.references,
+            self.constraint
+        )
+    }
+}
+
+impl fmt::Display for RedundantIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} on {} is covered by {}: {}",
+            self.index, self.table, self.covered_by, self.definition
+        )
+    }
+}
+
+impl fmt::Display for UnusedIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} on {} ({})", self.index, self.table, self.size)
+    }
+}
+
+// Tables outside the system schemas, without Diesel's migrations table
+const USER_TABLES: &str = "\
+    SELECT c.oid FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+    WHERE n.nspname NOT IN ('pg_catalog', 'information_schema') AND n.nspname NOT LIKE 'pg_toast%' \
+      AND c.relname <> '__diesel_schema_migrations'";
+
+/// Finds foreign keys without an index, indexes covered by another index, and indexes that are
+/// never scanned, in the tables outside the system schemas. Indexes backing a constraint or the
+/// only index of a foreign key are never reported as redundant or unused.
+pub fn analyze_indexes(conn: &mut PgConnection) -> QueryResult<IndexReport> {
+    // An index helps a foreign key if the key's columns are its leading columns, in any order
+    let unindexed_foreign_keys = diesel::sql_query(format!(
+        "SELECT k.conrelid::regclass::text AS \"table\", \
+                array_agg(a.attname::text ORDER BY c.ord) AS columns, \
+                k.confrelid::regclass::text AS \"references\", k.conname::text AS \"constraint\" \
+         FROM pg_constraint k \
+         CROSS JOIN LATERAL unnest(k.conkey) WITH ORDINALITY c (attnum, ord) \
+         JOIN pg_attribute a ON a.attrelid = k.conrelid AND a.attnum = c.attnum \
+         WHERE k.contype = 'f' AND k.conrelid IN ({USER_TABLES}) \
+           AND NOT EXISTS ( \
+               SELECT 1 FROM pg_index i \
+               WHERE i.indrelid = k.conrelid AND i.indisvalid AND i.indpred IS NULL \
+                 AND (i.indkey::int2[])[0:cardinality(k.conkey) - 1] @> k.conkey \
+           ) \
+         GROUP BY k.oid, k.conrelid, k.confrelid, k.conname \
+         ORDER BY 1, 2"
+    ))
+    .load(conn)?;
+
+    // Btree indexes on plain columns whose columns start another btree index with the same
+    // predicate. Of two identical indexes the newer one is reported, unless the other isn't unique
+    let redundant_indexes = diesel::sql_query(format!(
+        "SELECT a.indrelid::regclass::text AS \"table\", a.indexrelid::regclass::text AS index, \
+                b.indexrelid::regclass::text AS covered_by, \
+                pg_get_indexdef(a.indexrelid) AS definition \
+         FROM pg_index a \
+         JOIN pg_index b ON b.indrelid = a.indrelid AND b.indexrelid <> a.indexrelid \
+         JOIN pg_class ac ON ac.oid = a.indexrelid \
+         JOIN pg_class bc ON bc.oid = b.indexrelid \
+         WHERE a.indrelid IN ({USER_TABLES}) \
+           AND ac.relam = (SELECT oid FROM pg_am WHERE amname = 'btree') AND bc.relam = ac.relam \
+           AND a.indexprs IS NULL AND b.indexprs IS NULL AND b.indisvalid \
+           AND pg_get_expr(a.indpred, a.indrelid) IS NOT DISTINCT FROM \
+               pg_get_expr(b.indpred, b.indrelid) \
+           AND a.indnkeyatts <= b.indnkeyatts \
+           AND (b.indkey::int2[])[0:a.indnkeyatts - 1] = (a.indkey::int2[])[0:a.indnkeyatts - 1] \
+           AND (b.indclass::oid[])[0:a.indnkeyatts - 1] = (a.indclass::oid[])[0:a.indnkeyatts - 1] \
+           AND NOT EXISTS (SELECT 1 FROM pg_constraint k WHERE k.conindid = a.indexrelid) \
+           AND (NOT a.indisunique OR (b.indisunique AND a.indnkeyatts = b.indnkeyatts)) \
+           AND (a.indnkeyatts < b.indnkeyatts OR b.indisunique > a.indisunique \
+                OR EXISTS (SELECT 1 FROM pg_constraint k WHERE k.conindid = b.indexrelid) \
+                OR (a.indisunique = b.indisunique AND a.indexrelid > b.indexrelid)) \
+         ORDER BY 1, 2"
+    ))
+    .load::<RedundantIndex>(conn)?;
+
+    let unused_indexes = diesel::sql_query(format!(
+        "SELECT s.relid::regclass::text AS \"table\", s.indexrelid::regclass::text AS index, \
+                pg_size_pretty(pg_relation_size(s.indexrelid)) AS size \
+         FROM pg_stat_user_indexes s \
+         JOIN pg_index i ON i.indexrelid = s.indexrelid \
+         WHERE s.idx_scan = 0 AND s.relid IN ({USER_TABLES}) AND NOT i.indisunique \
+           AND NOT EXISTS (SELECT 1 FROM pg_constraint k WHERE k.conindid = s.indexrelid) \
+           AND NOT EXISTS ( \
+               SELECT 1 FROM pg_constraint k \
+               WHERE k.contype = 'f' AND k.conrelid = i.indrelid \
+                 AND (i.indkey::int2[])[0:cardinality(k.conkey) - 1] @> k.conkey \
+           ) \
+         ORDER BY pg_relation_size(s.indexrelid) DESC, 1, 2"
+    ))
+    .load::<UnusedIndex>(conn)?;
+
+    // An index can be covered by several, report it once
+    let redundant_indexes = dedup_by_index(redundant_indexes);
+    // and only as redundant, not also as unused
+    let unused_indexes = unused_indexes
+        .into_iter()
+        .filter(|unused| !redundant_indexes.iter().any(|r| r.index == unused.index))
+        .collect();
+
+    Ok(IndexReport {
+        unindexed_foreign_keys,
+        redundant_indexes,
+        unused_indexes,
+    })
+}
+
+fn dedup_by_index(mut indexes: Vec<RedundantIndex>) -> Vec<RedundantIndex> {
+    indexes.dedup_by(|a, b| a.index == b.index);
+    indexes
+}
+
+/// Writes a migration creating the missing index of each foreign key into `migrations_dir`, one
+/// per index since `CREATE INDEX CONCURRENTLY` can't run in a transaction. Redundant and unused
+/// indexes are left to decide by hand.
+pub fn write_index_migrations(
+    migrations_dir: impl AsRef<Path>,
+    foreign_keys: &[UnindexedForeignKey],
+) -> io::Result<Vec<PathBuf>> {
+    let migrations = foreign_keys
+        .iter()
+        .map(|fk| NewMigration {
+            name: format!("create_{}", fk.index_name()),
+            up_sql: format!(
+                "-- For the foreign key {}, generated by `index_advisor`\n{}\n",
+                fk,
+                fk.create_index_sql()
+            ),
+            down_sql: format!("{}\n", fk.drop_index_sql()),
+            run_in_transaction: false,
+        })
+        .collect::<Vec<_>>();
+
+    write_migrations(migrations_dir, &migrations)
+}
+
+fn quote_ident(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
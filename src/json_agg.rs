@@ -0,0 +1,448 @@
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Jsonb, Text};
+use serde::de::DeserializeOwned;
+
+use crate::diesel_jsonb::Json;
+
+/// Builds a query that returns every row as a single JSONB object, with its associations nested
+/// inside it using `jsonb_build_object` / `jsonb_agg` subqueries, so a whole tree is loaded in one
+/// round-trip and deserialized with serde.
+///
+/// ```ignore
+/// let authors: Vec<AuthorWithBooks> = JsonQuery::new("authors")
+///     .columns(&["id", "name"])
+///     .has_many_through(
+///         "books",
+///         JsonQuery::new("books").columns(&["id", "title"]),
+///         "books_authors",
+///         "author_id",
+///         "book_id",
+///     )
+///     .has_many("addresses", JsonQuery::new("address").columns(&["id", "value", "author_id"]), "author_id")
+///     .load(conn)?;
+/// ```
+///
+/// Table and column names are identifiers from the code, not user input; they are quoted but
+/// otherwise passed through, and escaped where they become JSON keys. Filter values are sent as bind parameters.
+#[derive(Debug, Clone)]
+pub struct JsonQuery {
+    table: &'static str,
+    primary_key: &'static str,
+    columns: Vec<&'static str>,
+    relations: Vec<(&'static str, Relation)>,
+    filters: Vec<(&'static str, Filter)>,
+    order: Vec<(&'static str, bool)>,
+}
+
+#[derive(Debug, Clone)]
+enum Relation {
+    /// `child.foreign_key = parent.primary_key`, nested as an array.
+    HasMany {
+        child: JsonQuery,
+        foreign_key: &'static str,
+    },
+    /// Through a join table, nested as an array.
+    HasManyThrough {
+        child: JsonQuery,
+        join_table: &'static str,
+        parent_key: &'static str,
+        child_key: &'static str,
+    },
+    /// `child.primary_key = parent.foreign_key`, nested as an object (or null).
+    BelongsTo {
+        child: JsonQuery,
+        foreign_key: &'static str,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Filter {
+    Eq(Bind),
+    IsNull,
+}
+
+#[derive(Debug, Clone)]
+pub enum Bind {
+    Integer(i32),
+    BigInt(i64),
+    Text(String),
+}
+
+impl From<i32> for Bind {
+    fn from(value: i32) -> Self {
+        Bind::Integer(value)
+    }
+}
+
+impl From<i64> for Bind {
+    fn from(value: i64) -> Self {
+        Bind::BigInt(value)
+    }
+}
+
+impl From<&str> for Bind {
+    fn from(value: &str) -> Self {
+        Bind::Text(value.to_string())
+    }
+}
+
+impl From<String> for Bind {
+    fn from(value: String) -> Self {
+        Bind::Text(value)
+    }
+}
+
+#[derive(QueryableByName)]
+struct JsonRow<T: DeserializeOwned + 'static> {
+    #[diesel(sql_type = Jsonb)]
+    json: Json<T>,
+}
+
+impl JsonQuery {
+    pub fn new(table: &'static str) -> Self {
+        JsonQuery {
+            table,
+            primary_key: "id",
+            columns: Vec::new(),
+            relations: Vec::new(),
+            filters: Vec::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Defaults to `id`.
+    pub fn primary_key(mut self, column: &'static str) -> Self {
+        self.primary_key = column;
+        self
+    }
+
+    /// The columns to include, each under its own name.
+    pub fn columns(mut self, columns: &[&'static str]) -> Self {
+        self.columns.extend_from_slice(columns);
+        self
+    }
+
+    pub fn has_many(
+        mut self,
+        name: &'static str,
+        child: JsonQuery,
+        foreign_key: &'static str,
+    ) -> Self {
+        self.relations
+            .push((name, Relation::HasMany { child, foreign_key }));
+        self
+    }
+
+    /// `parent_key` and `child_key` are the columns of `join_table` referencing the parent and
+    /// child tables.
+    pub fn has_many_through(
+        mut self,
+        name: &'static str,
+        child: JsonQuery,
+        join_table: &'static str,
+        parent_key: &'static str,
+        child_key: &'static str,
+    ) -> Self {
+        self.relations.push((
+            name,
+            Relation::HasManyThrough {
+                child,
+                join_table,
+                parent_key,
+                child_key,
+            },
+        ));
+        self
+    }
+
+    pub fn belongs_to(
+        mut self,
+        name: &'static str,
+        child: JsonQuery,
+        foreign_key: &'static str,
+    ) -> Self {
+        self.relations
+            .push((name, Relation::BelongsTo { child, foreign_key }));
+        self
+    }
+
+    pub fn filter_eq(mut self, column: &'static str, value: impl Into<Bind>) -> Self {
+        self.filters.push((column, Filter::Eq(value.into())));
+        self
+    }
+
+    /// E.g. `filter_is_null("deleted_at")` to leave out soft deleted rows.
+    pub fn filter_is_null(mut self, column: &'static str) -> Self {
+        self.filters.push((column, Filter::IsNull));
+        self
+    }
+
+    /// Orders the rows (or the nested array, for a child query). Defaults to the primary key.
+    pub fn order_by(mut self, column: &'static str) -> Self {
+        self.order.push((column, false));
+        self
+    }
+
+    pub fn order_by_desc(mut self, column: &'static str) -> Self {
+        self.order.push((column, true));
+        self
+    }
+
+    pub fn to_sql(&self) -> (String, Vec<Bind>) {
+        let mut builder = Builder::default();
+        let alias = builder.alias();
+
+        let object = builder.object(self, &alias);
+        let filters = builder.filters(self, &alias);
+
+        let sql = format!(
+            "SELECT {} AS json FROM {} {}{} ORDER BY {}",
+            object,
+            quote(self.table),
+            alias,
+            filters.map(|f| format!(" WHERE {}", f)).unwrap_or_default(),
+            order_sql(self, &alias),
+        );
+
+        (sql, builder.binds)
+    }
+
+    pub fn load<T: DeserializeOwned + 'static>(
+        &self,
+        conn: &mut PgConnection,
+    ) -> QueryResult<Vec<T>> {
+        let (sql, binds) = self.to_sql();
+
+        let mut query = diesel::sql_query(sql).into_boxed::<Pg>();
+        for bind in binds {
+            query = match bind {
+                Bind::Integer(v) => query.bind::<Integer, _>(v),
+                Bind::BigInt(v) => query.bind::<BigInt, _>(v),
+                Bind::Text(v) => query.bind::<Text, _>(v),
+            };
+        }
+
+        Ok(query
+            .load::<JsonRow<T>>(conn)?
+            .into_iter()
+            .map(|row| row.json.0)
+            .collect())
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    aliases: usize,
+    binds: Vec<Bind>,
+}
+
+impl Builder {
+    fn alias(&mut self) -> String {
+        self.aliases += 1;
+        format!("t{}", self.aliases)
+    }
+
+    fn bind(&mut self, value: Bind) -> String {
+        self.binds.push(value);
+        format!("${}", self.binds.len())
+    }
+
+    fn object(&mut self, query: &JsonQuery, alias: &str) -> String {
+        let mut fields = query
+            .columns
+            .iter()
+            .map(|column| format!("{}, {}.{}", literal(column), alias, quote(column)))
+            .collect::<Vec<_>>();
+
+        for (name, relation) in &query.relations {
+            let value = self.relation(relation, query, alias);
+            fields.push(format!("{}, {}", literal(name), value));
+        }
+
+        format!("jsonb_build_object({})", fields.join(", "))
+    }
+
+    fn relation(&mut self, relation: &Relation, parent: &JsonQuery, parent_alias: &str) -> String {
+        let child_alias = self.alias();
+
+        let (child, from, condition, many) = match relation {
+            Relation::HasMany { child, foreign_key } => (
+                child,
+                format!("{} {}", quote(child.table), child_alias),
+                format!(
+                    "{}.{} = {}.{}",
+                    child_alias,
+                    quote(foreign_key),
+                    parent_alias,
+                    quote(parent.primary_key)
+                ),
+                true,
+            ),
+            Relation::HasManyThrough {
+                child,
+                join_table,
+                parent_key,
+                child_key,
+            } => {
+                let join_alias = self.alias();
+                (
+                    child,
+                    format!(
+                        "{} {} JOIN {} {} ON {}.{} = {}.{}",
+                        quote(child.table),
+                        child_alias,
+                        quote(join_table),
+                        join_alias,
+                        join_alias,
+                        quote(child_key),
+                        child_alias,
+                        quote(child.primary_key)
+                    ),
+                    format!(
+                        "{}.{} = {}.{}",
+                        join_alias,
+                        quote(parent_key),
+                        parent_alias,
+                        quote(parent.primary_key)
+                    ),
+                    true,
+                )
+            }
+            Relation::BelongsTo { child, foreign_key } => (
+                child,
+                format!("{} {}", quote(child.table), child_alias),
+                format!(
+                    "{}.{} = {}.{}",
+                    child_alias,
+                    quote(child.primary_key),
+                    parent_alias,
+                    quote(foreign_key)
+                ),
+                false,
+            ),
+        };
+
+        let object = self.object(child, &child_alias);
+        let condition = match self.filters(child, &child_alias) {
+            Some(filters) => format!("{} AND {}", condition, filters),
+            None => condition,
+        };
+
+        if many {
+            format!(
+                "COALESCE((SELECT jsonb_agg({} ORDER BY {}) FROM {} WHERE {}), '[]'::jsonb)",
+                object,
+                order_sql(child, &child_alias),
+                from,
+                condition
+            )
+        } else {
+            format!("(SELECT {} FROM {} WHERE {})", object, from, condition)
+        }
+    }
+
+    fn filters(&mut self, query: &JsonQuery, alias: &str) -> Option<String> {
+        if query.filters.is_empty() {
+            return None;
+        }
+
+        let filters = query
+            .filters
+            .iter()
+            .map(|(column, filter)| match filter {
+                Filter::Eq(value) => {
+                    let placeholder = self.bind(value.clone());
+                    format!("{}.{} = {}", alias, quote(column), placeholder)
+                }
+                Filter::IsNull => format!("{}.{} IS NULL", alias, quote(column)),
+            })
+            .collect::<Vec<_>>();
+
+        Some(filters.join(" AND "))
+    }
+}
+
+fn order_sql(query: &JsonQuery, alias: &str) -> String {
+    if query.order.is_empty() {
+        return format!("{}.{}", alias, quote(query.primary_key));
+    }
+
+    query
+        .order
+        .iter()
+        .map(|(column, desc)| {
+            format!(
+                "{}.{}{}",
+                alias,
+                quote(column),
+                if *desc { " DESC" } else { "" }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// A string literal, for the JSON keys.
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nests_relations_in_subqueries() {
+        let (sql, binds) = JsonQuery::new("authors")
+            .columns(&["id", "name"])
+            .has_many_through(
+                "books",
+                JsonQuery::new("books")
+                    .columns(&["id", "title"])
+                    .filter_is_null("deleted_at")
+                    .has_many(
+                        "chapters",
+                        JsonQuery::new("chapters").columns(&["title"]).order_by("number"),
+                        "book_id",
+                    ),
+                "books_authors",
+                "author_id",
+                "book_id",
+            )
+            .filter_eq("id", 1)
+            .to_sql();
+
+        assert_eq!(
+            sql,
+            "SELECT jsonb_build_object('id', t1.\"id\", 'name', t1.\"name\", 'books', \
+             COALESCE((SELECT jsonb_agg(jsonb_build_object('id', t2.\"id\", 'title', t2.\"title\", \
+             'chapters', COALESCE((SELECT jsonb_agg(jsonb_build_object('title', t4.\"title\") \
+             ORDER BY t4.\"number\") FROM \"chapters\" t4 WHERE t4.\"book_id\" = t2.\"id\"), \
+             '[]'::jsonb)) ORDER BY t2.\"id\") FROM \"books\" t2 JOIN \"books_authors\" t3 \
+             ON t3.\"book_id\" = t2.\"id\" WHERE t3.\"author_id\" = t1.\"id\" \
+             AND t2.\"deleted_at\" IS NULL), '[]'::jsonb)) AS json FROM \"authors\" t1 \
+             WHERE t1.\"id\" = $1 ORDER BY t1.\"id\""
+        );
+        assert!(matches!(binds[..], [Bind::Integer(1)]));
+    }
+
+    #[test]
+    fn escapes_names() {
+        let (sql, _) = JsonQuery::new("auth\"ors")
+            .columns(&["it's"])
+            .belongs_to("o'clock", JsonQuery::new("clocks"), "clock_id")
+            .to_sql();
+
+        assert_eq!(
+            sql,
+            "SELECT jsonb_build_object('it''s', t1.\"it's\", 'o''clock', \
+             (SELECT jsonb_build_object() FROM \"clocks\" t2 WHERE t2.\"id\" = t1.\"clock_id\")) \
+             AS json FROM \"auth\"\"ors\" t1 ORDER BY t1.\"id\""
+        );
+    }
+}
@@ -1,20 +1,46 @@
-use std::env;
+use diesel::PgConnection;
 
-use diesel::{Connection, PgConnection};
-use dotenvy::dotenv;
+use config::{connect, connect_with_retry, Backoff, ConnectError, DbConfig};
+
+pub use router::{establish_router, DbRouter};
 
 pub mod models;
 pub mod schema;
 pub mod pagination;
 pub mod debug_query;
 pub mod diesel_jsonb;
+pub mod diesel_binary;
+pub mod repo;
+pub mod nested;
+pub mod preload;
+pub mod json_agg;
+pub mod timestamps;
+pub mod soft_delete;
+pub mod audit;
+pub mod versioned;
+pub mod search;
+pub mod fuzzy;
+pub mod diesel_pg_enum;
+pub mod encrypted;
+pub mod migrations;
+pub mod config;
+pub mod pool;
+pub mod async_pg;
+pub mod router;
+pub mod timeouts;
+pub mod cancel;
+pub mod schema_drift;
+pub mod schema_snapshot;
+pub mod migration_lint;
+pub mod index_advisor;
 
-pub fn establish_connection() -> PgConnection {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+/// Connects once with `DbConfig::load`, so a CLI fails right away when the server is down.
+pub fn establish_connection() -> Result<PgConnection, ConnectError> {
+    connect(&DbConfig::load()?)
 }
 
-
+/// Like `establish_connection`, retrying with `backoff` while the server isn't up yet, e.g. when
+/// starting next to it. `Backoff::default()` retries for about 30 seconds.
+pub fn establish_connection_with_retry(backoff: &Backoff) -> Result<PgConnection, ConnectError> {
+    connect_with_retry(&DbConfig::load()?, backoff)
+}
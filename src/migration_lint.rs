@@ -0,0 +1,873 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Something a migration does that can block or break a running app while it's being deployed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// `ADD COLUMN ... NOT NULL` without a default, which fails if the table has rows.
+    NotNullWithoutDefault,
+    /// `SET NOT NULL`, which scans the table while blocking reads and writes.
+    SetNotNull,
+    /// `CREATE INDEX` without `CONCURRENTLY`, which blocks writes while the index builds.
+    IndexNotConcurrent,
+    /// `ALTER COLUMN ... TYPE`, which usually rewrites the table while blocking reads and writes.
+    ColumnTypeChange,
+    /// A foreign key added without `NOT VALID`, which checks every row while blocking writes.
+    ForeignKeyValidation,
+    /// `ADD COLUMN` with a stored generated value, an identity, a serial type or a volatile
+    /// default, which fills in every row by rewriting the table while blocking reads and writes.
+    TableRewrite,
+    /// `RENAME COLUMN`, which breaks instances still running code that uses the old name.
+    RenameColumn,
+    /// A foreign key without an index on its column, so deletes from the referenced table and
+    /// joins from it scan the referencing table.
+    UnindexedForeignKey,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 8] = [
+        Rule::NotNullWithoutDefault,
+        Rule::SetNotNull,
+        Rule::IndexNotConcurrent,
+        Rule::ColumnTypeChange,
+        Rule::ForeignKeyValidation,
+        Rule::TableRewrite,
+        Rule::RenameColumn,
+        Rule::UnindexedForeignKey,
+    ];
+
+    /// The name to allow the rule with, see `lint_migrations`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::NotNullWithoutDefault => "not-null-without-default",
+            Rule::SetNotNull => "set-not-null",
+            Rule::IndexNotConcurrent => "index-not-concurrent",
+            Rule::ColumnTypeChange => "column-type-change",
+            Rule::ForeignKeyValidation => "foreign-key-validation",
+            Rule::TableRewrite => "table-rewrite",
+            Rule::RenameColumn => "rename-column",
+            Rule::UnindexedForeignKey => "unindexed-foreign-key",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The migration's directory name, e.g. `2024-07-17-174754_create_pages`.
+    pub migration: String,
+    /// Of the statement in `up.sql`.
+    pub line: usize,
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/up.sql:{}: {}: {}",
+            self.migration,
+            self.line,
+            self.rule.name(),
+            self.message
+        )
+    }
+}
+
+/// Checks the `up.sql` of every migration in `migrations_dir`, oldest first, for operations that
+/// take heavy locks or rewrite tables. Operations on a table created in the same migration are
+/// fine, nothing uses it yet. A foreign key only needs an index by the last migration.
+///
+/// A `-- lint: allow <rule>` comment in or right before a statement allows the named rules for it,
+/// e.g. for a table known to be small.
+pub fn lint_migrations(migrations_dir: impl AsRef<Path>) -> io::Result<Vec<Finding>> {
+    let mut migrations = fs::read_dir(migrations_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    migrations.sort();
+
+    let mut linter = Linter::default();
+    for dir in migrations {
+        let up_sql = dir.join("up.sql");
+        if !up_sql.is_file() {
+            continue;
+        }
+        let name = dir.file_name().unwrap().to_string_lossy().into_owned();
+        linter.lint(&name, &fs::read_to_string(up_sql)?);
+    }
+
+    Ok(linter.finish())
+}
+
+/// `lint_migrations` for a single migration's SQL, without the schema of earlier migrations.
+pub fn lint_sql(migration: &str, sql: &str) -> Vec<Finding> {
+    let mut linter = Linter::default();
+    linter.lint(migration, sql);
+    linter.finish()
+}
+
+#[derive(Default)]
+struct Linter {
+    /// The leading columns of the indexes on each table.
+    indexed: HashMap<String, HashSet<String>>,
+    foreign_keys: Vec<ForeignKey>,
+    findings: Vec<Finding>,
+}
+
+struct ForeignKey {
+    migration: String,
+    line: usize,
+    allowed: Vec<String>,
+    table: String,
+    column: String,
+    references: String,
+}
+
+/// The statement being linted.
+struct Context<'a> {
+    migration: &'a str,
+    statement: &'a Statement,
+    table: String,
+    /// Created in this migration.
+    new_table: bool,
+}
+
+impl Linter {
+    fn lint(&mut self, migration: &str, sql: &str) {
+        let mut new_tables = HashSet::new();
+
+        for statement in split_statements(sql) {
+            let mut cursor = Cursor::new(&statement.tokens);
+
+            if cursor.eat("create") {
+                cursor.eat("temp");
+                cursor.eat("temporary");
+                cursor.eat("unlogged");
+
+                if cursor.eat("table") {
+                    cursor.eat_all(&["if", "not", "exists"]);
+                    let (Some(table), Some(elements)) = (cursor.qualified_name(), cursor.group())
+                    else {
+                        continue;
+                    };
+
+                    new_tables.insert(table.clone());
+                    let context = Context {
+                        migration,
+                        statement: &statement,
+                        table,
+                        new_table: true,
+                    };
+                    for element in split_top_level(elements) {
+                        self.table_element(&context, element, false);
+                    }
+                } else {
+                    cursor.eat("unique");
+                    if cursor.eat("index") {
+                        self.create_index(migration, &statement, cursor, &new_tables);
+                    }
+                }
+            } else if cursor.eat_all(&["alter", "table"]) {
+                cursor.eat_all(&["if", "exists"]);
+                cursor.eat("only");
+                let Some(table) = cursor.qualified_name() else {
+                    continue;
+                };
+
+                let context = Context {
+                    migration,
+                    statement: &statement,
+                    new_table: new_tables.contains(&table),
+                    table,
+                };
+                for action in split_top_level(cursor.rest()) {
+                    self.alter_table_action(&context, action);
+                }
+            }
+        }
+    }
+
+    fn create_index(
+        &mut self,
+        migration: &str,
+        statement: &Statement,
+        mut cursor: Cursor,
+        new_tables: &HashSet<String>,
+    ) {
+        let concurrently = cursor.eat("concurrently");
+        cursor.eat_all(&["if", "not", "exists"]);
+        let name = if cursor.peek_is("on") {
+            None
+        } else {
+            cursor.name()
+        };
+        if !cursor.eat("on") {
+            return;
+        }
+        cursor.eat("only");
+        let Some(table) = cursor.qualified_name() else {
+            return;
+        };
+        if cursor.eat("using") {
+            cursor.name();
+        }
+
+        if let Some(column) = cursor
+            .group()
+            .and_then(|columns| leading_column(split_top_level(columns).first()?))
+        {
+            self.add_index(&table, column);
+        }
+
+        if !concurrently && !new_tables.contains(&table) {
+            self.report(
+                migration,
+                statement,
+                Rule::IndexNotConcurrent,
+                format!(
+                    "CREATE INDEX {}blocks writes to {} while it builds, use CREATE INDEX \
+                     CONCURRENTLY in a migration with `run_in_transaction = false` in its \
+                     metadata.toml",
+                    name.map(|name| format!("{} ", name)).unwrap_or_default(),
+                    table
+                ),
+            );
+        }
+    }
+
+    fn alter_table_action(&mut self, context: &Context, action: &[Token]) {
+        let mut cursor = Cursor::new(action);
+
+        if cursor.eat("add") {
+            if cursor.peek_is("constraint")
+                || cursor.peek_is("primary")
+                || cursor.peek_is("unique")
+                || cursor.peek_is("foreign")
+                || cursor.peek_is("check")
+                || cursor.peek_is("exclude")
+            {
+                self.table_element(context, cursor.rest(), true);
+            } else {
+                cursor.eat("column");
+                cursor.eat_all(&["if", "not", "exists"]);
+                self.add_column(context, cursor.rest());
+            }
+        } else if cursor.eat("alter") {
+            cursor.eat("column");
+            let Some(column) = cursor.name() else {
+                return;
+            };
+
+            if cursor.eat("type") || cursor.eat_all(&["set", "data", "type"]) {
+                if !context.new_table {
+                    self.report_in(
+                        context,
+                        Rule::ColumnTypeChange,
+                        format!(
+                            "changing the type of {}.{} usually rewrites the table while blocking \
+                             reads and writes, add a new column and backfill it instead",
+                            context.table, column
+                        ),
+                    );
+                }
+            } else if cursor.eat_all(&["set", "not", "null"]) && !context.new_table {
+                self.report_in(
+                    context,
+                    Rule::SetNotNull,
+                    format!(
+                        "SET NOT NULL on {}.{} scans the table while blocking reads and writes, \
+                         add CHECK ({} IS NOT NULL) NOT VALID and VALIDATE it first",
+                        context.table, column, column
+                    ),
+                );
+            }
+        } else if cursor.eat("rename") {
+            cursor.eat("column");
+            if cursor.peek_is("to") || cursor.peek_is("constraint") {
+                return;
+            }
+            let (Some(from), true, Some(to)) = (cursor.name(), cursor.eat("to"), cursor.name())
+            else {
+                return;
+            };
+
+            if !context.new_table {
+                self.report_in(
+                    context,
+                    Rule::RenameColumn,
+                    format!(
+                        "renaming {}.{} to {} breaks instances still running the old code, add \
+                         the new column, write both and drop the old one in a later deploy",
+                        context.table, from, to
+                    ),
+                );
+            }
+        }
+    }
+
+    fn add_column(&mut self, context: &Context, definition: &[Token]) {
+        let Some(column) = definition.first().and_then(Token::name) else {
+            return;
+        };
+
+        if !context.new_table
+            && has_words(definition, &["not", "null"])
+            && !has_words(definition, &["default"])
+            && !has_words(definition, &["generated"])
+        {
+            self.report_in(
+                context,
+                Rule::NotNullWithoutDefault,
+                format!(
+                    "adding {}.{} NOT NULL without a default fails if {} has rows, add a default \
+                     or add it nullable and backfill it",
+                    context.table, column, context.table
+                ),
+            );
+        }
+
+        if !context.new_table {
+            if let Some(reason) = rewrite_reason(definition) {
+                self.report_in(
+                    context,
+                    Rule::TableRewrite,
+                    format!(
+                        "adding {}.{} {} rewrites {} while blocking reads and writes, add it \
+                         without that and fill it in batches",
+                        context.table, column, reason, context.table
+                    ),
+                );
+            }
+        }
+
+        self.column_constraints(context, column, definition, true);
+    }
+
+    /// A column or table constraint of a `CREATE TABLE`, or a constraint added by `ALTER TABLE`.
+    fn table_element(&mut self, context: &Context, element: &[Token], in_alter_table: bool) {
+        let mut cursor = Cursor::new(element);
+
+        if cursor.eat("constraint") {
+            cursor.name();
+        }
+
+        if cursor.eat_all(&["primary", "key"]) || cursor.eat("unique") {
+            if let Some(column) = cursor.group().and_then(|columns| columns.first()?.name()) {
+                self.add_index(&context.table, column);
+            }
+
+            if in_alter_table && !context.new_table && !has_words(element, &["using", "index"]) {
+                self.report_in(
+                    context,
+                    Rule::IndexNotConcurrent,
+                    format!(
+                        "adding a primary key or unique constraint builds its index while \
+                         blocking writes to {}, create a unique index CONCURRENTLY and add the \
+                         constraint USING INDEX",
+                        context.table
+                    ),
+                );
+            }
+        } else if cursor.eat_all(&["foreign", "key"]) {
+            let columns = cursor.group().unwrap_or_default();
+            let (Some(column), true, Some(references)) = (
+                columns.first().and_then(Token::name),
+                cursor.eat("references"),
+                cursor.qualified_name(),
+            ) else {
+                return;
+            };
+
+            self.add_foreign_key(context, column, &references, element, in_alter_table);
+        } else if !in_alter_table && !is_keyword_element(element) {
+            if let Some(column) = element.first().and_then(Token::name) {
+                self.column_constraints(context, column, element, false);
+            }
+        }
+    }
+
+    /// `PRIMARY KEY`, `UNIQUE` and `REFERENCES` in a column definition.
+    fn column_constraints(
+        &mut self,
+        context: &Context,
+        column: &str,
+        definition: &[Token],
+        in_alter_table: bool,
+    ) {
+        if has_words(definition, &["primary", "key"]) || has_words(definition, &["unique"]) {
+            self.add_index(&context.table, column);
+        }
+
+        let mut cursor = Cursor::new(definition);
+        while !cursor.is_done() {
+            if cursor.eat("references") {
+                if let Some(references) = cursor.qualified_name() {
+                    self.add_foreign_key(context, column, &references, definition, in_alter_table);
+                }
+                return;
+            }
+            cursor.skip();
+        }
+    }
+
+    fn add_foreign_key(
+        &mut self,
+        context: &Context,
+        column: &str,
+        references: &str,
+        definition: &[Token],
+        in_alter_table: bool,
+    ) {
+        self.foreign_keys.push(ForeignKey {
+            migration: context.migration.to_string(),
+            line: context.statement.line,
+            allowed: context.statement.allowed.clone(),
+            table: context.table.clone(),
+            column: column.to_string(),
+            references: references.to_string(),
+        });
+
+        if in_alter_table && !context.new_table && !has_words(definition, &["not", "valid"]) {
+            self.report_in(
+                context,
+                Rule::ForeignKeyValidation,
+                format!(
+                    "adding the foreign key on {}.{} checks every row while blocking writes to {} \
+                     and {}, add it NOT VALID and VALIDATE CONSTRAINT it in a later migration",
+                    context.table, column, context.table, references
+                ),
+            );
+        }
+    }
+
+    fn add_index(&mut self, table: &str, column: &str) {
+        self.indexed
+            .entry(table.to_string())
+            .or_default()
+            .insert(column.to_string());
+    }
+
+    fn report_in(&mut self, context: &Context, rule: Rule, message: String) {
+        self.report(context.migration, context.statement, rule, message);
+    }
+
+    fn report(&mut self, migration: &str, statement: &Statement, rule: Rule, message: String) {
+        if !statement.allows(rule) {
+            self.findings.push(Finding {
+                migration: migration.to_string(),
+                line: statement.line,
+                rule,
+                message,
+            });
+        }
+    }
+
+    fn finish(mut self) -> Vec<Finding> {
+        for fk in &self.foreign_keys {
+            let indexed = self
+                .indexed
+                .get(&fk.table)
+                .is_some_and(|columns| columns.contains(&fk.column));
+            let allowed = fk
+                .allowed
+                .iter()
+                .any(|name| name == Rule::UnindexedForeignKey.name());
+
+            if !indexed && !allowed {
+                self.findings.push(Finding {
+                    migration: fk.migration.clone(),
+                    line: fk.line,
+                    rule: Rule::UnindexedForeignKey,
+                    message: format!(
+                        "{}.{} references {} without an index on {}, so deletes from {} and \
+                         lookups by {} scan {}",
+                        fk.table,
+                        fk.column,
+                        fk.references,
+                        fk.column,
+                        fk.references,
+                        fk.column,
+                        fk.table
+                    ),
+                });
+            }
+        }
+
+        self.findings
+            .sort_by(|a, b| (&a.migration, a.line).cmp(&(&b.migration, b.line)));
+        self.findings
+    }
+}
+
+/// A table constraint in a `CREATE TABLE`, rather than a column.
+fn is_keyword_element(element: &[Token]) -> bool {
+    [
+        "constraint",
+        "primary",
+        "unique",
+        "foreign",
+        "check",
+        "exclude",
+        "like",
+    ]
+    .iter()
+    .any(|keyword| element.first().is_some_and(|token| token.is(keyword)))
+}
+
+/// Functions that return something else for every row, so a default calling them can't be stored
+/// once for the whole table like a constant default is.
+const VOLATILE_FUNCTIONS: [&str; 10] = [
+    "random",
+    "gen_random_uuid",
+    "uuid_generate_v1",
+    "uuid_generate_v1mc",
+    "uuid_generate_v4",
+    "clock_timestamp",
+    "timeofday",
+    "nextval",
+    "txid_current",
+    "pg_current_xact_id",
+];
+
+/// Why adding the column `definition` rewrites the table, if it does.
+fn rewrite_reason(definition: &[Token]) -> Option<String> {
+    if has_words(definition, &["generated"]) && has_words(definition, &["stored"]) {
+        return Some("as a stored generated column".to_string());
+    }
+    if has_words(definition, &["generated"]) && has_words(definition, &["as", "identity"]) {
+        return Some("as an identity column".to_string());
+    }
+    if let Some(Token::Word(column_type)) = definition.get(1) {
+        if matches!(
+            column_type.as_str(),
+            "serial" | "serial2" | "serial4" | "serial8" | "smallserial" | "bigserial"
+        ) {
+            return Some(format!("as {}", column_type));
+        }
+    }
+
+    let default = definition.iter().position(|token| token.is("default"))?;
+    definition[default + 1..]
+        .windows(2)
+        .find_map(|pair| match pair {
+            [Token::Word(function), Token::Punct('(')]
+                if VOLATILE_FUNCTIONS.contains(&function.as_str()) =>
+            {
+                Some(format!("with a default calling {}()", function))
+            }
+            _ => None,
+        })
+}
+
+/// The column an index element is on, if it's a plain column rather than an expression.
+fn leading_column(element: &[Token]) -> Option<&str> {
+    match element {
+        [first, rest @ ..] if !matches!(rest.first(), Some(Token::Punct('(' | '.'))) => {
+            first.name()
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A keyword or unquoted identifier, lowercased like Postgres does.
+    Word(String),
+    /// A `"quoted"` identifier.
+    Quoted(String),
+    /// A string, `$$` body or number.
+    Literal,
+    Punct(char),
+}
+
+impl Token {
+    fn is(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word == keyword)
+    }
+
+    fn name(&self) -> Option<&str> {
+        match self {
+            Token::Word(name) | Token::Quoted(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+struct Statement {
+    /// Where the statement starts in the file.
+    line: usize,
+    tokens: Vec<Token>,
+    /// Rule names from `-- lint: allow` comments.
+    allowed: Vec<String>,
+}
+
+impl Statement {
+    fn allows(&self, rule: Rule) -> bool {
+        self.allowed.iter().any(|name| name == rule.name())
+    }
+}
+
+/// Splits `sql` into statements, skipping comments, strings and `$$` bodies.
+fn split_statements(sql: &str) -> Vec<Statement> {
+    let chars = sql.chars().collect::<Vec<_>>();
+    let mut statements = Vec::new();
+    let mut current = Statement {
+        line: 1,
+        tokens: Vec::new(),
+        allowed: Vec::new(),
+    };
+    let mut line = 1;
+    let mut i = 0;
+
+    let until = |from: usize, end: &[char]| -> usize {
+        (from..chars.len())
+            .find(|&j| chars[j..].starts_with(end))
+            .unwrap_or(chars.len())
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let start = i;
+
+        let token = match c {
+            '-' if next == Some('-') => {
+                i = until(i, &['\n']);
+                let comment = chars[start..i].iter().collect::<String>();
+                if let Some(rules) = comment
+                    .trim_start_matches('-')
+                    .trim()
+                    .strip_prefix("lint: allow")
+                {
+                    current.allowed.extend(
+                        rules
+                            .split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|rule| !rule.is_empty())
+                            .map(str::to_string),
+                    );
+                }
+                None
+            }
+            '/' if next == Some('*') => {
+                i = (until(i + 2, &['*', '/']) + 2).min(chars.len());
+                None
+            }
+            '\'' => {
+                // `''` inside a string reads as two strings, which is fine here
+                i = (until(i + 1, &['\'']) + 1).min(chars.len());
+                Some(Token::Literal)
+            }
+            '"' => {
+                i = until(i + 1, &['"']);
+                let name = chars[start + 1..i].iter().collect();
+                i = (i + 1).min(chars.len());
+                Some(Token::Quoted(name))
+            }
+            '$' if dollar_tag(&chars[i..]).is_some() => {
+                let tag = dollar_tag(&chars[i..]).unwrap();
+                i = (until(i + tag.len(), &tag) + tag.len()).min(chars.len());
+                Some(Token::Literal)
+            }
+            ';' => {
+                i += 1;
+                if !current.tokens.is_empty() {
+                    statements.push(current);
+                }
+                current = Statement {
+                    line,
+                    tokens: Vec::new(),
+                    allowed: Vec::new(),
+                };
+                None
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || "_$".contains(chars[i])) {
+                    i += 1;
+                }
+                let word = chars[start..i].iter().collect::<String>();
+                Some(if c.is_ascii_digit() {
+                    Token::Literal
+                } else {
+                    Token::Word(word.to_lowercase())
+                })
+            }
+            c if c.is_whitespace() => {
+                i += 1;
+                None
+            }
+            c => {
+                i += 1;
+                Some(Token::Punct(c))
+            }
+        };
+
+        if let Some(token) = token {
+            if current.tokens.is_empty() {
+                current.line = line;
+            }
+            current.tokens.push(token);
+        }
+        line += chars[start..i].iter().filter(|&&c| c == '\n').count();
+    }
+
+    if !current.tokens.is_empty() {
+        statements.push(current);
+    }
+    statements
+}
+
+/// The `$tag$` opening a dollar-quoted string at the start of `chars`, not a `$1` parameter.
+fn dollar_tag(chars: &[char]) -> Option<Vec<char>> {
+    let end = chars
+        .iter()
+        .skip(1)
+        .position(|&c| !(c.is_alphanumeric() || c == '_'))?
+        + 1;
+
+    (chars[end] == '$' && !chars.get(1).is_some_and(char::is_ascii_digit))
+        .then(|| chars[..=end].to_vec())
+}
+
+/// Splits a list at the commas outside parentheses.
+fn split_top_level(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') => depth -= 1,
+            Token::Punct(',') if depth == 0 => {
+                parts.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < tokens.len() {
+        parts.push(&tokens[start..]);
+    }
+    parts
+}
+
+/// Whether `words` appear in a row outside parentheses, e.g. not in a `CHECK (...)`.
+fn has_words(tokens: &[Token], words: &[&str]) -> bool {
+    let mut depth = 0;
+
+    (0..tokens.len()).any(|i| {
+        match tokens[i] {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+            && tokens.len() - i >= words.len()
+            && words
+                .iter()
+                .zip(&tokens[i..])
+                .all(|(word, token)| token.is(word))
+    })
+}
+
+struct Cursor<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Cursor { tokens, pos: 0 }
+    }
+
+    fn peek_is(&self, keyword: &str) -> bool {
+        self.tokens
+            .get(self.pos)
+            .is_some_and(|token| token.is(keyword))
+    }
+
+    fn eat(&mut self, keyword: &str) -> bool {
+        let matched = self.peek_is(keyword);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    /// Eats all of `keywords` in a row, or nothing.
+    fn eat_all(&mut self, keywords: &[&str]) -> bool {
+        let matched = keywords.len() <= self.tokens.len() - self.pos
+            && keywords
+                .iter()
+                .zip(&self.tokens[self.pos..])
+                .all(|(keyword, token)| token.is(keyword));
+        if matched {
+            self.pos += keywords.len();
+        }
+        matched
+    }
+
+    fn name(&mut self) -> Option<&'a str> {
+        let name = self.tokens.get(self.pos)?.name()?;
+        self.pos += 1;
+        Some(name)
+    }
+
+    /// A table name, without the `public.` schema.
+    fn qualified_name(&mut self) -> Option<String> {
+        let mut name = self.name()?.to_string();
+        while self.tokens.get(self.pos) == Some(&Token::Punct('.')) {
+            self.pos += 1;
+            let part = self.name()?;
+            name = if name == "public" {
+                part.to_string()
+            } else {
+                format!("{}.{}", name, part)
+            };
+        }
+        Some(name)
+    }
+
+    /// The tokens inside the parentheses at the cursor.
+    fn group(&mut self) -> Option<&'a [Token]> {
+        if self.tokens.get(self.pos) != Some(&Token::Punct('(')) {
+            return None;
+        }
+
+        let mut depth = 0;
+        for i in self.pos..self.tokens.len() {
+            match self.tokens[i] {
+                Token::Punct('(') => depth += 1,
+                Token::Punct(')') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let group = &self.tokens[self.pos + 1..i];
+                        self.pos = i + 1;
+                        return Some(group);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn skip(&mut self) {
+        self.pos += 1;
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn rest(&self) -> &'a [Token] {
+        &self.tokens[self.pos.min(self.tokens.len())..]
+    }
+}
@@ -0,0 +1,317 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use diesel::migration::{MigrationSource, MigrationVersion};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+use crate::schema_snapshot::SchemaSnapshot;
+
+/// The migrations in `migrations/`, compiled into the binary so it can migrate the database it's
+/// deployed against without the Diesel CLI.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+pub type MigrationResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// The `pg_advisory_lock` key held while migrating, "rust-pg" in ASCII.
+const MIGRATION_LOCK_KEY: i64 = 0x72_75_73_74_2d_70_67;
+
+/// How long to wait between attempts to take the migration lock.
+const MIGRATION_LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs the migrations that haven't been applied yet, oldest first, and returns their versions.
+/// Safe to call from several instances starting at once, see `with_migration_lock`.
+pub fn run_pending_migrations(
+    conn: &mut PgConnection,
+) -> MigrationResult<Vec<MigrationVersion<'static>>> {
+    with_migration_lock(conn, |conn| {
+        let versions = conn.run_pending_migrations(MIGRATIONS)?;
+        Ok(versions.into_iter().map(|v| v.as_owned()).collect())
+    })
+}
+
+/// Reverts the most recently applied migration and returns its version.
+pub fn revert_last_migration(
+    conn: &mut PgConnection,
+) -> MigrationResult<MigrationVersion<'static>> {
+    with_migration_lock(conn, |conn| {
+        Ok(conn.revert_last_migration(MIGRATIONS)?.as_owned())
+    })
+}
+
+/// Reverts the most recently applied migration and runs it again, to try changes to it.
+pub fn redo_last_migration(conn: &mut PgConnection) -> MigrationResult<MigrationVersion<'static>> {
+    with_migration_lock(conn, |conn| {
+        conn.revert_last_migration(MIGRATIONS)?;
+        Ok(conn.run_next_migration(MIGRATIONS)?.as_owned())
+    })
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: MigrationVersion<'static>,
+    /// The directory name, `None` for a migration that was applied but isn't in `migrations/`,
+    /// e.g. one from a newer build.
+    pub name: Option<String>,
+    pub applied: bool,
+}
+
+/// Every known migration, oldest first.
+pub fn migration_status(conn: &mut PgConnection) -> MigrationResult<Vec<MigrationStatus>> {
+    let applied = conn.applied_migrations()?;
+
+    let mut statuses = MigrationSource::<Pg>::migrations(&MIGRATIONS)?
+        .iter()
+        .map(|migration| {
+            let version = migration.name().version().as_owned();
+            MigrationStatus {
+                applied: applied.contains(&version),
+                name: Some(migration.name().to_string()),
+                version,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for version in applied {
+        if !statuses.iter().any(|status| status.version == version) {
+            statuses.push(MigrationStatus {
+                version,
+                name: None,
+                applied: true,
+            });
+        }
+    }
+
+    statuses.sort_by(|a, b| a.version.cmp(&b.version));
+    Ok(statuses)
+}
+
+/// A migration whose `down.sql` doesn't leave the schema as it was before its `up.sql`.
+#[derive(Debug)]
+pub struct IrreversibleMigration {
+    pub version: MigrationVersion<'static>,
+    /// From the schema before `up.sql` to the schema after `down.sql`, see `SchemaSnapshot::diff`.
+    pub diff: Vec<String>,
+}
+
+impl fmt::Display for IrreversibleMigration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "down.sql of {} doesn't revert its up.sql (before up.sql -, after down.sql +):",
+            self.version
+        )?;
+        write!(f, "{}", self.diff.join("\n"))
+    }
+}
+
+impl Error for IrreversibleMigration {}
+
+/// Runs the pending migrations one at a time, reverting and re-running each to check that its
+/// `down.sql` undoes its `up.sql`. Only for a scratch database: it ends up fully migrated, or
+/// stops at the first migration that fails, with an `IrreversibleMigration` if it reverted to a
+/// different schema. Extensions may stay after `down.sql`: `up.sql` creates them `IF NOT EXISTS`,
+/// so it can't tell whether dropping them is safe.
+pub fn verify_reversible(conn: &mut PgConnection) -> MigrationResult<()> {
+    with_migration_lock(conn, |conn| {
+        while conn.has_pending_migration(MIGRATIONS)? {
+            let before = SchemaSnapshot::take(conn)?;
+            let version = conn.run_next_migration(MIGRATIONS)?.as_owned();
+
+            conn.revert_last_migration(MIGRATIONS)
+                .map_err(|e| format!("Reverting {} failed: {}", version, e))?;
+
+            let diff = before
+                .diff(&SchemaSnapshot::take(conn)?)
+                .into_iter()
+                .filter(|line| !line.starts_with("+ extension "))
+                .collect::<Vec<_>>();
+            if !diff.is_empty() {
+                return Err(IrreversibleMigration { version, diff }.into());
+            }
+
+            conn.run_next_migration(MIGRATIONS)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Runs `f` holding a session-level advisory lock, so instances starting at the same time migrate
+/// one after another instead of racing. The others then find nothing left to run. A transaction
+/// level lock won't do, the migrations run in transactions of their own, and `conn` must not be in
+/// one either, see `MigrationLock::acquire`. If both `f` and unlocking fail, `f`'s error is
+/// returned.
+pub fn with_migration_lock<T>(
+    conn: &mut PgConnection,
+    f: impl FnOnce(&mut PgConnection) -> MigrationResult<T>,
+) -> MigrationResult<T> {
+    let mut lock = MigrationLock::acquire(conn)?;
+    let result = f(&mut lock);
+    let unlocked = lock.release();
+
+    let value = result?;
+    unlocked?;
+    Ok(value)
+}
+
+/// Unlocks when dropped, so a panic in `with_migration_lock` doesn't leave a pooled connection
+/// holding the lock.
+struct MigrationLock<'c> {
+    conn: &'c mut PgConnection,
+    locked: bool,
+}
+
+impl<'c> MigrationLock<'c> {
+    /// Polls with `pg_try_advisory_lock` rather than blocking in `pg_advisory_lock`: a session
+    /// blocked in a statement keeps its snapshot, which a `CREATE INDEX CONCURRENTLY` of the lock
+    /// holder waits for, and Postgres fails one of them as a deadlock. Between attempts this
+    /// session is idle outside a transaction, which the index build doesn't wait for.
+    fn acquire(conn: &'c mut PgConnection) -> QueryResult<Self> {
+        let mut waiting = false;
+
+        loop {
+            let locked = diesel::select(
+                diesel::dsl::sql::<Bool>("pg_try_advisory_lock(")
+                    .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+                    .sql(")"),
+            )
+            .get_result::<bool>(conn)?;
+
+            if locked {
+                return Ok(MigrationLock { conn, locked: true });
+            }
+            if !waiting {
+                log::info!("Waiting for another instance to finish migrating");
+                waiting = true;
+            }
+            thread::sleep(MIGRATION_LOCK_POLL_INTERVAL);
+        }
+    }
+
+    fn release(mut self) -> QueryResult<()> {
+        self.locked = false;
+        unlock(self.conn)
+    }
+}
+
+impl Deref for MigrationLock<'_> {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        self.conn
+    }
+}
+
+impl DerefMut for MigrationLock<'_> {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        self.conn
+    }
+}
+
+impl Drop for MigrationLock<'_> {
+    fn drop(&mut self) {
+        if self.locked {
+            // Only reached while unwinding, there's no one to return the error to
+            let _ = unlock(self.conn);
+        }
+    }
+}
+
+fn unlock(conn: &mut PgConnection) -> QueryResult<()> {
+    diesel::sql_query("SELECT pg_advisory_unlock($1)")
+        .bind::<BigInt, _>(MIGRATION_LOCK_KEY)
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Writes a new Diesel migration (`<timestamp>_<name>/{up,down}.sql`) into `migrations_dir`,
+/// using the same directory naming as `diesel migration generate`.
+pub fn write_migration(
+    migrations_dir: impl AsRef<Path>,
+    name: &str,
+    up_sql: &str,
+    down_sql: &str,
+) -> io::Result<PathBuf> {
+    let migration = NewMigration {
+        name: name.to_string(),
+        up_sql: up_sql.to_string(),
+        down_sql: down_sql.to_string(),
+        run_in_transaction: true,
+    };
+
+    Ok(write_migrations(migrations_dir, &[migration])?.remove(0))
+}
+
+/// A migration for `write_migrations`.
+pub struct NewMigration {
+    pub name: String,
+    pub up_sql: String,
+    pub down_sql: String,
+    /// `false` writes a `metadata.toml` that runs it outside a transaction, which e.g.
+    /// `CREATE INDEX CONCURRENTLY` needs. Such a migration should only have one statement, since
+    /// Postgres runs several statements sent at once in a transaction too.
+    pub run_in_transaction: bool,
+}
+
+/// `write_migration` for several migrations, a second apart so they get distinct versions and run
+/// in the given order, after the migrations already in `migrations_dir`.
+pub fn write_migrations(
+    migrations_dir: impl AsRef<Path>,
+    migrations: &[NewMigration],
+) -> io::Result<Vec<PathBuf>> {
+    let now = chrono::Utc::now().naive_utc();
+    let start = match latest_version(migrations_dir.as_ref())? {
+        Some(latest) if latest >= now => latest + chrono::Duration::seconds(1),
+        _ => now,
+    };
+
+    migrations
+        .iter()
+        .zip(0..)
+        .map(|(migration, i)| {
+            let version = (start + chrono::Duration::seconds(i)).format(VERSION_FORMAT);
+            let dir = migrations_dir
+                .as_ref()
+                .join(format!("{version}_{}", migration.name));
+
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("up.sql"), &migration.up_sql)?;
+            fs::write(dir.join("down.sql"), &migration.down_sql)?;
+            if !migration.run_in_transaction {
+                fs::write(dir.join("metadata.toml"), "run_in_transaction = false\n")?;
+            }
+
+            Ok(dir)
+        })
+        .collect()
+}
+
+/// Of the directory names, like `diesel migration generate` writes them.
+const VERSION_FORMAT: &str = "%Y-%m-%d-%H%M%S";
+
+fn latest_version(migrations_dir: &Path) -> io::Result<Option<chrono::NaiveDateTime>> {
+    if !migrations_dir.exists() {
+        return Ok(None);
+    }
+
+    let mut latest = None;
+    for entry in fs::read_dir(migrations_dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        let version = name.split('_').next().unwrap_or_default();
+
+        if let Ok(version) = chrono::NaiveDateTime::parse_from_str(version, VERSION_FORMAT) {
+            latest = latest.max(Some(version));
+        }
+    }
+
+    Ok(latest)
+}
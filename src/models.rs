@@ -1,18 +1,28 @@
+use chrono::{DateTime, Utc};
 use diesel::{AsExpression, FromSqlRow};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
-use crate::diesel_jsonb;
-use crate::schema::{address, authors, books_authors, items, posts, reports};
+use crate::timestamps::Timestamps;
+use crate::{diesel_binary, diesel_jsonb, diesel_pg_enum, preload};
+use crate::schema::{address, authors, books_authors, invites, items, post_revisions, posts, reports};
 use crate::schema::{books, pages};
 
-#[derive(Debug, Queryable, Selectable, Identifiable, AsChangeset)]
+#[derive(Debug, Queryable, Selectable, Identifiable, Serialize)]
 #[diesel(table_name = crate::schema::posts)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Post {
     pub id: i32,
     pub title: String,
     pub body: String,
-    pub published: bool,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub timestamps: Timestamps<posts::table>,
+    pub version: i32,
+    pub status: PostStatus,
+    /// When a scheduled post gets published.
+    pub publish_at: Option<DateTime<Utc>>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub slug: String,
 }
 
 #[derive(Insertable)]
@@ -20,16 +30,83 @@ pub struct Post {
 pub struct NewPost<'a> {
     pub title: &'a str,
     pub body: &'a str,
+    pub slug: &'a str,
 }
 
-#[derive(Queryable, Identifiable, Selectable, Debug, Hash, Eq, PartialEq, Clone)]
+/// Apply with `Versioned::update_with_version`, so concurrent edits don't overwrite each other.
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = posts)]
+pub struct PostChanges<'a> {
+    pub title: Option<&'a str>,
+    pub body: Option<&'a str>,
+}
+
+/// Moves between states with `PostRepository::publish`, `schedule`, `unpublish` and `archive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::PostStatus)]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    Draft,
+    Scheduled,
+    Published,
+    Archived,
+}
+diesel_pg_enum!(PostStatus, crate::schema::sql_types::PostStatus, "post_status", {
+    Draft => "draft",
+    Scheduled => "scheduled",
+    Published => "published",
+    Archived => "archived",
+});
+
+/// The title and body of a post after each edit, starting at revision 1.
+#[derive(Debug, Queryable, Selectable, Identifiable, Associations, Serialize)]
+#[diesel(belongs_to(Post))]
+#[diesel(table_name = post_revisions)]
+#[diesel(primary_key(post_id, revision))]
+pub struct PostRevision {
+    pub post_id: i32,
+    pub revision: i32,
+    pub title: String,
+    pub body: String,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub timestamps: Timestamps<post_revisions::table>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = post_revisions)]
+pub struct NewPostRevision<'a> {
+    pub post_id: i32,
+    pub revision: i32,
+    pub title: &'a str,
+    pub body: &'a str,
+}
+
+#[derive(Queryable, Identifiable, Selectable, Debug, Hash, Eq, PartialEq, Clone, Serialize, Deserialize)]
 #[diesel(table_name = books)]
 pub struct Book {
     pub id: i32,
     pub title: String,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub timestamps: Timestamps<books::table>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub version: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = books)]
+pub struct NewBook<'a> {
+    pub title: &'a str,
 }
 
-#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = books)]
+pub struct BookChanges<'a> {
+    pub title: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq, Serialize, Deserialize)]
 #[diesel(belongs_to(Book))]
 #[diesel(table_name = pages)]
 pub struct Page {
@@ -37,13 +114,48 @@ pub struct Page {
     pub page_number: i32,
     pub content: String,
     pub book_id: i32,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub timestamps: Timestamps<pages::table>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = pages)]
+pub struct NewPage<'a> {
+    pub page_number: i32,
+    pub content: &'a str,
+    pub book_id: i32,
+}
+
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = pages)]
+pub struct PageChanges<'a> {
+    pub page_number: Option<i32>,
+    pub content: Option<&'a str>,
 }
 
-#[derive(Queryable, Selectable, Identifiable, Debug, Clone, Hash, Eq, PartialEq)]
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 #[diesel(table_name = authors)]
 pub struct Author {
     pub id: i32,
     pub name: String,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub timestamps: Timestamps<authors::table>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = authors)]
+pub struct NewAuthor<'a> {
+    pub name: &'a str,
+}
+
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = authors)]
+pub struct AuthorChanges<'a> {
+    pub name: Option<&'a str>,
 }
 
 #[derive(Identifiable, Selectable, Queryable, Associations, Debug, Clone)]
@@ -54,65 +166,143 @@ pub struct Author {
 pub struct BookAuthor {
     pub book_id: i32,
     pub author_id: i32,
+    #[diesel(embed)]
+    pub timestamps: Timestamps<books_authors::table>,
 }
 
-#[derive(Queryable, Selectable, Identifiable, Associations, Debug, Hash, Eq, PartialEq, Clone)]
+#[derive(Insertable)]
+#[diesel(table_name = books_authors)]
+pub struct NewBookAuthor {
+    pub book_id: i32,
+    pub author_id: i32,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, Hash, Eq, PartialEq, Clone, Serialize, Deserialize)]
 #[diesel(belongs_to(Author))]
 #[diesel(table_name = address)]
 pub struct Address {
     pub id: i32,
     pub value: String,
     pub author_id: i32,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub timestamps: Timestamps<address::table>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = address)]
+pub struct NewAddress<'a> {
+    pub value: &'a str,
+    pub author_id: i32,
 }
 
-#[derive(Queryable, Identifiable, Selectable, Debug, Hash, Eq, PartialEq, Clone)]
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = address)]
+pub struct AddressChanges<'a> {
+    pub value: Option<&'a str>,
+}
+
+preload!(Book => Page, active);
+preload!(Book => Author, through BookAuthor, active);
+preload!(Author => Book, through BookAuthor, active);
+preload!(Author => Address, active);
+
+#[derive(Queryable, Identifiable, Selectable, Debug, Hash, Eq, PartialEq, Clone, Serialize)]
 #[diesel(table_name = items)]
 pub struct Item {
     pub id: i32,
     pub title: String,
     pub num_plays: i32,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub timestamps: Timestamps<items::table>,
 }
 
-#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq, Serialize)]
 #[diesel(belongs_to(Item))]
 #[diesel(table_name = reports)]
 pub struct Report {
     pub id: i32,
     pub title: String,
     pub item_id: i32,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub timestamps: Timestamps<reports::table>,
 }
 
-#[derive(Debug, Queryable, Selectable, Identifiable, AsChangeset)]
+preload!(Item => Report);
+
+#[derive(Debug, Queryable, Selectable, Identifiable)]
 #[diesel(table_name = crate::schema::invites)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Invite {
     pub id: i64,
-    pub kind: String,
+    pub kind: InviteKind,
     pub json: serde_json::Value,
+    #[diesel(embed)]
+    pub timestamps: Timestamps<invites::table>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+// By hand, since the derive can't leave out the embedded timestamps, which the database sets
+impl<'a> AsChangeset for &'a Invite {
+    type Target = invites::table;
+    type Changeset = <(
+        diesel::dsl::Eq<invites::kind, &'a InviteKind>,
+        diesel::dsl::Eq<invites::json, &'a serde_json::Value>,
+        diesel::dsl::Eq<invites::deleted_at, &'a Option<DateTime<Utc>>>,
+    ) as AsChangeset>::Changeset;
+
+    fn as_changeset(self) -> Self::Changeset {
+        (
+            invites::kind.eq(&self.kind),
+            invites::json.eq(&self.json),
+            invites::deleted_at.eq(&self.deleted_at),
+        )
+            .as_changeset()
+    }
 }
 
 #[derive(Debug, Queryable, Selectable, Identifiable)]
 #[diesel(table_name = crate::schema::invites)]
 pub struct InviteJson {
     pub id: i64,
-    pub kind: String,
+    pub kind: InviteKind,
     pub json: InviteData,
+    #[diesel(embed)]
+    pub timestamps: Timestamps<invites::table>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Insertable)]
 #[diesel(table_name = crate::schema::invites)]
 pub struct NewInviteJson {
-    pub kind: String,
+    pub kind: InviteKind,
     pub json: InviteData,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = crate::schema::sql_types::InviteKind)]
+pub enum InviteKind {
+    Email,
+    Link,
+}
+diesel_pg_enum!(InviteKind, crate::schema::sql_types::InviteKind, "invite_kind", {
+    Email => "email",
+    Link => "link",
+});
+
 use diesel::pg::sql_types::Jsonb;
+use diesel::sql_types::Bytea;
 
 #[derive(Debug, AsExpression, FromSqlRow, Serialize, Deserialize)]
 #[diesel(sql_type = Jsonb)]
+#[diesel(sql_type = Bytea)]
 #[serde(tag = "kind")]
 pub enum InviteData {
     Email { name: String },
     Link { url: String },
 }
 diesel_jsonb!(InviteData);
+diesel_binary!(InviteData, MessagePack, zstd(3));
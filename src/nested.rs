@@ -0,0 +1,227 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use diesel::Identifiable;
+use serde::Serialize;
+
+/// An owned row together with the rows grouped under it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Node<T, C> {
+    pub item: T,
+    pub children: Vec<C>,
+}
+
+/// Groups flat join rows into nested `Node`s, one level per tuple element except the last.
+///
+/// Rows are grouped by the `Identifiable` id of each level, and both parents and children keep the
+/// order they first appear in, so an `ORDER BY` on the query carries over to the tree.
+///
+/// ```ignore
+/// let rows: Vec<(Address, Author, Book)> = query.load(conn)?;
+/// let tree: Vec<Node<Address, Node<Author, Book>>> = rows.nest();
+/// ```
+pub trait Nest {
+    type Output;
+
+    fn nest(self) -> Self::Output;
+}
+
+impl<A, B> Nest for Vec<(A, B)>
+where
+    for<'a> &'a A: Identifiable,
+    for<'a> <&'a A as Identifiable>::Id: Hash + Eq,
+{
+    type Output = Vec<Node<A, B>>;
+
+    fn nest(self) -> Self::Output {
+        group(self)
+    }
+}
+
+impl<A, B, C> Nest for Vec<(A, B, C)>
+where
+    for<'a> &'a A: Identifiable,
+    for<'a> <&'a A as Identifiable>::Id: Hash + Eq,
+    for<'a> &'a B: Identifiable,
+    for<'a> <&'a B as Identifiable>::Id: Hash + Eq,
+{
+    type Output = Vec<Node<A, Node<B, C>>>;
+
+    fn nest(self) -> Self::Output {
+        group(self.into_iter().map(|(a, b, c)| (a, (b, c))))
+            .into_iter()
+            .map(|node| Node {
+                item: node.item,
+                children: node.children.nest(),
+            })
+            .collect()
+    }
+}
+
+impl<A, B, C, D> Nest for Vec<(A, B, C, D)>
+where
+    for<'a> &'a A: Identifiable,
+    for<'a> <&'a A as Identifiable>::Id: Hash + Eq,
+    for<'a> &'a B: Identifiable,
+    for<'a> <&'a B as Identifiable>::Id: Hash + Eq,
+    for<'a> &'a C: Identifiable,
+    for<'a> <&'a C as Identifiable>::Id: Hash + Eq,
+{
+    type Output = Vec<Node<A, Node<B, Node<C, D>>>>;
+
+    fn nest(self) -> Self::Output {
+        group(self.into_iter().map(|(a, b, c, d)| (a, (b, c, d))))
+            .into_iter()
+            .map(|node| Node {
+                item: node.item,
+                children: node.children.nest(),
+            })
+            .collect()
+    }
+}
+
+fn group<P, C>(rows: impl IntoIterator<Item = (P, C)>) -> Vec<Node<P, C>>
+where
+    for<'a> &'a P: Identifiable,
+    for<'a> <&'a P as Identifiable>::Id: Hash + Eq,
+{
+    let mut nodes: Vec<Node<P, C>> = Vec::new();
+
+    // The ids borrow from the rows, so index the nodes by the hash of their id and compare the ids
+    // on lookup instead of keeping them around as keys.
+    let mut index: HashMap<u64, Vec<usize>> = HashMap::new();
+
+    for (parent, child) in rows {
+        let candidates = index.entry(hash_id(&parent)).or_default();
+
+        match candidates
+            .iter()
+            .find(|&&i| nodes[i].item.id() == parent.id())
+        {
+            Some(&i) => nodes[i].children.push(child),
+            None => {
+                candidates.push(nodes.len());
+                nodes.push(Node {
+                    item: parent,
+                    children: vec![child],
+                });
+            }
+        }
+    }
+
+    nodes
+}
+
+fn hash_id<P>(item: &P) -> u64
+where
+    for<'a> &'a P: Identifiable,
+    for<'a> <&'a P as Identifiable>::Id: Hash,
+{
+    let mut hasher = DefaultHasher::new();
+    item.id().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::associations::HasTable;
+
+    use crate::schema::books;
+
+    // Any table will do, `Nest` only looks at the ids
+    #[derive(Debug, Clone, PartialEq)]
+    struct Row(i32, &'static str);
+
+    impl HasTable for Row {
+        type Table = books::table;
+
+        fn table() -> Self::Table {
+            books::table
+        }
+    }
+
+    impl<'a> Identifiable for &'a Row {
+        type Id = &'a i32;
+
+        fn id(self) -> Self::Id {
+            &self.0
+        }
+    }
+
+    fn node<T, C>(item: T, children: Vec<C>) -> Node<T, C> {
+        Node { item, children }
+    }
+
+    #[test]
+    fn groups_pairs_by_parent_in_first_seen_order() {
+        let rows = vec![
+            (Row(2, "b"), "x"),
+            (Row(1, "a"), "y"),
+            (Row(2, "b"), "z"),
+        ];
+
+        assert_eq!(
+            rows.nest(),
+            vec![
+                node(Row(2, "b"), vec!["x", "z"]),
+                node(Row(1, "a"), vec!["y"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_by_id_not_by_value() {
+        let rows = vec![(Row(1, "a"), 1), (Row(1, "changed"), 2)];
+
+        assert_eq!(rows.nest(), vec![node(Row(1, "a"), vec![1, 2])]);
+    }
+
+    #[test]
+    fn nests_each_level() {
+        let rows = vec![
+            (Row(1, "address"), Row(10, "author"), Row(100, "book")),
+            (Row(1, "address"), Row(11, "author"), Row(101, "book")),
+            (Row(1, "address"), Row(10, "author"), Row(102, "book")),
+            (Row(2, "address"), Row(10, "author"), Row(100, "book")),
+        ];
+
+        assert_eq!(
+            rows.nest(),
+            vec![
+                node(
+                    Row(1, "address"),
+                    vec![
+                        node(Row(10, "author"), vec![Row(100, "book"), Row(102, "book")]),
+                        node(Row(11, "author"), vec![Row(101, "book")]),
+                    ]
+                ),
+                node(
+                    Row(2, "address"),
+                    vec![node(Row(10, "author"), vec![Row(100, "book")])]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn nests_four_levels() {
+        let rows = vec![(Row(1, "a"), Row(2, "b"), Row(3, "c"), "d")];
+
+        assert_eq!(
+            rows.nest(),
+            vec![node(
+                Row(1, "a"),
+                vec![node(Row(2, "b"), vec![node(Row(3, "c"), vec!["d"])])]
+            )]
+        );
+    }
+
+    #[test]
+    fn empty_rows_make_an_empty_tree() {
+        let rows: Vec<(Row, Row, i32)> = Vec::new();
+
+        assert!(rows.nest().is_empty());
+    }
+}
@@ -3,6 +3,8 @@ use diesel::prelude::*;
 use diesel::query_builder::*;
 use diesel::query_dsl::methods::LoadQuery;
 use diesel::sql_types::BigInt;
+use diesel_async::AsyncPgConnection;
+use serde::Serialize;
 
 pub trait Paginate: Sized {
     fn paginate(self, page: i64) -> Paginated<Self>;
@@ -62,7 +64,7 @@ pub struct PaginatedWithTotal<T> {
     common: Common<T>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct PaginatedResult<T> {
     pub data: Vec<T>,
     pub page_size: i64,
@@ -83,7 +85,7 @@ impl<T> PaginatedWithTotal<T> {
     }
 
     // TODO: How to make this generic over all pairs, currently it only supports returning one datatype
-    //   --> Seems to work with <tables>::all_columns, but not Table::as_select() for some reason
+    //   --> Works with `<(A, B)>::as_select()`, but not a tuple of `A::as_select()`s
     pub fn load_and_count_pages<'a, U>(
         self,
         conn: &mut PgConnection,
@@ -93,14 +95,34 @@ impl<T> PaginatedWithTotal<T> {
     {
         let per_page = self.common.per_page;
         let results = self.load::<(U, i64)>(conn)?;
-        let total = results.first().map(|x| x.1).unwrap_or(0);
-        let records = results.into_iter().map(|x| x.0).collect();
+        Ok(PaginatedResult::from_rows(results, per_page))
+    }
+
+    /// `load_and_count_pages` on an async connection.
+    pub async fn load_and_count_pages_async<'a, U>(
+        self,
+        conn: &mut AsyncPgConnection,
+    ) -> QueryResult<PaginatedResult<U>>
+    where
+        Self: diesel_async::methods::LoadQuery<'a, AsyncPgConnection, (U, i64)> + 'a,
+        U: Send + 'a,
+    {
+        let per_page = self.common.per_page;
+        let results = diesel_async::RunQueryDsl::load::<(U, i64)>(self, conn).await?;
+        Ok(PaginatedResult::from_rows(results, per_page))
+    }
+}
+
+impl<T> PaginatedResult<T> {
+    fn from_rows(rows: Vec<(T, i64)>, per_page: i64) -> Self {
+        let total = rows.first().map(|x| x.1).unwrap_or(0);
+        let records = rows.into_iter().map(|x| x.0).collect();
         let total_pages = (total as f64 / per_page as f64).ceil() as i64;
-        Ok(PaginatedResult {
+        PaginatedResult {
             data: records,
             page_size: per_page,
             total_pages,
-        })
+        }
     }
 }
 
@@ -126,6 +148,8 @@ impl<T: Query> Query for PaginatedWithTotal<T> {
 
 impl<T> RunQueryDsl<PgConnection> for Paginated<T> {}
 impl<T> RunQueryDsl<PgConnection> for PaginatedWithTotal<T> {}
+// Both also load on an `AsyncPgConnection` through `diesel_async::RunQueryDsl`, which is implemented
+// for every type
 
 impl<T> QueryFragment<Pg> for Paginated<T>
 where
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use diesel::r2d2::{self, ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use dotenvy::dotenv;
+
+use crate::config::{connection_failed, env_var, retry, Backoff, ConnectError, DbConfig};
+
+pub type PgPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type PooledPgConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+/// Sizing and limits of a `PgPool`. The defaults suit the webserver; `from_env` overrides them
+/// with the `DATABASE_POOL_*` variables.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Most connections open at once.
+    pub max_size: u32,
+    /// Idle connections kept open. `None` keeps `max_size` of them.
+    pub min_idle: Option<u32>,
+    /// How long `get` waits for a free connection before failing.
+    pub connection_timeout: Duration,
+    /// Idle connections above `min_idle` are closed after this long.
+    pub idle_timeout: Option<Duration>,
+    /// Connections are closed after this long, so server-side state doesn't pile up.
+    pub max_lifetime: Option<Duration>,
+    /// Runs `SELECT 1` before handing out a connection, so a connection the server dropped is
+    /// replaced instead of failing the first query.
+    pub test_on_check_out: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 10,
+            min_idle: Some(1),
+            connection_timeout: Duration::from_secs(5),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            max_lifetime: Some(Duration::from_secs(30 * 60)),
+            test_on_check_out: true,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// The defaults, overridden by `DATABASE_POOL_MAX_SIZE`, `DATABASE_POOL_MIN_IDLE`,
+    /// `DATABASE_POOL_TIMEOUT_SECS`, `DATABASE_POOL_IDLE_TIMEOUT_SECS`,
+    /// `DATABASE_POOL_MAX_LIFETIME_SECS` and `DATABASE_POOL_TEST_ON_CHECK_OUT`. The timeouts can be
+    /// set to 0 to disable them. Fails on values that don't parse, rather than silently using the
+    /// default.
+    pub fn from_env() -> Result<Self, ConnectError> {
+        dotenv().ok();
+
+        let defaults = PoolConfig::default();
+        let secs = |name, default: Option<Duration>| {
+            Ok(match env_var::<u64>(name)? {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => default,
+            })
+        };
+
+        Ok(PoolConfig {
+            max_size: env_var("DATABASE_POOL_MAX_SIZE")?.unwrap_or(defaults.max_size),
+            min_idle: env_var("DATABASE_POOL_MIN_IDLE")?.or(defaults.min_idle),
+            connection_timeout: env_var("DATABASE_POOL_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.connection_timeout),
+            idle_timeout: secs("DATABASE_POOL_IDLE_TIMEOUT_SECS", defaults.idle_timeout)?,
+            max_lifetime: secs("DATABASE_POOL_MAX_LIFETIME_SECS", defaults.max_lifetime)?,
+            test_on_check_out: env_var("DATABASE_POOL_TEST_ON_CHECK_OUT")?
+                .unwrap_or(defaults.test_on_check_out),
+        })
+    }
+}
+
+/// Opens `config.min_idle` connections to `database_url` (a URL or libpq connection string) up
+/// front, and fails if that takes longer than `config.connection_timeout`.
+pub fn build_pool(database_url: &str, config: &PoolConfig) -> Result<PgPool, r2d2::PoolError> {
+    builder(config).build(ConnectionManager::new(database_url))
+}
+
+fn builder(config: &PoolConfig) -> r2d2::Builder<ConnectionManager<PgConnection>> {
+    r2d2::Pool::builder()
+        .max_size(config.max_size)
+        .min_idle(config.min_idle)
+        .connection_timeout(config.connection_timeout)
+        .idle_timeout(config.idle_timeout)
+        .max_lifetime(config.max_lifetime)
+        .test_on_check_out(config.test_on_check_out)
+}
+
+/// A pool for `config`, see `build_pool`.
+pub fn connect_pool(config: &DbConfig, pool_config: &PoolConfig) -> Result<PgPool, ConnectError> {
+    build_pool(&config.connection_string()?, pool_config)
+        .map_err(|e| connection_failed(config, &e.to_string()))
+}
+
+/// Like `connect_pool`, but connects in the background instead of waiting for the first
+/// connections, so it can't fail on an unreachable server.
+pub fn connect_pool_lazily(
+    config: &DbConfig,
+    pool_config: &PoolConfig,
+) -> Result<PgPool, ConnectError> {
+    Ok(builder(pool_config).build_unchecked(ConnectionManager::new(config.connection_string()?)))
+}
+
+/// A pool configured by `DbConfig::load` and `PoolConfig::from_env`, retrying for a while if the
+/// server isn't up yet.
+pub fn establish_pool() -> Result<PgPool, ConnectError> {
+    let config = DbConfig::load()?;
+    let pool_config = PoolConfig::from_env()?;
+
+    retry(&Backoff::default(), || connect_pool(&config, &pool_config))
+}
@@ -0,0 +1,154 @@
+use diesel::prelude::*;
+
+/// A parent together with its preloaded children.
+pub type WithChildren<P, C> = (P, Vec<C>);
+
+/// Loads the `C` children of many parents in a single query. Implemented with `preload!`.
+pub trait Preload<C>: Sized {
+    /// One `Vec` of children per parent, in the same order as `parents`.
+    fn preload(conn: &mut PgConnection, parents: &[Self]) -> QueryResult<Vec<Vec<C>>>;
+}
+
+/// A many-to-many association to `C` through the join table `J`. Implemented with
+/// `preload!(Parent => Child, through Join)`, which also implements `Preload<C>`.
+pub trait PreloadThrough<J, C>: Sized {
+    fn preload_through(conn: &mut PgConnection, parents: &[Self]) -> QueryResult<Vec<Vec<C>>>;
+}
+
+pub trait LoadWith: Sized {
+    /// `Book::load_with::<Page>(conn, books)`
+    fn load_with<C>(
+        conn: &mut PgConnection,
+        parents: Vec<Self>,
+    ) -> QueryResult<Vec<WithChildren<Self, C>>>
+    where
+        Self: Preload<C>,
+    {
+        let children = Self::preload(conn, &parents)?;
+        Ok(parents.into_iter().zip(children).collect())
+    }
+
+    /// `Author::load_with_through::<BookAuthor, Book>(conn, authors)`
+    fn load_with_through<J, C>(
+        conn: &mut PgConnection,
+        parents: Vec<Self>,
+    ) -> QueryResult<Vec<WithChildren<Self, C>>>
+    where
+        Self: PreloadThrough<J, C>,
+    {
+        let children = Self::preload_through(conn, &parents)?;
+        Ok(parents.into_iter().zip(children).collect())
+    }
+}
+
+impl<T> LoadWith for T {}
+
+/// Preloads the next level of an already preloaded result, e.g. the pages of every book in
+/// `Vec<(Author, Vec<Book>)>`. Runs one query for all children, regardless of the number of
+/// parents.
+pub fn load_nested<P, C, G>(
+    conn: &mut PgConnection,
+    parents: Vec<WithChildren<P, C>>,
+) -> QueryResult<Vec<WithChildren<P, WithChildren<C, G>>>>
+where
+    C: Preload<G>,
+{
+    let mut sizes = Vec::with_capacity(parents.len());
+    let mut all_children = Vec::new();
+    let mut all_parents = Vec::with_capacity(parents.len());
+
+    for (parent, children) in parents {
+        sizes.push(children.len());
+        all_children.extend(children);
+        all_parents.push(parent);
+    }
+
+    let grandchildren = C::preload(conn, &all_children)?;
+    let mut nested = all_children.into_iter().zip(grandchildren);
+
+    Ok(all_parents
+        .into_iter()
+        .zip(sizes)
+        .map(|(parent, size)| (parent, nested.by_ref().take(size).collect()))
+        .collect())
+}
+
+/// `preload!(Book => Page)`, or `preload!(Author => Book, through BookAuthor)` for a many-to-many
+/// association. Children come in the order of their primary key. Add `active` when the child table
+/// is `SoftDelete`, to leave out deleted children: `preload!(Book => Page, active)`.
+#[macro_export]
+macro_rules! preload {
+    ($parent: ty => $child: ty) => {
+        $crate::preload!(@children $parent => $child;);
+    };
+    ($parent: ty => $child: ty, active) => {
+        $crate::preload!(@children $parent => $child; .filter($crate::preload!(@active $child)));
+    };
+    ($parent: ty => $child: ty, through $join: ty) => {
+        $crate::preload!(@through $parent => $child, $join;);
+    };
+    ($parent: ty => $child: ty, through $join: ty, active) => {
+        $crate::preload!(
+            @through $parent => $child, $join; .filter($crate::preload!(@active $child))
+        );
+    };
+    (@active $child: ty) => {
+        <<$child as ::diesel::associations::HasTable>::Table as $crate::soft_delete::SoftDelete>
+            ::DeletedAt::default()
+            .is_null()
+    };
+    (@children $parent: ty => $child: ty; $($scope: tt)*) => {
+        impl $crate::preload::Preload<$child> for $parent {
+            fn preload(
+                conn: &mut ::diesel::PgConnection,
+                parents: &[Self],
+            ) -> ::diesel::QueryResult<Vec<Vec<$child>>> {
+                use ::diesel::prelude::*;
+                use ::diesel::Table;
+
+                let children = <$child>::belonging_to(parents)
+                    $($scope)*
+                    .order(<$child as ::diesel::associations::HasTable>::table().primary_key())
+                    .select(<$child>::as_select())
+                    .load::<$child>(conn)?;
+
+                Ok(children.grouped_by(parents))
+            }
+        }
+    };
+    (@through $parent: ty => $child: ty, $join: ty; $($scope: tt)*) => {
+        impl $crate::preload::PreloadThrough<$join, $child> for $parent {
+            fn preload_through(
+                conn: &mut ::diesel::PgConnection,
+                parents: &[Self],
+            ) -> ::diesel::QueryResult<Vec<Vec<$child>>> {
+                use ::diesel::prelude::*;
+                use ::diesel::Table;
+
+                let rows = <$join>::belonging_to(parents)
+                    .inner_join(<$child as ::diesel::associations::HasTable>::table())
+                    $($scope)*
+                    .order(<$child as ::diesel::associations::HasTable>::table().primary_key())
+                    .select((<$join>::as_select(), <$child>::as_select()))
+                    .load::<($join, $child)>(conn)?;
+
+                Ok(rows
+                    .grouped_by(parents)
+                    .into_iter()
+                    .map(|group| group.into_iter().map(|(_, child)| child).collect())
+                    .collect())
+            }
+        }
+
+        impl $crate::preload::Preload<$child> for $parent {
+            fn preload(
+                conn: &mut ::diesel::PgConnection,
+                parents: &[Self],
+            ) -> ::diesel::QueryResult<Vec<Vec<$child>>> {
+                <Self as $crate::preload::PreloadThrough<$join, $child>>::preload_through(
+                    conn, parents,
+                )
+            }
+        }
+    };
+}
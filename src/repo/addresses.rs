@@ -0,0 +1,68 @@
+use diesel::prelude::*;
+
+use crate::models::{Address, AddressChanges, NewAddress};
+use crate::schema::address;
+use crate::soft_delete::SoftDelete;
+
+use super::{RepoError, RepoResult};
+
+pub struct AddressRepository<'a> {
+    conn: &'a mut PgConnection,
+}
+
+impl<'a> AddressRepository<'a> {
+    pub fn new(conn: &'a mut PgConnection) -> Self {
+        AddressRepository { conn }
+    }
+
+    pub fn create(&mut self, address: &NewAddress) -> RepoResult<Address> {
+        Ok(diesel::insert_into(address::table)
+            .values(address)
+            .returning(Address::as_returning())
+            .get_result(self.conn)?)
+    }
+
+    pub fn get(&mut self, id: i32) -> RepoResult<Address> {
+        Ok(address::table
+            .active()
+            .find(id)
+            .select(Address::as_select())
+            .get_result(self.conn)?)
+    }
+
+    pub fn update(&mut self, id: i32, changes: &AddressChanges) -> RepoResult<Address> {
+        if changes.value.is_none() {
+            return self.get(id);
+        }
+
+        Ok(diesel::update(address::table.active().find(id))
+            .set(changes)
+            .returning(Address::as_returning())
+            .get_result(self.conn)?)
+    }
+
+    pub fn delete(&mut self, id: i32) -> RepoResult<()> {
+        if !address::table::soft_delete(self.conn, id)? {
+            return Err(RepoError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    pub fn restore(&mut self, id: i32) -> RepoResult<Address> {
+        if !address::table::restore(self.conn, id)? {
+            return Err(RepoError::NotFound);
+        }
+
+        self.get(id)
+    }
+
+    pub fn list_for_author(&mut self, author_id: i32) -> RepoResult<Vec<Address>> {
+        Ok(address::table
+            .active()
+            .filter(address::author_id.eq(author_id))
+            .order(address::id.asc())
+            .select(Address::as_select())
+            .load(self.conn)?)
+    }
+}
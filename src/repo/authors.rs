@@ -0,0 +1,179 @@
+use chrono::{DateTime, Utc};
+use diesel::dsl::now;
+use diesel::prelude::*;
+use diesel::sql_types::Timestamptz;
+
+use crate::models::{Author, AuthorChanges, Book, BookAuthor, NewAuthor, NewBookAuthor};
+use crate::pagination::Paginate;
+use crate::schema::{address, authors, books, books_authors};
+use crate::soft_delete::SoftDelete;
+use crate::timestamps::recent_first;
+
+use super::{contains_pattern, expect_affected, RepoError, RepoResult};
+
+#[derive(Debug, Default)]
+pub struct AuthorFilter<'a> {
+    /// Case-insensitive substring match on the name.
+    pub name: Option<&'a str>,
+    pub book_id: Option<i32>,
+    /// Most recently updated first instead of by id.
+    pub recent_first: bool,
+}
+
+pub struct AuthorRepository<'a> {
+    conn: &'a mut PgConnection,
+}
+
+impl<'a> AuthorRepository<'a> {
+    pub fn new(conn: &'a mut PgConnection) -> Self {
+        AuthorRepository { conn }
+    }
+
+    pub fn create(&mut self, author: &NewAuthor) -> RepoResult<Author> {
+        Ok(diesel::insert_into(authors::table)
+            .values(author)
+            .returning(Author::as_returning())
+            .get_result(self.conn)?)
+    }
+
+    pub fn get(&mut self, id: i32) -> RepoResult<Author> {
+        Ok(authors::table
+            .active()
+            .find(id)
+            .select(Author::as_select())
+            .get_result(self.conn)?)
+    }
+
+    pub fn update(&mut self, id: i32, changes: &AuthorChanges) -> RepoResult<Author> {
+        if changes.name.is_none() {
+            return self.get(id);
+        }
+
+        Ok(diesel::update(authors::table.active().find(id))
+            .set(changes)
+            .returning(Author::as_returning())
+            .get_result(self.conn)?)
+    }
+
+    /// Soft deletes the author together with their addresses. The book links are kept so
+    /// `restore` brings everything back, `soft_delete::purge` removes them for good.
+    pub fn delete(&mut self, id: i32) -> RepoResult<()> {
+        self.conn.transaction(|conn| {
+            if !authors::table::soft_delete(conn, id)? {
+                return Err(RepoError::NotFound);
+            }
+
+            // now() is fixed for the transaction, so the addresses get the same deleted_at
+            diesel::update(address::table.active().filter(address::author_id.eq(id)))
+                .set(address::deleted_at.eq(now.into_sql::<Timestamptz>().nullable()))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+
+    /// Undoes `delete`, including the addresses that were deleted along with the author.
+    pub fn restore(&mut self, id: i32) -> RepoResult<Author> {
+        self.conn.transaction(|conn| {
+            let deleted_at: Option<DateTime<Utc>> = authors::table
+                .deleted()
+                .find(id)
+                .select(authors::deleted_at)
+                .get_result(conn)?;
+
+            diesel::update(
+                address::table
+                    .filter(address::author_id.eq(id))
+                    .filter(address::deleted_at.eq(deleted_at)),
+            )
+            .set(address::deleted_at.eq(None::<DateTime<Utc>>))
+            .execute(conn)?;
+
+            authors::table::restore(conn, id)?;
+
+            Ok(authors::table
+                .find(id)
+                .select(Author::as_select())
+                .get_result(conn)?)
+        })
+    }
+
+    pub fn list(&mut self, filter: &AuthorFilter, page: i64) -> RepoResult<Vec<Author>> {
+        let mut query = authors::table
+            .active()
+            .select(Author::as_select())
+            .into_boxed();
+
+        query = if filter.recent_first {
+            query.order((recent_first::<authors::table>(), authors::id.desc()))
+        } else {
+            query.order(authors::id.asc())
+        };
+
+        if let Some(name) = filter.name {
+            query = query.filter(authors::name.ilike(contains_pattern(name)));
+        }
+
+        if let Some(book_id) = filter.book_id {
+            query = query.filter(
+                authors::id.eq_any(
+                    books_authors::table
+                        .filter(books_authors::book_id.eq(book_id))
+                        .select(books_authors::author_id),
+                ),
+            );
+        }
+
+        Ok(query.paginate(page).load(self.conn)?)
+    }
+
+    /// Links the author to a book. Fails with `Conflict` if they are already linked or either
+    /// side does not exist or is deleted.
+    pub fn add_book(&mut self, author_id: i32, book_id: i32) -> RepoResult<BookAuthor> {
+        self.conn.transaction(|conn| {
+            // Shared locks keep either side from being deleted until the link is in
+            let author = authors::table
+                .active()
+                .find(author_id)
+                .select(authors::id)
+                .for_share()
+                .get_result::<i32>(conn)
+                .optional()?;
+            if author.is_none() {
+                return Err(RepoError::Conflict(format!("No author {}", author_id)));
+            }
+
+            let book = books::table
+                .active()
+                .find(book_id)
+                .select(books::id)
+                .for_share()
+                .get_result::<i32>(conn)
+                .optional()?;
+            if book.is_none() {
+                return Err(RepoError::Conflict(format!("No book {}", book_id)));
+            }
+
+            Ok(diesel::insert_into(books_authors::table)
+                .values(NewBookAuthor { book_id, author_id })
+                .returning(BookAuthor::as_returning())
+                .get_result(conn)?)
+        })
+    }
+
+    pub fn remove_book(&mut self, author_id: i32, book_id: i32) -> RepoResult<()> {
+        expect_affected(
+            diesel::delete(books_authors::table.find((book_id, author_id))).execute(self.conn)?,
+        )
+    }
+
+    pub fn books(&mut self, author_id: i32) -> RepoResult<Vec<Book>> {
+        Ok(books_authors::table
+            .inner_join(books::table)
+            .filter(books_authors::author_id.eq(author_id))
+            .filter(books::deleted_at.is_null())
+            .order(books::id.asc())
+            .select(Book::as_select())
+            .load(self.conn)?)
+    }
+}
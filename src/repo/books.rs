@@ -0,0 +1,147 @@
+use chrono::{DateTime, Utc};
+use diesel::dsl::now;
+use diesel::prelude::*;
+use diesel::sql_types::Timestamptz;
+
+use crate::models::{Book, BookChanges, NewBook};
+use crate::pagination::Paginate;
+use crate::schema::{books, books_authors, pages};
+use crate::soft_delete::SoftDelete;
+use crate::timestamps::recent_first;
+use crate::versioned::Versioned;
+
+use super::{contains_pattern, RepoError, RepoResult};
+
+#[derive(Debug, Default)]
+pub struct BookFilter<'a> {
+    /// Case-insensitive substring match on the title.
+    pub title: Option<&'a str>,
+    pub author_id: Option<i32>,
+    /// Most recently updated first instead of by id.
+    pub recent_first: bool,
+}
+
+pub struct BookRepository<'a> {
+    conn: &'a mut PgConnection,
+}
+
+impl<'a> BookRepository<'a> {
+    pub fn new(conn: &'a mut PgConnection) -> Self {
+        BookRepository { conn }
+    }
+
+    pub fn create(&mut self, book: &NewBook) -> RepoResult<Book> {
+        Ok(diesel::insert_into(books::table)
+            .values(book)
+            .returning(Book::as_returning())
+            .get_result(self.conn)?)
+    }
+
+    pub fn get(&mut self, id: i32) -> RepoResult<Book> {
+        Ok(books::table
+            .active()
+            .find(id)
+            .select(Book::as_select())
+            .get_result(self.conn)?)
+    }
+
+    pub fn update(&mut self, id: i32, changes: &BookChanges) -> RepoResult<Book> {
+        if changes.title.is_none() {
+            return self.get(id);
+        }
+
+        Ok(diesel::update(books::table.active().find(id))
+            .set((changes, books::version.eq(books::version + 1)))
+            .returning(Book::as_returning())
+            .get_result(self.conn)?)
+    }
+
+    /// Like `update`, but fails with `RepoError::StaleObject` if the book was changed since it was
+    /// read at `version`.
+    pub fn update_with_version(
+        &mut self,
+        id: i32,
+        version: i32,
+        changes: &BookChanges,
+    ) -> RepoResult<Book> {
+        self.conn.transaction(|conn| {
+            let mut books = BookRepository::new(conn);
+            books.get(id)?;
+
+            books::table::update_with_version(books.conn, id, version, changes)?;
+            books.get(id)
+        })
+    }
+
+    /// Soft deletes the book together with its pages. The author links are kept so `restore`
+    /// brings everything back, `soft_delete::purge` removes them for good.
+    pub fn delete(&mut self, id: i32) -> RepoResult<()> {
+        self.conn.transaction(|conn| {
+            if !books::table::soft_delete(conn, id)? {
+                return Err(RepoError::NotFound);
+            }
+
+            // now() is fixed for the transaction, so the pages get the same deleted_at as the book
+            diesel::update(pages::table.active().filter(pages::book_id.eq(id)))
+                .set(pages::deleted_at.eq(now.into_sql::<Timestamptz>().nullable()))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+
+    /// Undoes `delete`, including the pages that were deleted along with the book.
+    pub fn restore(&mut self, id: i32) -> RepoResult<Book> {
+        self.conn.transaction(|conn| {
+            let deleted_at: Option<DateTime<Utc>> = books::table
+                .deleted()
+                .find(id)
+                .select(books::deleted_at)
+                .get_result(conn)?;
+
+            diesel::update(
+                pages::table
+                    .filter(pages::book_id.eq(id))
+                    .filter(pages::deleted_at.eq(deleted_at)),
+            )
+            .set(pages::deleted_at.eq(None::<DateTime<Utc>>))
+            .execute(conn)?;
+
+            books::table::restore(conn, id)?;
+
+            Ok(books::table
+                .find(id)
+                .select(Book::as_select())
+                .get_result(conn)?)
+        })
+    }
+
+    pub fn list(&mut self, filter: &BookFilter, page: i64) -> RepoResult<Vec<Book>> {
+        let mut query = books::table
+            .active()
+            .select(Book::as_select())
+            .into_boxed();
+
+        query = if filter.recent_first {
+            query.order((recent_first::<books::table>(), books::id.desc()))
+        } else {
+            query.order(books::id.asc())
+        };
+
+        if let Some(title) = filter.title {
+            query = query.filter(books::title.ilike(contains_pattern(title)));
+        }
+
+        if let Some(author_id) = filter.author_id {
+            query = query.filter(
+                books::id.eq_any(
+                    books_authors::table
+                        .filter(books_authors::author_id.eq(author_id))
+                        .select(books_authors::book_id),
+                ),
+            );
+        }
+
+        Ok(query.paginate(page).load(self.conn)?)
+    }
+}
@@ -0,0 +1,134 @@
+use std::error::Error;
+use std::fmt;
+
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+mod addresses;
+mod authors;
+mod books;
+mod pages;
+mod posts;
+mod reports;
+
+pub use addresses::AddressRepository;
+pub use authors::{AuthorFilter, AuthorRepository};
+pub use books::{BookFilter, BookRepository};
+pub use pages::PageRepository;
+pub use posts::{
+    list_posts_async, list_posts_with_total_async, slugify, PostFilter, PostRepository,
+};
+pub use reports::ReportRepository;
+
+pub type RepoResult<T> = Result<T, RepoError>;
+
+#[derive(Debug)]
+pub enum RepoError {
+    /// The row does not exist.
+    NotFound,
+    /// The change violates a unique or foreign key constraint, e.g. linking a book to an author
+    /// twice or adding a page to a book that does not exist.
+    Conflict(String),
+    /// The row was changed by someone else since it was read at version `expected`, see
+    /// `Versioned::update_with_version`.
+    StaleObject { expected: i32, actual: i32 },
+    Database(DieselError),
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::NotFound => write!(f, "Not found"),
+            RepoError::Conflict(message) => write!(f, "Conflict: {}", message),
+            RepoError::StaleObject { expected, actual } => write!(
+                f,
+                "Stale object: expected version {}, but it is at version {}",
+                expected, actual
+            ),
+            RepoError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl Error for RepoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RepoError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DieselError> for RepoError {
+    fn from(e: DieselError) -> Self {
+        match e {
+            DieselError::NotFound => RepoError::NotFound,
+            DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation | DatabaseErrorKind::ForeignKeyViolation,
+                info,
+            ) => RepoError::Conflict(info.message().to_string()),
+            e => RepoError::Database(e),
+        }
+    }
+}
+
+/// Turns "no rows affected" into `NotFound` for updates and deletes by id.
+fn expect_affected(rows: usize) -> RepoResult<()> {
+    match rows {
+        0 => Err(RepoError::NotFound),
+        _ => Ok(()),
+    }
+}
+
+/// An `ILIKE` pattern matching `value` anywhere, with the wildcards in `value` matched literally.
+fn contains_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database_error(kind: DatabaseErrorKind) -> DieselError {
+        DieselError::DatabaseError(kind, Box::new("duplicate key value".to_string()))
+    }
+
+    #[test]
+    fn maps_missing_rows_to_not_found() {
+        assert!(matches!(
+            RepoError::from(DieselError::NotFound),
+            RepoError::NotFound
+        ));
+        assert!(matches!(expect_affected(0), Err(RepoError::NotFound)));
+        assert!(expect_affected(1).is_ok());
+    }
+
+    #[test]
+    fn maps_constraint_violations_to_conflict() {
+        for kind in [
+            DatabaseErrorKind::UniqueViolation,
+            DatabaseErrorKind::ForeignKeyViolation,
+        ] {
+            match RepoError::from(database_error(kind)) {
+                RepoError::Conflict(message) => assert_eq!(message, "duplicate key value"),
+                e => panic!("expected a conflict, got {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn escapes_wildcards_in_patterns() {
+        assert_eq!(contains_pattern("Momo"), "%Momo%");
+        assert_eq!(contains_pattern("100%_\\"), "%100\\%\\_\\\\%");
+    }
+
+    #[test]
+    fn keeps_other_errors_as_database_errors() {
+        let e = RepoError::from(database_error(DatabaseErrorKind::CheckViolation));
+        assert!(matches!(e, RepoError::Database(_)));
+        assert!(e.source().is_some());
+    }
+}
@@ -0,0 +1,68 @@
+use diesel::prelude::*;
+
+use crate::models::{NewPage, Page, PageChanges};
+use crate::schema::pages;
+use crate::soft_delete::SoftDelete;
+
+use super::{RepoError, RepoResult};
+
+pub struct PageRepository<'a> {
+    conn: &'a mut PgConnection,
+}
+
+impl<'a> PageRepository<'a> {
+    pub fn new(conn: &'a mut PgConnection) -> Self {
+        PageRepository { conn }
+    }
+
+    pub fn create(&mut self, page: &NewPage) -> RepoResult<Page> {
+        Ok(diesel::insert_into(pages::table)
+            .values(page)
+            .returning(Page::as_returning())
+            .get_result(self.conn)?)
+    }
+
+    pub fn get(&mut self, id: i32) -> RepoResult<Page> {
+        Ok(pages::table
+            .active()
+            .find(id)
+            .select(Page::as_select())
+            .get_result(self.conn)?)
+    }
+
+    pub fn update(&mut self, id: i32, changes: &PageChanges) -> RepoResult<Page> {
+        if changes.page_number.is_none() && changes.content.is_none() {
+            return self.get(id);
+        }
+
+        Ok(diesel::update(pages::table.active().find(id))
+            .set(changes)
+            .returning(Page::as_returning())
+            .get_result(self.conn)?)
+    }
+
+    pub fn delete(&mut self, id: i32) -> RepoResult<()> {
+        if !pages::table::soft_delete(self.conn, id)? {
+            return Err(RepoError::NotFound);
+        }
+
+        Ok(())
+    }
+
+    pub fn restore(&mut self, id: i32) -> RepoResult<Page> {
+        if !pages::table::restore(self.conn, id)? {
+            return Err(RepoError::NotFound);
+        }
+
+        self.get(id)
+    }
+
+    pub fn list_for_book(&mut self, book_id: i32) -> RepoResult<Vec<Page>> {
+        Ok(pages::table
+            .active()
+            .filter(pages::book_id.eq(book_id))
+            .order((pages::page_number.asc(), pages::id.asc()))
+            .select(Page::as_select())
+            .load(self.conn)?)
+    }
+}
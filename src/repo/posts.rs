@@ -0,0 +1,439 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use diesel::dsl::now;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::sql_types::Timestamptz;
+use diesel_async::AsyncPgConnection;
+
+use crate::diesel_pg_enum::PgEnum;
+use crate::models::{NewPost, NewPostRevision, Post, PostChanges, PostRevision, PostStatus};
+use crate::pagination::{Paginate, PaginateWithTotal, PaginatedResult};
+use crate::schema::{post_revisions, posts};
+use crate::search::{self, PostMatch};
+use crate::timestamps::recent_first;
+use crate::versioned::Versioned;
+
+use super::{contains_pattern, RepoError, RepoResult};
+
+#[derive(Debug, Default)]
+pub struct PostFilter<'a> {
+    pub status: Option<PostStatus>,
+    /// Case-insensitive substring match on the title.
+    pub title: Option<&'a str>,
+    /// Most recently updated first instead of by id.
+    pub recent_first: bool,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = posts, treat_none_as_null = true)]
+struct StatusChange {
+    status: PostStatus,
+    publish_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+/// Posts go from `Draft` to `Published`, either right away or through `Scheduled`, and can be
+/// taken back to `Draft` or `Archived`. Every edit of the title or body is kept as a revision.
+pub struct PostRepository<'a> {
+    conn: &'a mut PgConnection,
+}
+
+impl<'a> PostRepository<'a> {
+    pub fn new(conn: &'a mut PgConnection) -> Self {
+        PostRepository { conn }
+    }
+
+    /// Creates a draft, with a slug made unique by appending `-2`, `-3`, ... if needed.
+    pub fn create(&mut self, title: &str, body: &str) -> RepoResult<Post> {
+        self.conn.transaction(|conn| {
+            let post = insert_with_unique_slug(conn, title, body)?;
+
+            add_revision(conn, &post, 1)?;
+
+            Ok(post)
+        })
+    }
+
+    pub fn get(&mut self, id: i32) -> RepoResult<Post> {
+        Ok(posts::table
+            .find(id)
+            .select(Post::as_select())
+            .get_result(self.conn)?)
+    }
+
+    pub fn get_by_slug(&mut self, slug: &str) -> RepoResult<Post> {
+        Ok(posts::table
+            .filter(posts::slug.eq(slug))
+            .select(Post::as_select())
+            .get_result(self.conn)?)
+    }
+
+    /// Like `get`, but `NotFound` unless the post is published, for readers who aren't editors.
+    pub fn get_published(&mut self, id: i32) -> RepoResult<Post> {
+        Ok(posts::table
+            .find(id)
+            .filter(posts::status.eq(PostStatus::Published))
+            .select(Post::as_select())
+            .get_result(self.conn)?)
+    }
+
+    /// Like `get_by_slug`, but `NotFound` unless the post is published.
+    pub fn get_published_by_slug(&mut self, slug: &str) -> RepoResult<Post> {
+        Ok(posts::table
+            .filter(posts::slug.eq(slug))
+            .filter(posts::status.eq(PostStatus::Published))
+            .select(Post::as_select())
+            .get_result(self.conn)?)
+    }
+
+    pub fn list(&mut self, filter: &PostFilter, page: i64) -> RepoResult<Vec<Post>> {
+        Ok(filtered(filter)
+            .select(Post::as_select())
+            .paginate(page)
+            .load(self.conn)?)
+    }
+
+    /// Like `list`, with the number of pages. `per_page` is at most 10.
+    pub fn list_with_total(
+        &mut self,
+        filter: &PostFilter,
+        page: i64,
+        per_page: i64,
+    ) -> RepoResult<PaginatedResult<Post>> {
+        Ok(filtered(filter)
+            .select(<Post as Selectable<Pg>>::construct_selection())
+            .paginate_with_total(page)
+            .per_page(per_page)
+            .load_and_count_pages(self.conn)?)
+    }
+
+    /// Full-text search over the title and body, see `search::search_posts`.
+    pub fn search(&mut self, query: &str, language: &str) -> RepoResult<Vec<PostMatch>> {
+        Ok(search::search_posts(self.conn, query, language)?)
+    }
+
+    /// Changes the title and/or body if the post is still at `version`, and records the result as a
+    /// new revision. The slug stays the same, so links keep working.
+    pub fn edit(&mut self, id: i32, version: i32, changes: &PostChanges) -> RepoResult<Post> {
+        self.conn.transaction(|conn| {
+            let post = PostRepository::new(conn).get(id)?;
+            if post.status == PostStatus::Archived {
+                return Err(RepoError::Conflict(
+                    "Archived posts can't be edited".to_string(),
+                ));
+            }
+            if changes.title.is_none() && changes.body.is_none() {
+                return Ok(post);
+            }
+
+            posts::table::update_with_version(conn, id, version, changes)?;
+            let post = PostRepository::new(conn).get(id)?;
+
+            let latest: Option<i32> = post_revisions::table
+                .filter(post_revisions::post_id.eq(id))
+                .order(post_revisions::revision.desc())
+                .select(post_revisions::revision)
+                .first(conn)
+                .optional()?;
+            add_revision(conn, &post, latest.unwrap_or(0) + 1)?;
+
+            Ok(post)
+        })
+    }
+
+    pub fn publish(&mut self, id: i32) -> RepoResult<Post> {
+        self.transition(
+            id,
+            "publish",
+            &[PostStatus::Draft, PostStatus::Scheduled],
+            |_| StatusChange {
+                status: PostStatus::Published,
+                publish_at: None,
+                published_at: Some(Utc::now()),
+            },
+        )
+    }
+
+    /// Publishes the post at `at`, see `publish_due`. Rescheduling a scheduled post moves it.
+    pub fn schedule(&mut self, id: i32, at: DateTime<Utc>) -> RepoResult<Post> {
+        self.transition(
+            id,
+            "schedule",
+            &[PostStatus::Draft, PostStatus::Scheduled],
+            |_| StatusChange {
+                status: PostStatus::Scheduled,
+                publish_at: Some(at),
+                published_at: None,
+            },
+        )
+    }
+
+    /// Takes a published or scheduled post back to draft.
+    pub fn unpublish(&mut self, id: i32) -> RepoResult<Post> {
+        self.transition(
+            id,
+            "unpublish",
+            &[PostStatus::Scheduled, PostStatus::Published],
+            |_| StatusChange {
+                status: PostStatus::Draft,
+                publish_at: None,
+                published_at: None,
+            },
+        )
+    }
+
+    pub fn archive(&mut self, id: i32) -> RepoResult<Post> {
+        self.transition(
+            id,
+            "archive",
+            &[
+                PostStatus::Draft,
+                PostStatus::Scheduled,
+                PostStatus::Published,
+            ],
+            |post| StatusChange {
+                status: PostStatus::Archived,
+                publish_at: None,
+                published_at: post.published_at,
+            },
+        )
+    }
+
+    /// Deletes the post and its revisions for good.
+    pub fn delete(&mut self, id: i32) -> RepoResult<()> {
+        let rows = diesel::delete(posts::table.find(id)).execute(self.conn)?;
+        super::expect_affected(rows)
+    }
+
+    /// Oldest first.
+    pub fn revisions(&mut self, id: i32) -> RepoResult<Vec<PostRevision>> {
+        Ok(post_revisions::table
+            .filter(post_revisions::post_id.eq(id))
+            .order(post_revisions::revision)
+            .select(PostRevision::as_select())
+            .load(self.conn)?)
+    }
+
+    /// Publishes the scheduled posts whose `publish_at` has passed, with `published_at` set to
+    /// their `publish_at`. Run this periodically.
+    pub fn publish_due(&mut self) -> RepoResult<Vec<Post>> {
+        Ok(diesel::update(
+            posts::table
+                .filter(posts::status.eq(PostStatus::Scheduled))
+                .filter(posts::publish_at.le(now.into_sql::<Timestamptz>().nullable())),
+        )
+        .set((
+            posts::status.eq(PostStatus::Published),
+            posts::published_at.eq(posts::publish_at),
+            posts::publish_at.eq(None::<DateTime<Utc>>),
+            posts::version.eq(posts::version + 1),
+        ))
+        .returning(Post::as_returning())
+        .get_results(self.conn)?)
+    }
+
+    /// Applies the change returned by `change` if the post is in one of the `from` states, and
+    /// bumps its version. Fails with `Conflict` otherwise.
+    fn transition(
+        &mut self,
+        id: i32,
+        action: &str,
+        from: &[PostStatus],
+        change: impl FnOnce(&Post) -> StatusChange,
+    ) -> RepoResult<Post> {
+        self.conn.transaction(|conn| {
+            let post = posts::table
+                .find(id)
+                .select(Post::as_select())
+                .for_update()
+                .get_result(conn)?;
+
+            if !from.contains(&post.status) {
+                return Err(RepoError::Conflict(format!(
+                    "Can't {} a {} post",
+                    action,
+                    post.status.as_pg_str()
+                )));
+            }
+
+            Ok(diesel::update(posts::table.find(id))
+                .set((change(&post), posts::version.eq(posts::version + 1)))
+                .returning(Post::as_returning())
+                .get_result(conn)?)
+        })
+    }
+}
+
+/// `PostRepository::list` on an async connection.
+pub async fn list_posts_async(
+    conn: &mut AsyncPgConnection,
+    filter: &PostFilter<'_>,
+    page: i64,
+) -> RepoResult<Vec<Post>> {
+    let query = filtered(filter).select(Post::as_select()).paginate(page);
+    Ok(diesel_async::RunQueryDsl::load(query, conn).await?)
+}
+
+/// `PostRepository::list_with_total` on an async connection.
+pub async fn list_posts_with_total_async(
+    conn: &mut AsyncPgConnection,
+    filter: &PostFilter<'_>,
+    page: i64,
+    per_page: i64,
+) -> RepoResult<PaginatedResult<Post>> {
+    Ok(filtered(filter)
+        .select(<Post as Selectable<Pg>>::construct_selection())
+        .paginate_with_total(page)
+        .per_page(per_page)
+        .load_and_count_pages_async(conn)
+        .await?)
+}
+
+fn filtered<'f>(filter: &PostFilter<'f>) -> posts::BoxedQuery<'f, Pg> {
+    let mut query = posts::table.into_boxed();
+
+    query = if filter.recent_first {
+        query.order((recent_first::<posts::table>(), posts::id.desc()))
+    } else {
+        query.order(posts::id.asc())
+    };
+
+    if let Some(status) = filter.status {
+        query = query.filter(posts::status.eq(status));
+    }
+
+    if let Some(title) = filter.title {
+        query = query.filter(posts::title.ilike(contains_pattern(title)));
+    }
+
+    query
+}
+
+fn add_revision(conn: &mut PgConnection, post: &Post, revision: i32) -> QueryResult<()> {
+    diesel::insert_into(post_revisions::table)
+        .values(&NewPostRevision {
+            post_id: post.id,
+            revision,
+            title: &post.title,
+            body: &post.body,
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// `"Pippi Långstrump!"` -> `"pippi-langstrump"`. Common accented letters are folded to ASCII, other
+/// characters become dashes.
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+
+    for c in title.chars().flat_map(char::to_lowercase) {
+        let folded = match c {
+            'a'..='z' | '0'..='9' => c,
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+            'ç' => 'c',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ñ' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ý' | 'ÿ' => 'y',
+            'ß' => {
+                slug.push_str("ss");
+                continue;
+            }
+            _ => '-',
+        };
+
+        if folded != '-' || !slug.ends_with('-') {
+            slug.push(folded);
+        }
+    }
+
+    let slug = slug.trim_matches('-');
+    if slug.is_empty() {
+        "post".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Inserts the post with the first free slug, see `PostRepository::create`. The unique index on
+/// `slug` decides which slugs are free, a post created concurrently can take the one found free
+/// here, so the next one is tried then.
+fn insert_with_unique_slug(conn: &mut PgConnection, title: &str, body: &str) -> QueryResult<Post> {
+    let base = slugify(title);
+
+    // The slug only contains [a-z0-9-], so it needs no escaping in the pattern
+    let taken: HashSet<String> = posts::table
+        .filter(
+            posts::slug
+                .eq(&base)
+                .or(posts::slug.like(format!("{}-%", base))),
+        )
+        .select(posts::slug)
+        .load(conn)?
+        .into_iter()
+        .collect();
+
+    let candidates = (1..)
+        .map(|n| match n {
+            1 => base.clone(),
+            n => format!("{}-{}", base, n),
+        })
+        .filter(|slug| !taken.contains(slug));
+
+    for slug in candidates {
+        // In a savepoint, so a taken slug doesn't abort the surrounding transaction
+        let inserted = conn.transaction(|conn| {
+            diesel::insert_into(posts::table)
+                .values(&NewPost {
+                    title,
+                    body,
+                    slug: &slug,
+                })
+                .returning(Post::as_returning())
+                .get_result(conn)
+        });
+
+        match inserted {
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info))
+                if info.constraint_name() == Some(SLUG_CONSTRAINT) => {}
+            result => return result,
+        }
+    }
+
+    unreachable!("there are always more slug candidates")
+}
+
+const SLUG_CONSTRAINT: &str = "posts_slug_key";
+
+#[cfg(test)]
+mod tests {
+    use super::slugify;
+
+    #[test]
+    fn lowercases_and_dashes_everything_else() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Rust 2024 -- edition "), "rust-2024-edition");
+        assert_eq!(slugify("a_b.c/d"), "a-b-c-d");
+    }
+
+    #[test]
+    fn folds_common_accents() {
+        assert_eq!(slugify("Pippi Långstrump!"), "pippi-langstrump");
+        assert_eq!(slugify("Crème Brûlée"), "creme-brulee");
+        assert_eq!(slugify("Straße"), "strasse");
+        assert_eq!(slugify("ÅÄÖ"), "aao");
+    }
+
+    #[test]
+    fn falls_back_for_titles_without_letters() {
+        assert_eq!(slugify(""), "post");
+        assert_eq!(slugify("!!!"), "post");
+        assert_eq!(slugify("日本語"), "post");
+    }
+}
@@ -0,0 +1,36 @@
+use diesel::pg::Pg;
+use diesel::prelude::*;
+
+use crate::models::{Item, Report};
+use crate::pagination::{PaginateWithTotal, PaginatedResult};
+use crate::schema::{items, reports};
+
+use super::RepoResult;
+
+pub struct ReportRepository<'a> {
+    conn: &'a mut PgConnection,
+}
+
+impl<'a> ReportRepository<'a> {
+    pub fn new(conn: &'a mut PgConnection) -> Self {
+        ReportRepository { conn }
+    }
+
+    /// Reports with their item, most played items first. `per_page` is at most 10.
+    pub fn list_with_items(
+        &mut self,
+        page: i64,
+        per_page: i64,
+    ) -> RepoResult<PaginatedResult<(Report, Item)>> {
+        Ok(reports::table
+            .inner_join(items::table)
+            .order_by((items::num_plays.desc(), reports::id.desc()))
+            .select((
+                <Report as Selectable<Pg>>::construct_selection(),
+                <Item as Selectable<Pg>>::construct_selection(),
+            ))
+            .paginate_with_total(page)
+            .per_page(per_page)
+            .load_and_count_pages(self.conn)?)
+    }
+}
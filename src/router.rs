@@ -0,0 +1,375 @@
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use diesel::prelude::*;
+use diesel::r2d2::PoolError;
+use diesel::sql_types::{Double, Nullable};
+
+use crate::cancel::CancelHandle;
+use crate::config::{retry, Backoff, ConnectError, DbConfig};
+use crate::pool::{connect_pool, connect_pool_lazily, PgPool, PoolConfig, PooledPgConnection};
+use crate::timeouts::{with_timeouts, Timeouts};
+
+/// How long a read waits for a replica connection before trying the next replica or the primary.
+const REPLICA_CHECKOUT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Sends reads to replicas and writes to the primary. Replicas lagging more than `max_lag` behind,
+/// or that can't be reached, are skipped until their next lag check; without a usable replica
+/// reads go to the primary too. Why a replica was skipped is logged as a warning through `log`.
+///
+/// ```ignore
+/// let mut session = router.session();
+/// let post = session.write(|conn| PostRepository::new(conn).publish(id))??;
+/// // Goes to the primary, the replicas may not have the published post yet
+/// let posts = session.read(|conn| PostRepository::new(conn).list(&filter, 1))??;
+/// ```
+pub struct DbRouter {
+    primary: PgPool,
+    /// How `primary` connects, for `CancelHandle`.
+    primary_config: DbConfig,
+    replicas: Vec<Replica>,
+    next: AtomicUsize,
+    max_lag: Duration,
+    lag_check_interval: Duration,
+    sticky_window: Duration,
+}
+
+struct Replica {
+    name: String,
+    config: DbConfig,
+    pool: PgPool,
+    health: Mutex<Health>,
+}
+
+#[derive(Clone, Copy)]
+struct Health {
+    checked_at: Option<Instant>,
+    usable: bool,
+}
+
+impl DbRouter {
+    /// A router without replicas, so everything goes to `primary` until replicas are added.
+    /// `config` is how `primary` connects, which a `CancelHandle` needs to cancel its queries.
+    pub fn new(config: DbConfig, primary: PgPool) -> Self {
+        DbRouter {
+            primary,
+            primary_config: config,
+            replicas: Vec::new(),
+            next: AtomicUsize::new(0),
+            max_lag: Duration::from_secs(5),
+            lag_check_interval: Duration::from_secs(1),
+            sticky_window: Duration::from_secs(5),
+        }
+    }
+
+    /// A replica that connects with `config`, named by its `DbConfig::target` in log messages.
+    pub fn with_replica(mut self, config: DbConfig, pool: PgPool) -> Self {
+        self.replicas.push(Replica {
+            name: config.target(),
+            config,
+            pool,
+            health: Mutex::new(Health {
+                checked_at: None,
+                usable: true,
+            }),
+        });
+        self
+    }
+
+    /// How far a replica may be behind the primary and still serve reads. 5 seconds by default.
+    pub fn max_lag(self, max_lag: Duration) -> Self {
+        DbRouter { max_lag, ..self }
+    }
+
+    /// How long a replica's lag check is trusted before it's checked again. 1 second by default.
+    pub fn lag_check_interval(self, lag_check_interval: Duration) -> Self {
+        DbRouter {
+            lag_check_interval,
+            ..self
+        }
+    }
+
+    /// How long reads in a session go to the primary after a write, so they see the write even if
+    /// the replicas haven't replayed it yet. 5 seconds by default.
+    pub fn sticky_window(self, sticky_window: Duration) -> Self {
+        DbRouter {
+            sticky_window,
+            ..self
+        }
+    }
+
+    pub fn primary(&self) -> &PgPool {
+        &self.primary
+    }
+
+    /// Routes the queries of one request or unit of work.
+    pub fn session(&self) -> Session<'_> {
+        Session {
+            router: self,
+            last_write: None,
+            timeouts: Timeouts::default(),
+            cancel_handle: None,
+        }
+    }
+
+    /// A connection to a replica that is caught up, taking turns between the replicas, or to the
+    /// primary if there is none.
+    pub fn reader(&self) -> Result<PooledPgConnection, PoolError> {
+        self.route_read().map(|(conn, _)| conn)
+    }
+
+    pub fn writer(&self) -> Result<PooledPgConnection, PoolError> {
+        self.primary.get()
+    }
+
+    /// `reader`, with the config of the pool the connection came from.
+    fn route_read(&self) -> Result<(PooledPgConnection, &DbConfig), PoolError> {
+        let count = self.replicas.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+
+        for i in 0..count {
+            let replica = &self.replicas[(start + i) % count];
+            if let Some(conn) = self.checked_out(replica) {
+                return Ok((conn, &replica.config));
+            }
+        }
+
+        Ok((self.primary.get()?, &self.primary_config))
+    }
+
+    /// A connection to `replica` if it's usable, checking its lag first if the last check is older
+    /// than `lag_check_interval`.
+    fn checked_out(&self, replica: &Replica) -> Option<PooledPgConnection> {
+        let health = *replica.health.lock().unwrap();
+        let fresh = health
+            .checked_at
+            .is_some_and(|at| at.elapsed() < self.lag_check_interval);
+
+        if fresh && !health.usable {
+            return None;
+        }
+
+        let mut conn = match replica.pool.get_timeout(REPLICA_CHECKOUT_TIMEOUT) {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::warn!("Replica {} is unavailable: {}", replica.name, e);
+                replica.set_health(false);
+                return None;
+            }
+        };
+
+        if fresh {
+            return Some(conn);
+        }
+
+        let usable = lag_is_acceptable(&replica.name, replica_lag(&mut conn), self.max_lag);
+        replica.set_health(usable);
+
+        usable.then_some(conn)
+    }
+}
+
+/// Whether a replica whose lag check came back with `lag` may serve reads, logging why not.
+fn lag_is_acceptable(name: &str, lag: QueryResult<Option<Duration>>, max_lag: Duration) -> bool {
+    match lag {
+        Ok(Some(lag)) if lag <= max_lag => true,
+        Ok(Some(lag)) => {
+            log::warn!("Replica {} is {:?} behind, skipping it", name, lag);
+            false
+        }
+        Ok(None) => {
+            log::warn!("Replica {} hasn't replayed anything yet, skipping it", name);
+            false
+        }
+        Err(e) => {
+            log::warn!("Checking the lag of replica {} failed: {}", name, e);
+            false
+        }
+    }
+}
+
+impl Replica {
+    fn set_health(&self, usable: bool) {
+        *self.health.lock().unwrap() = Health {
+            checked_at: Some(Instant::now()),
+            usable,
+        };
+    }
+}
+
+/// Routes reads to a replica, unless this session wrote less than `sticky_window` ago.
+pub struct Session<'r> {
+    router: &'r DbRouter,
+    last_write: Option<Instant>,
+    timeouts: Timeouts,
+    cancel_handle: Option<CancelHandle>,
+}
+
+impl Session<'_> {
+    /// Applies `timeouts` to everything run through `read` and `write`.
+    pub fn with_timeouts(self, timeouts: Timeouts) -> Self {
+        Session { timeouts, ..self }
+    }
+
+    /// Lets `handle` cancel whatever runs through `read` and `write`.
+    pub fn cancellable_by(self, handle: CancelHandle) -> Self {
+        Session {
+            cancel_handle: Some(handle),
+            ..self
+        }
+    }
+
+    /// A connection for reads. Don't write with it: replicas refuse writes, and writes on the
+    /// primary don't start the sticky window. Prefer `read`, which enforces this.
+    pub fn reader(&mut self) -> Result<PooledPgConnection, PoolError> {
+        if self.is_sticky() {
+            self.router.writer()
+        } else {
+            self.router.reader()
+        }
+    }
+
+    /// A connection to the primary. Reads in this session go to the primary for the next
+    /// `sticky_window`.
+    pub fn writer(&mut self) -> Result<PooledPgConnection, PoolError> {
+        let conn = self.router.writer()?;
+        self.last_write = Some(Instant::now());
+        Ok(conn)
+    }
+
+    /// Runs `f` in a read-only transaction on a `reader` connection, so it fails the same way
+    /// whether it ends up on a replica or on the primary if it tries to write. Fails with the
+    /// outer error if no connection is available.
+    pub fn read<T, E, F>(&mut self, f: F) -> Result<Result<T, E>, PoolError>
+    where
+        F: FnOnce(&mut PgConnection) -> Result<T, E>,
+        E: From<diesel::result::Error>,
+    {
+        let (mut conn, config) = if self.is_sticky() {
+            (self.router.writer()?, &self.router.primary_config)
+        } else {
+            self.router.route_read()?
+        };
+
+        let timeouts = self.timeouts;
+        let run = |conn: &mut PgConnection| {
+            conn.build_transaction().read_only().run(|conn| {
+                timeouts.set_local(conn)?;
+                f(conn)
+            })
+        };
+
+        Ok(match &self.cancel_handle {
+            Some(handle) => handle.run(config, &mut conn, run),
+            None => run(&mut conn),
+        })
+    }
+
+    /// Runs `f` on the primary, see `writer`. `f` can start its own transactions, which become
+    /// savepoints if the session has timeouts, since those need a transaction to apply to.
+    pub fn write<T, E, F>(&mut self, f: F) -> Result<Result<T, E>, PoolError>
+    where
+        F: FnOnce(&mut PgConnection) -> Result<T, E>,
+        E: From<diesel::result::Error>,
+    {
+        let mut conn = self.writer()?;
+
+        let timeouts = self.timeouts;
+        let run = |conn: &mut PgConnection| {
+            if timeouts.is_empty() {
+                f(conn)
+            } else {
+                with_timeouts(conn, &timeouts, f)
+            }
+        };
+
+        Ok(match &self.cancel_handle {
+            Some(handle) => handle.run(&self.router.primary_config, &mut conn, run),
+            None => run(&mut conn),
+        })
+    }
+
+    fn is_sticky(&self) -> bool {
+        self.last_write
+            .is_some_and(|at| at.elapsed() < self.router.sticky_window)
+    }
+}
+
+/// How far behind its primary the server is, from `pg_last_xact_replay_timestamp()`. Zero if it
+/// isn't a replica, or if its WAL receiver is running and it has replayed everything received,
+/// since the replay timestamp stays put while the primary is idle. A replica whose receiver is
+/// disconnected can't tell how much it's missing, so it only gets the replay timestamp lag, which
+/// grows until it reconnects. `None` if it hasn't replayed a transaction since it started, so its
+/// lag is unknown.
+pub fn replica_lag(conn: &mut PgConnection) -> QueryResult<Option<Duration>> {
+    // Without pg_read_all_stats the receiver's row only shows its pid, so a NULL status counts
+    let lag = diesel::select(diesel::dsl::sql::<Nullable<Double>>(
+        "CASE \
+            WHEN NOT pg_is_in_recovery() THEN 0 \
+            WHEN pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() \
+                AND EXISTS (SELECT FROM pg_stat_wal_receiver \
+                            WHERE coalesce(status, 'streaming') = 'streaming') THEN 0 \
+            ELSE EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp())::float8 \
+        END",
+    ))
+    .get_result::<Option<f64>>(conn)?;
+
+    Ok(lag.map(|secs| Duration::from_secs_f64(secs.max(0.0))))
+}
+
+/// A router with the primary from `DbConfig::load` and a replica for each URL in the
+/// comma-separated `DATABASE_REPLICA_URLS`, all with `PoolConfig::from_env`. The sticky window and
+/// lag limit can be set with `DATABASE_STICKY_WINDOW_MS` and `DATABASE_MAX_REPLICA_LAG_MS`.
+pub fn establish_router() -> Result<DbRouter, ConnectError> {
+    let config = DbConfig::load()?;
+    let pool_config = PoolConfig::from_env()?;
+    let backoff = Backoff::default();
+
+    let primary = retry(&backoff, || connect_pool(&config, &pool_config))?;
+    let mut router = DbRouter::new(config, primary);
+
+    let urls = env::var("DATABASE_REPLICA_URLS").unwrap_or_default();
+    for url in urls.split(',').map(str::trim).filter(|url| !url.is_empty()) {
+        // Connects lazily, a replica that's down shouldn't keep the app from starting
+        let replica = DbConfig::from_url(url)?;
+        let pool = connect_pool_lazily(&replica, &pool_config)?;
+        router = router.with_replica(replica, pool);
+    }
+
+    if let Some(ms) = env_millis("DATABASE_STICKY_WINDOW_MS")? {
+        router = router.sticky_window(ms);
+    }
+    if let Some(ms) = env_millis("DATABASE_MAX_REPLICA_LAG_MS")? {
+        router = router.max_lag(ms);
+    }
+
+    Ok(router)
+}
+
+fn env_millis(name: &str) -> Result<Option<Duration>, ConnectError> {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(|ms| Some(Duration::from_millis(ms)))
+            .map_err(|_| ConnectError::Config(format!("{} has an invalid value", name))),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_lagging_and_unchecked_replicas() {
+        let max_lag = Duration::from_secs(5);
+        assert!(lag_is_acceptable("replica", Ok(Some(Duration::ZERO)), max_lag));
+        assert!(lag_is_acceptable("replica", Ok(Some(max_lag)), max_lag));
+        assert!(!lag_is_acceptable("replica", Ok(Some(Duration::from_secs(6))), max_lag));
+        assert!(!lag_is_acceptable("replica", Ok(None), max_lag));
+        assert!(!lag_is_acceptable("replica", Err(diesel::result::Error::NotFound), max_lag));
+    }
+}
@@ -1,10 +1,31 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "invite_kind"))]
+    pub struct InviteKind;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "post_status"))]
+    pub struct PostStatus;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "regconfig", schema = "pg_catalog"))]
+    pub struct Regconfig;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
 diesel::table! {
     address (id) {
         id -> Int4,
         value -> Text,
         author_id -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -12,6 +33,9 @@ diesel::table! {
     authors (id) {
         id -> Int4,
         name -> Varchar,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -19,6 +43,10 @@ diesel::table! {
     books (id) {
         id -> Int4,
         title -> Varchar,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+        version -> Int4,
     }
 }
 
@@ -26,14 +54,22 @@ diesel::table! {
     books_authors (book_id, author_id) {
         book_id -> Int4,
         author_id -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::InviteKind;
+
     invites (id) {
         id -> Int8,
-        kind -> Varchar,
+        kind -> InviteKind,
         json -> Jsonb,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -42,24 +78,59 @@ diesel::table! {
         id -> Int4,
         title -> Text,
         num_plays -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Regconfig;
+    use super::sql_types::Tsvector;
+
     pages (id) {
         id -> Int4,
         page_number -> Int4,
         content -> Text,
         book_id -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+        language -> Regconfig,
+        search -> Tsvector,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Regconfig;
+    use super::sql_types::Tsvector;
+    use super::sql_types::PostStatus;
+
     posts (id) {
         id -> Int4,
         title -> Varchar,
         body -> Text,
-        published -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        version -> Int4,
+        language -> Regconfig,
+        search -> Tsvector,
+        status -> PostStatus,
+        publish_at -> Nullable<Timestamptz>,
+        published_at -> Nullable<Timestamptz>,
+        slug -> Text,
+    }
+}
+
+diesel::table! {
+    post_revisions (post_id, revision) {
+        post_id -> Int4,
+        revision -> Int4,
+        title -> Varchar,
+        body -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -68,6 +139,8 @@ diesel::table! {
         id -> Int4,
         title -> Text,
         item_id -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -75,6 +148,7 @@ diesel::joinable!(address -> authors (author_id));
 diesel::joinable!(books_authors -> authors (author_id));
 diesel::joinable!(books_authors -> books (book_id));
 diesel::joinable!(pages -> books (book_id));
+diesel::joinable!(post_revisions -> posts (post_id));
 diesel::joinable!(reports -> items (item_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
@@ -85,6 +159,7 @@ diesel::allow_tables_to_appear_in_same_query!(
     invites,
     items,
     pages,
+    post_revisions,
     posts,
     reports,
 );
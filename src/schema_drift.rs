@@ -0,0 +1,476 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use diesel::expression::Expression;
+use diesel::pg::{Pg, PgMetadataLookup, PgTypeMetadata};
+use diesel::prelude::*;
+use diesel::query_builder::QueryFragment;
+use diesel::sql_types::{is_nullable, BigInt, Bool, HasSqlType, SqlType, Text};
+use diesel::{debug_query, Column, JoinTo, Table};
+
+use crate::schema::*;
+
+/// A table as `schema.rs` declares it.
+pub struct TableDef {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    pub primary_key: Vec<String>,
+}
+
+pub struct ColumnDef {
+    pub name: &'static str,
+    pub nullable: bool,
+    /// Looks the type up on the connection, since custom types like `post_status` have no fixed
+    /// oid.
+    sql_type: fn(&mut (dyn PgMetadataLookup + 'static)) -> PgTypeMetadata,
+}
+
+/// A `joinable!`, which Diesel only generates for foreign keys on a single column.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ForeignKeyDef {
+    pub table: String,
+    pub column: String,
+    pub references: String,
+}
+
+/// The tables and `joinable!`s of `schema.rs`, read from the compiled definitions. Add new tables
+/// and joins here when regenerating it; a table missing here is reported as only in the database.
+pub fn expected_schema() -> (Vec<TableDef>, Vec<ForeignKeyDef>) {
+    let tables = vec![
+        table_def::<address::table>(),
+        table_def::<authors::table>(),
+        table_def::<books::table>(),
+        table_def::<books_authors::table>(),
+        table_def::<invites::table>(),
+        table_def::<items::table>(),
+        table_def::<pages::table>(),
+        table_def::<post_revisions::table>(),
+        table_def::<posts::table>(),
+        table_def::<reports::table>(),
+    ];
+
+    let foreign_keys = vec![
+        foreign_key_def::<address::table, authors::table>(),
+        foreign_key_def::<books_authors::table, authors::table>(),
+        foreign_key_def::<books_authors::table, books::table>(),
+        foreign_key_def::<pages::table, books::table>(),
+        foreign_key_def::<post_revisions::table, posts::table>(),
+        foreign_key_def::<reports::table, items::table>(),
+    ];
+
+    (tables, foreign_keys)
+}
+
+pub fn table_def<T>() -> TableDef
+where
+    T: Table + Default,
+    T::AllColumns: ColumnDefs,
+    T::PrimaryKey: QueryFragment<Pg>,
+{
+    // Renders as `"table"."column"`, or a list of them for composite keys
+    let identifiers = quoted_identifiers(&T::default().primary_key());
+
+    TableDef {
+        name: identifiers[0].clone(),
+        columns: T::AllColumns::column_defs(),
+        primary_key: identifiers.into_iter().skip(1).step_by(2).collect(),
+    }
+}
+
+pub fn foreign_key_def<Child, Parent>() -> ForeignKeyDef
+where
+    Child: JoinTo<Parent>,
+    Child::OnClause: QueryFragment<Pg>,
+    Parent: Default,
+{
+    // Renders as `"child"."fk" = "parent"."id"`
+    let (_, on_clause) = Child::join_target(Parent::default());
+    let identifiers = quoted_identifiers(&on_clause);
+
+    ForeignKeyDef {
+        table: identifiers[0].clone(),
+        column: identifiers[1].clone(),
+        references: identifiers[2].clone(),
+    }
+}
+
+fn quoted_identifiers<T: QueryFragment<Pg>>(fragment: &T) -> Vec<String> {
+    debug_query::<Pg, _>(fragment)
+        .to_string()
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .map(str::to_string)
+        .collect()
+}
+
+/// The columns of a `table!`, implemented for the `all_columns` tuples.
+pub trait ColumnDefs {
+    fn column_defs() -> Vec<ColumnDef>;
+}
+
+/// Whether a Diesel `SqlType` is `Nullable<_>`.
+pub trait Nullability {
+    const NULLABLE: bool;
+}
+
+impl Nullability for is_nullable::NotNull {
+    const NULLABLE: bool = false;
+}
+
+impl Nullability for is_nullable::IsNullable {
+    const NULLABLE: bool = true;
+}
+
+fn column_def<C>() -> ColumnDef
+where
+    C: Column,
+    C::SqlType: SqlType,
+    <C::SqlType as SqlType>::IsNull: Nullability,
+    Pg: HasSqlType<C::SqlType>,
+{
+    ColumnDef {
+        name: C::NAME,
+        nullable: <<C::SqlType as SqlType>::IsNull as Nullability>::NULLABLE,
+        sql_type: <Pg as HasSqlType<C::SqlType>>::metadata,
+    }
+}
+
+macro_rules! impl_column_defs {
+    ($($column:ident),+) => {
+        impl<$($column),+> ColumnDefs for ($($column,)+)
+        where
+            $(
+                $column: Column,
+                <$column as Expression>::SqlType: SqlType,
+                <<$column as Expression>::SqlType as SqlType>::IsNull: Nullability,
+                Pg: HasSqlType<<$column as Expression>::SqlType>,
+            )+
+        {
+            fn column_defs() -> Vec<ColumnDef> {
+                vec![$(column_def::<$column>()),+]
+            }
+        }
+    };
+}
+
+impl_column_defs!(A);
+impl_column_defs!(A, B);
+impl_column_defs!(A, B, C);
+impl_column_defs!(A, B, C, D);
+impl_column_defs!(A, B, C, D, E);
+impl_column_defs!(A, B, C, D, E, F);
+impl_column_defs!(A, B, C, D, E, F, G);
+impl_column_defs!(A, B, C, D, E, F, G, H);
+impl_column_defs!(A, B, C, D, E, F, G, H, I);
+impl_column_defs!(A, B, C, D, E, F, G, H, I, J);
+impl_column_defs!(A, B, C, D, E, F, G, H, I, J, K);
+impl_column_defs!(A, B, C, D, E, F, G, H, I, J, K, L);
+impl_column_defs!(A, B, C, D, E, F, G, H, I, J, K, L, M);
+impl_column_defs!(A, B, C, D, E, F, G, H, I, J, K, L, M, N);
+impl_column_defs!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O);
+impl_column_defs!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
+
+/// A difference between `schema.rs` and the database. Displays as a diff line: `-` for what only
+/// `schema.rs` has, `+` for what only the database has and `~` for what differs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    MissingTable(String),
+    ExtraTable(String),
+    MissingColumn {
+        table: String,
+        column: String,
+    },
+    ExtraColumn {
+        table: String,
+        column: String,
+        sql_type: String,
+    },
+    ColumnType {
+        table: String,
+        column: String,
+        expected: String,
+        actual: String,
+    },
+    Nullability {
+        table: String,
+        column: String,
+        expected: bool,
+    },
+    PrimaryKey {
+        table: String,
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+    MissingForeignKey(ForeignKeyDef),
+    ExtraForeignKey(ForeignKeyDef),
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let null = |nullable: bool| if nullable { "null" } else { "not null" };
+
+        match self {
+            Drift::MissingTable(table) => write!(f, "- table {}", table),
+            Drift::ExtraTable(table) => write!(f, "+ table {}", table),
+            Drift::MissingColumn { table, column } => write!(f, "- column {}.{}", table, column),
+            Drift::ExtraColumn {
+                table,
+                column,
+                sql_type,
+            } => write!(f, "+ column {}.{} {}", table, column, sql_type),
+            Drift::ColumnType {
+                table,
+                column,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "~ column {}.{}: {} -> {}",
+                table, column, expected, actual
+            ),
+            Drift::Nullability {
+                table,
+                column,
+                expected,
+            } => write!(
+                f,
+                "~ column {}.{}: {} -> {}",
+                table,
+                column,
+                null(*expected),
+                null(!expected)
+            ),
+            Drift::PrimaryKey {
+                table,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "~ primary key of {}: ({}) -> ({})",
+                table,
+                expected.join(", "),
+                actual.join(", ")
+            ),
+            Drift::MissingForeignKey(fk) => write!(
+                f,
+                "- foreign key {}.{} -> {}",
+                fk.table, fk.column, fk.references
+            ),
+            Drift::ExtraForeignKey(fk) => write!(
+                f,
+                "+ foreign key {}.{} -> {}",
+                fk.table, fk.column, fk.references
+            ),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct DbColumn {
+    #[diesel(sql_type = Text)]
+    table_name: String,
+    #[diesel(sql_type = Text)]
+    column_name: String,
+    #[diesel(sql_type = BigInt)]
+    type_oid: i64,
+    #[diesel(sql_type = Text)]
+    type_name: String,
+    #[diesel(sql_type = Bool)]
+    nullable: bool,
+}
+
+#[derive(QueryableByName)]
+struct DbKeyColumn {
+    #[diesel(sql_type = Text)]
+    table_name: String,
+    #[diesel(sql_type = Text)]
+    column_name: String,
+}
+
+#[derive(QueryableByName)]
+struct DbForeignKey {
+    #[diesel(sql_type = Text)]
+    table_name: String,
+    #[diesel(sql_type = Text)]
+    column_name: String,
+    #[diesel(sql_type = Text)]
+    referenced_table: String,
+}
+
+/// Compares `schema.rs` with the tables in the `public` schema, except Diesel's migrations table.
+/// Returns nothing if they match.
+pub fn check_schema(conn: &mut PgConnection) -> QueryResult<Vec<Drift>> {
+    let (tables, foreign_keys) = expected_schema();
+    compare_schema(conn, &tables, &foreign_keys)
+}
+
+/// `check_schema` against other definitions, e.g. a subset of the tables.
+pub fn compare_schema(
+    conn: &mut PgConnection,
+    tables: &[TableDef],
+    foreign_keys: &[ForeignKeyDef],
+) -> QueryResult<Vec<Drift>> {
+    let mut db_tables = BTreeMap::<String, Vec<DbColumn>>::new();
+    for column in diesel::sql_query(
+        "SELECT c.relname::text AS table_name, a.attname::text AS column_name, \
+                a.atttypid::int8 AS type_oid, format_type(a.atttypid, a.atttypmod) AS type_name, \
+                NOT a.attnotnull AS nullable \
+         FROM pg_class c \
+         JOIN pg_namespace n ON n.oid = c.relnamespace \
+         JOIN pg_attribute a ON a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped \
+         WHERE n.nspname = 'public' AND c.relkind IN ('r', 'p') \
+           AND c.relname <> '__diesel_schema_migrations' \
+         ORDER BY c.relname, a.attnum",
+    )
+    .load::<DbColumn>(conn)?
+    {
+        db_tables
+            .entry(column.table_name.clone())
+            .or_default()
+            .push(column);
+    }
+
+    let mut db_primary_keys = BTreeMap::<String, Vec<String>>::new();
+    for key in diesel::sql_query(
+        "SELECT c.relname::text AS table_name, a.attname::text AS column_name \
+         FROM pg_index i \
+         JOIN pg_class c ON c.oid = i.indrelid \
+         JOIN pg_namespace n ON n.oid = c.relnamespace \
+         JOIN pg_attribute a ON a.attrelid = c.oid AND a.attnum = ANY (i.indkey) \
+         WHERE n.nspname = 'public' AND i.indisprimary \
+         ORDER BY c.relname, array_position(i.indkey::int2[], a.attnum)",
+    )
+    .load::<DbKeyColumn>(conn)?
+    {
+        db_primary_keys
+            .entry(key.table_name)
+            .or_default()
+            .push(key.column_name);
+    }
+
+    let mut drift = Vec::new();
+
+    for table in tables {
+        let Some(db_columns) = db_tables.remove(&table.name) else {
+            drift.push(Drift::MissingTable(table.name.clone()));
+            continue;
+        };
+
+        for column in &table.columns {
+            let Some(db_column) = db_columns.iter().find(|c| c.column_name == column.name) else {
+                drift.push(Drift::MissingColumn {
+                    table: table.name.clone(),
+                    column: column.name.to_string(),
+                });
+                continue;
+            };
+
+            // `None` for a custom type that doesn't exist in the database
+            let expected_oid = (column.sql_type)(conn).oid().ok();
+            if expected_oid != Some(diesel_oid(db_column.type_oid)) {
+                drift.push(Drift::ColumnType {
+                    table: table.name.clone(),
+                    column: column.name.to_string(),
+                    expected: match expected_oid {
+                        Some(oid) => type_name(conn, oid)?,
+                        None => "unknown type".to_string(),
+                    },
+                    actual: db_column.type_name.clone(),
+                });
+            }
+
+            if column.nullable != db_column.nullable {
+                drift.push(Drift::Nullability {
+                    table: table.name.clone(),
+                    column: column.name.to_string(),
+                    expected: column.nullable,
+                });
+            }
+        }
+
+        for db_column in &db_columns {
+            if !table
+                .columns
+                .iter()
+                .any(|c| c.name == db_column.column_name)
+            {
+                drift.push(Drift::ExtraColumn {
+                    table: table.name.clone(),
+                    column: db_column.column_name.clone(),
+                    sql_type: db_column.type_name.clone(),
+                });
+            }
+        }
+
+        let db_primary_key = db_primary_keys.remove(&table.name).unwrap_or_default();
+        if table.primary_key != db_primary_key {
+            drift.push(Drift::PrimaryKey {
+                table: table.name.clone(),
+                expected: table.primary_key.clone(),
+                actual: db_primary_key,
+            });
+        }
+    }
+
+    for table in db_tables.into_keys() {
+        drift.push(Drift::ExtraTable(table));
+    }
+
+    // Single column foreign keys between the compared tables, like `diesel print-schema` generates
+    let names = tables.iter().map(|t| t.name.as_str()).collect::<Vec<_>>();
+    let db_foreign_keys = diesel::sql_query(
+        "SELECT c.relname::text AS table_name, a.attname::text AS column_name, \
+                r.relname::text AS referenced_table \
+         FROM pg_constraint k \
+         JOIN pg_class c ON c.oid = k.conrelid \
+         JOIN pg_namespace n ON n.oid = c.relnamespace \
+         JOIN pg_class r ON r.oid = k.confrelid \
+         JOIN pg_attribute a ON a.attrelid = c.oid AND a.attnum = k.conkey[1] \
+         WHERE n.nspname = 'public' AND k.contype = 'f' AND cardinality(k.conkey) = 1",
+    )
+    .load::<DbForeignKey>(conn)?
+    .into_iter()
+    .map(|fk| ForeignKeyDef {
+        table: fk.table_name,
+        column: fk.column_name,
+        references: fk.referenced_table,
+    })
+    .filter(|fk| names.contains(&fk.table.as_str()) && names.contains(&fk.references.as_str()))
+    .collect::<Vec<_>>();
+
+    for fk in foreign_keys {
+        if !db_foreign_keys.contains(fk) {
+            drift.push(Drift::MissingForeignKey(fk.clone()));
+        }
+    }
+    for fk in db_foreign_keys {
+        if !foreign_keys.contains(&fk) {
+            drift.push(Drift::ExtraForeignKey(fk));
+        }
+    }
+
+    Ok(drift)
+}
+
+/// The oid Diesel uses for a column of type `oid`: `Varchar` and `Bpchar` are aliases of `Text` in
+/// Diesel, so `varchar` and `char` columns are `text` to it.
+fn diesel_oid(oid: i64) -> u32 {
+    const TEXT: u32 = 25;
+    const TEXT_ARRAY: u32 = 1009;
+
+    match oid as u32 {
+        1042 | 1043 => TEXT,
+        1014 | 1015 => TEXT_ARRAY,
+        oid => oid,
+    }
+}
+
+fn type_name(conn: &mut PgConnection, oid: u32) -> QueryResult<String> {
+    diesel::select(
+        diesel::dsl::sql::<Text>("format_type(")
+            .bind::<BigInt, _>(oid as i64)
+            .sql("::oid, NULL)"),
+    )
+    .get_result(conn)
+}
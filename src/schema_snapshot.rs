@@ -0,0 +1,124 @@
+use std::collections::BTreeSet;
+
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+
+/// The schema of a database as one line per object, to compare it before and after migrating.
+/// Covers tables, views and sequences, their columns, constraints, indexes and triggers, enums,
+/// domains, functions and extensions in every non-system schema. Objects that belong to an
+/// extension only show up as the extension, and Diesel's migrations table is left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaSnapshot {
+    lines: BTreeSet<String>,
+}
+
+#[derive(QueryableByName)]
+struct Line {
+    #[diesel(sql_type = Text)]
+    line: String,
+}
+
+// Relations not owned by an extension, outside the system schemas
+const RELATIONS: &str = "\
+    SELECT c.oid, c.relkind FROM pg_class c \
+    JOIN pg_namespace n ON n.oid = c.relnamespace \
+    WHERE n.nspname NOT IN ('pg_catalog', 'information_schema') AND n.nspname NOT LIKE 'pg_toast%' \
+      AND c.relname <> '__diesel_schema_migrations' \
+      AND NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = c.oid AND d.deptype = 'e')";
+
+const NOT_SYSTEM: &str =
+    "n.nspname NOT IN ('pg_catalog', 'information_schema') AND n.nspname NOT LIKE 'pg_toast%'";
+
+const NOT_IN_EXTENSION: &str =
+    "NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = o.oid AND d.deptype = 'e')";
+
+impl SchemaSnapshot {
+    pub fn take(conn: &mut PgConnection) -> QueryResult<Self> {
+        let queries = [
+            "SELECT 'extension ' || extname AS line FROM pg_extension WHERE extname <> 'plpgsql'"
+                .to_string(),
+            format!(
+                "SELECT 'schema ' || n.nspname AS line FROM pg_namespace n \
+                 WHERE {NOT_SYSTEM} AND n.nspname <> 'public'"
+            ),
+            format!(
+                "SELECT CASE r.relkind \
+                     WHEN 'r' THEN 'table ' WHEN 'p' THEN 'table ' WHEN 'S' THEN 'sequence ' \
+                     WHEN 'v' THEN 'view ' WHEN 'm' THEN 'materialized view ' ELSE NULL END \
+                     || r.oid::regclass::text \
+                     || CASE WHEN r.relkind IN ('v', 'm') \
+                        THEN ' ' || md5(pg_get_viewdef(r.oid)) ELSE '' END AS line \
+                 FROM ({RELATIONS}) r WHERE r.relkind IN ('r', 'p', 'S', 'v', 'm')"
+            ),
+            format!(
+                "SELECT format('column %s.%I %s', r.oid::regclass, a.attname, \
+                            format_type(a.atttypid, a.atttypmod)) \
+                     || CASE WHEN a.attnotnull THEN ' not null' ELSE '' END \
+                     || CASE WHEN a.attgenerated <> '' THEN ' generated ' ELSE ' default ' END \
+                     || coalesce(pg_get_expr(ad.adbin, ad.adrelid), 'none') AS line \
+                 FROM ({RELATIONS}) r \
+                 JOIN pg_attribute a ON a.attrelid = r.oid AND a.attnum > 0 AND NOT a.attisdropped \
+                 LEFT JOIN pg_attrdef ad ON ad.adrelid = r.oid AND ad.adnum = a.attnum \
+                 WHERE r.relkind IN ('r', 'p', 'v', 'm')"
+            ),
+            format!(
+                "SELECT format('constraint %s %I %s', r.oid::regclass, k.conname, \
+                            pg_get_constraintdef(k.oid)) AS line \
+                 FROM ({RELATIONS}) r JOIN pg_constraint k ON k.conrelid = r.oid"
+            ),
+            format!(
+                "SELECT 'index ' || pg_get_indexdef(i.indexrelid) AS line \
+                 FROM ({RELATIONS}) r JOIN pg_index i ON i.indrelid = r.oid"
+            ),
+            format!(
+                "SELECT 'trigger ' || pg_get_triggerdef(t.oid) AS line \
+                 FROM ({RELATIONS}) r JOIN pg_trigger t ON t.tgrelid = r.oid \
+                 WHERE NOT t.tgisinternal"
+            ),
+            format!(
+                "SELECT 'enum ' || o.oid::regtype::text || ' (' \
+                     || (SELECT string_agg(e.enumlabel, ', ' ORDER BY e.enumsortorder) \
+                         FROM pg_enum e WHERE e.enumtypid = o.oid) || ')' AS line \
+                 FROM pg_type o JOIN pg_namespace n ON n.oid = o.typnamespace \
+                 WHERE o.typtype = 'e' AND {NOT_SYSTEM} AND {NOT_IN_EXTENSION}"
+            ),
+            format!(
+                "SELECT 'domain ' || o.oid::regtype::text || ' ' \
+                     || format_type(o.typbasetype, o.typtypmod) AS line \
+                 FROM pg_type o JOIN pg_namespace n ON n.oid = o.typnamespace \
+                 WHERE o.typtype = 'd' AND {NOT_SYSTEM} AND {NOT_IN_EXTENSION}"
+            ),
+            // Hashed, a changed body shows up as the function being replaced
+            format!(
+                "SELECT 'function ' || o.oid::regprocedure::text || ' ' \
+                     || md5(pg_get_functiondef(o.oid)) AS line \
+                 FROM pg_proc o JOIN pg_namespace n ON n.oid = o.pronamespace \
+                 WHERE o.prokind IN ('f', 'p') AND {NOT_SYSTEM} AND {NOT_IN_EXTENSION}"
+            ),
+        ];
+
+        let mut lines = BTreeSet::new();
+        for query in queries {
+            for row in diesel::sql_query(query).load::<Line>(conn)? {
+                lines.insert(row.line);
+            }
+        }
+
+        Ok(SchemaSnapshot { lines })
+    }
+
+    /// What changed from `self` to `other`, as diff lines: `-` for what only `self` has and `+`
+    /// for what only `other` has. Empty if they are the same.
+    pub fn diff(&self, other: &SchemaSnapshot) -> Vec<String> {
+        let removed = self
+            .lines
+            .difference(&other.lines)
+            .map(|l| format!("- {}", l));
+        let added = other
+            .lines
+            .difference(&self.lines)
+            .map(|l| format!("+ {}", l));
+
+        removed.chain(added).collect()
+    }
+}
@@ -0,0 +1,179 @@
+use diesel::expression::{
+    is_aggregate, AppearsOnTable, AsExpression, SelectableExpression, ValidGrouping,
+};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::sql_types::{Bool, SqlType, Text};
+use diesel::{define_sql_function, infix_operator};
+use serde::Serialize;
+
+use crate::models::{Book, Post, PostStatus};
+use crate::nested::{Nest, Node};
+use crate::schema::sql_types::{Regconfig, Tsvector};
+use crate::schema::{books, pages, posts};
+use crate::soft_delete::SoftDelete;
+
+/// The text search configuration used when none is given. Matches the column default.
+pub const DEFAULT_CONFIG: &str = "simple";
+
+#[derive(SqlType, QueryId)]
+#[diesel(postgres_type(name = "tsquery", schema = "pg_catalog"))]
+pub struct Tsquery;
+
+define_sql_function! {
+    /// Parses `&`, `|`, `!` and `:*` prefix operators, fails on invalid syntax.
+    fn to_tsquery(config: Regconfig, query: Text) -> Tsquery;
+}
+
+define_sql_function! {
+    /// All words must match, punctuation is ignored.
+    fn plainto_tsquery(config: Regconfig, query: Text) -> Tsquery;
+}
+
+define_sql_function! {
+    /// Search engine syntax: `"quoted phrases"`, `or` and `-excluded`. Never fails, so use this for
+    /// user input.
+    fn websearch_to_tsquery(config: Regconfig, query: Text) -> Tsquery;
+}
+
+define_sql_function! {
+    fn ts_rank(vector: Tsvector, query: Tsquery) -> Float;
+}
+
+define_sql_function! {
+    /// The parts of `document` matching `query`, with the matches wrapped in `<b>` tags.
+    /// `options` is e.g. `"MaxFragments=2, MaxWords=20"`.
+    fn ts_headline(config: Regconfig, document: Text, query: Tsquery, options: Text) -> Text;
+}
+
+infix_operator!(Matches, " @@ ", Bool, backend: Pg);
+
+pub trait TsvectorExpressionMethods: Expression<SqlType = Tsvector> + Sized {
+    /// `vector @@ query`
+    fn matches<Q>(self, query: Q) -> Matches<Self, Q::Expression>
+    where
+        Q: AsExpression<Tsquery>,
+    {
+        Matches::new(self, query.as_expression())
+    }
+}
+
+impl<T: Expression<SqlType = Tsvector>> TsvectorExpressionMethods for T {}
+
+/// A text search configuration by name, e.g. `config("german")`. Use it with the functions above
+/// or to set the `language` of a row.
+pub fn config(name: &str) -> Config {
+    Config(name.to_string())
+}
+
+/// Renders as `CAST($1 AS regconfig)`: Postgres sends `regconfig` values as OIDs, so the name is
+/// bound as text and looked up by the server.
+#[derive(Debug, Clone, QueryId)]
+pub struct Config(String);
+
+impl Expression for Config {
+    type SqlType = Regconfig;
+}
+
+impl QueryFragment<Pg> for Config {
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, Pg>) -> QueryResult<()> {
+        out.push_sql("CAST(");
+        out.push_bind_param::<Text, _>(&self.0)?;
+        out.push_sql(" AS regconfig)");
+        Ok(())
+    }
+}
+
+impl<QS> AppearsOnTable<QS> for Config {}
+
+impl<QS> SelectableExpression<QS> for Config {}
+
+impl<GB> ValidGrouping<GB> for Config {
+    type IsAggregate = is_aggregate::Never;
+}
+
+/// Whether `name` is an installed text search configuration, to validate user input before
+/// passing it to `config`.
+pub fn config_exists(conn: &mut PgConnection, name: &str) -> QueryResult<bool> {
+    diesel::select(
+        diesel::dsl::sql::<Bool>("EXISTS (SELECT 1 FROM pg_ts_config WHERE cfgname = ")
+            .bind::<Text, _>(name)
+            .sql(")"),
+    )
+    .get_result(conn)
+}
+
+/// A page matching a search, with the matching words highlighted.
+#[derive(Debug, Serialize, Queryable)]
+pub struct PageExcerpt {
+    pub page_id: i32,
+    pub page_number: i32,
+    pub headline: String,
+    pub rank: f32,
+}
+
+/// A post matching a search, with the matching words in the body highlighted.
+#[derive(Debug, Serialize, Queryable)]
+pub struct PostMatch {
+    pub post: Post,
+    pub headline: String,
+    pub rank: f32,
+}
+
+const HEADLINE_OPTIONS: &str = "MaxFragments=2, MaxWords=20, MinWords=5";
+
+/// Books with pages matching `query` (in `websearch_to_tsquery` syntax), best match first, each
+/// with its matching pages in rank order. Only pages written in `language` are searched, so the
+/// words are stemmed the same way as the page content.
+pub fn search_books(
+    conn: &mut PgConnection,
+    query: &str,
+    language: &str,
+) -> QueryResult<Vec<Node<Book, PageExcerpt>>> {
+    let tsquery = websearch_to_tsquery(config(language), query);
+    let rank = ts_rank(pages::search, tsquery.clone());
+
+    let rows = books::table
+        .active()
+        .inner_join(pages::table)
+        .filter(pages::deleted_at.is_null())
+        .filter(pages::language.eq(config(language)))
+        .filter(pages::search.matches(tsquery.clone()))
+        .order((rank.clone().desc(), pages::id))
+        .select((
+            Book::as_select(),
+            (
+                pages::id,
+                pages::page_number,
+                ts_headline(config(language), pages::content, tsquery, HEADLINE_OPTIONS),
+                rank,
+            ),
+        ))
+        .load::<(Book, PageExcerpt)>(conn)?;
+
+    Ok(rows.nest())
+}
+
+/// Published posts matching `query` in their title or body, best match first. Title matches rank
+/// higher.
+pub fn search_posts(
+    conn: &mut PgConnection,
+    query: &str,
+    language: &str,
+) -> QueryResult<Vec<PostMatch>> {
+    let tsquery = websearch_to_tsquery(config(language), query);
+    let rank = ts_rank(posts::search, tsquery.clone());
+
+    posts::table
+        .filter(posts::status.eq(PostStatus::Published))
+        .filter(posts::language.eq(config(language)))
+        .filter(posts::search.matches(tsquery.clone()))
+        .order((rank.clone().desc(), posts::id))
+        .select((
+            Post::as_select(),
+            ts_headline(config(language), posts::body, tsquery, HEADLINE_OPTIONS),
+            rank,
+        ))
+        .load(conn)
+}
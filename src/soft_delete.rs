@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use diesel::dsl::{self, now};
+use diesel::prelude::*;
+use diesel::query_dsl::methods;
+use diesel::sql_types::{Nullable, Timestamptz};
+use diesel::Column;
+
+use crate::schema::*;
+
+/// Tables with a nullable `deleted_at` column. A row is deleted when `deleted_at` is set, and
+/// only hard-deleted by `purge` once it has been deleted for longer than the retention window.
+pub trait SoftDelete: Table + Sized {
+    type Id;
+    type DeletedAt: Column<Table = Self, SqlType = Nullable<Timestamptz>>
+        + ExpressionMethods
+        + Default;
+
+    /// Rows that have not been deleted. Use this as the default scope for reads.
+    fn active(self) -> dsl::Filter<Self, dsl::IsNull<Self::DeletedAt>>
+    where
+        Self: methods::FilterDsl<dsl::IsNull<Self::DeletedAt>>,
+    {
+        methods::FilterDsl::filter(self, Self::DeletedAt::default().is_null())
+    }
+
+    /// Rows that have been deleted but not purged yet.
+    fn deleted(self) -> dsl::Filter<Self, dsl::IsNotNull<Self::DeletedAt>>
+    where
+        Self: methods::FilterDsl<dsl::IsNotNull<Self::DeletedAt>>,
+    {
+        methods::FilterDsl::filter(self, Self::DeletedAt::default().is_not_null())
+    }
+
+    /// Marks the row as deleted. Returns `false` if it does not exist or is already deleted.
+    fn soft_delete(conn: &mut PgConnection, id: Self::Id) -> QueryResult<bool>;
+
+    /// Undoes `soft_delete`. Returns `false` if the row does not exist or is not deleted.
+    fn restore(conn: &mut PgConnection, id: Self::Id) -> QueryResult<bool>;
+}
+
+macro_rules! soft_delete {
+    ($($table: ident => $id: ty),+ $(,)?) => {
+        $(
+            impl SoftDelete for $table::table {
+                type Id = $id;
+                type DeletedAt = $table::deleted_at;
+
+                fn soft_delete(conn: &mut PgConnection, id: $id) -> QueryResult<bool> {
+                    let target = $table::table.find(id).filter($table::deleted_at.is_null());
+                    let rows = diesel::update(target)
+                        .set($table::deleted_at.eq(now.into_sql::<Timestamptz>().nullable()))
+                        .execute(conn)?;
+                    Ok(rows > 0)
+                }
+
+                fn restore(conn: &mut PgConnection, id: $id) -> QueryResult<bool> {
+                    let target = $table::table.find(id).filter($table::deleted_at.is_not_null());
+                    let rows = diesel::update(target)
+                        .set($table::deleted_at.eq(None::<DateTime<Utc>>))
+                        .execute(conn)?;
+                    Ok(rows > 0)
+                }
+            }
+        )+
+    };
+}
+
+soft_delete!(
+    address => i32,
+    authors => i32,
+    books => i32,
+    invites => i64,
+    pages => i32,
+);
+
+#[derive(Debug, Default)]
+pub struct PurgeStats {
+    pub pages: usize,
+    pub books_authors: usize,
+    pub books: usize,
+    pub address: usize,
+    pub authors: usize,
+    pub invites: usize,
+}
+
+/// Hard-deletes rows that were soft deleted more than `retention` ago, children before parents so
+/// foreign keys hold: pages and author links before books, addresses and author links before
+/// authors. Children of a purged parent are purged with it, even if they were not deleted
+/// themselves.
+pub fn purge(conn: &mut PgConnection, retention: Duration) -> QueryResult<PurgeStats> {
+    // A retention reaching back before the earliest representable time keeps everything
+    let Some(cutoff) = chrono::Duration::from_std(retention)
+        .ok()
+        .and_then(|retention| Utc::now().checked_sub_signed(retention))
+    else {
+        return Ok(PurgeStats::default());
+    };
+
+    conn.transaction(|conn| {
+        let purged_books = books::table
+            .filter(books::deleted_at.lt(cutoff))
+            .select(books::id);
+        let purged_authors = authors::table
+            .filter(authors::deleted_at.lt(cutoff))
+            .select(authors::id);
+
+        let pages = diesel::delete(
+            pages::table.filter(
+                pages::deleted_at
+                    .lt(cutoff)
+                    .or(pages::book_id.eq_any(purged_books)),
+            ),
+        )
+        .execute(conn)?;
+
+        let books_authors = diesel::delete(
+            books_authors::table.filter(
+                books_authors::book_id
+                    .eq_any(purged_books)
+                    .or(books_authors::author_id.eq_any(purged_authors)),
+            ),
+        )
+        .execute(conn)?;
+
+        let books =
+            diesel::delete(books::table.filter(books::deleted_at.lt(cutoff))).execute(conn)?;
+
+        let address = diesel::delete(
+            address::table.filter(
+                address::deleted_at
+                    .lt(cutoff)
+                    .or(address::author_id.eq_any(purged_authors)),
+            ),
+        )
+        .execute(conn)?;
+
+        let authors =
+            diesel::delete(authors::table.filter(authors::deleted_at.lt(cutoff))).execute(conn)?;
+
+        let invites =
+            diesel::delete(invites::table.filter(invites::deleted_at.lt(cutoff))).execute(conn)?;
+
+        Ok(PurgeStats {
+            pages,
+            books_authors,
+            books,
+            address,
+            authors,
+            invites,
+        })
+    })
+}
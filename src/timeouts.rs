@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+
+/// Server-side limits for a block of queries, see `with_timeouts`. `None` keeps the connection's
+/// setting, e.g. from `DbConfig`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Cancels a statement running longer than this.
+    pub statement: Option<Duration>,
+    /// Fails a statement waiting longer than this for a lock, e.g. a row another transaction is
+    /// updating.
+    pub lock: Option<Duration>,
+    /// Ends the session if a transaction sits idle longer than this, so a stuck client can't hold
+    /// its locks forever.
+    pub idle_in_transaction: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Only a `statement_timeout`.
+    pub fn statement(timeout: Duration) -> Self {
+        Timeouts {
+            statement: Some(timeout),
+            ..Timeouts::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Timeouts::default()
+    }
+
+    /// `SET LOCAL`s the timeouts, so they only last until the current transaction ends.
+    pub fn set_local(&self, conn: &mut PgConnection) -> QueryResult<()> {
+        let settings = [
+            ("statement_timeout", self.statement),
+            ("lock_timeout", self.lock),
+            (
+                "idle_in_transaction_session_timeout",
+                self.idle_in_transaction,
+            ),
+        ];
+
+        for (name, timeout) in settings {
+            if let Some(timeout) = timeout {
+                diesel::sql_query("SELECT set_config($1, $2, true)")
+                    .bind::<Text, _>(name)
+                    .bind::<Text, _>(format!("{}ms", timeout.as_millis().max(1)))
+                    .execute(conn)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs `f` in a transaction with `timeouts` applied. A statement cancelled by them fails with a
+/// `DatabaseError` (`query_canceled` or `lock_not_available`), which rolls the transaction back.
+///
+/// ```ignore
+/// let reports = with_timeouts(conn, &Timeouts::statement(Duration::from_secs(5)), |conn| {
+///     reports::table.load::<Report>(conn)
+/// })?;
+/// ```
+pub fn with_timeouts<T, E, F>(conn: &mut PgConnection, timeouts: &Timeouts, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut PgConnection) -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    conn.transaction(|conn| {
+        timeouts.set_local(conn)?;
+        f(conn)
+    })
+}
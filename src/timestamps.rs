@@ -0,0 +1,132 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use chrono::{DateTime, Utc};
+use diesel::deserialize;
+use diesel::dsl::Desc;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::Timestamptz;
+use diesel::Column;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::*;
+
+/// Tables with the `created_at` / `updated_at` columns added by the `add_timestamps` SQL function.
+/// New tables should call `SELECT add_timestamps('...')` in their migration and be listed here,
+/// so their models can embed `Timestamps`.
+pub trait HasTimestamps: Table + Sized {
+    type CreatedAt: Column<Table = Self, SqlType = Timestamptz> + ExpressionMethods + Default;
+    type UpdatedAt: Column<Table = Self, SqlType = Timestamptz> + ExpressionMethods + Default;
+}
+
+macro_rules! has_timestamps {
+    ($($table: ident),+ $(,)?) => {
+        $(
+            impl HasTimestamps for $table::table {
+                type CreatedAt = $table::created_at;
+                type UpdatedAt = $table::updated_at;
+            }
+        )+
+    };
+}
+
+has_timestamps!(
+    address,
+    authors,
+    books,
+    books_authors,
+    invites,
+    items,
+    pages,
+    post_revisions,
+    posts,
+    reports,
+);
+
+/// Most recently updated first: `query.order(recent_first::<books::table>())`
+pub fn recent_first<T: HasTimestamps>() -> Desc<T::UpdatedAt> {
+    T::UpdatedAt::default().desc()
+}
+
+/// Most recently created first.
+pub fn newest_first<T: HasTimestamps>() -> Desc<T::CreatedAt> {
+    T::CreatedAt::default().desc()
+}
+
+/// The `created_at` / `updated_at` columns of table `T`, for a model of it to embed:
+///
+/// ```ignore
+/// #[diesel(embed)]
+/// #[serde(flatten)]
+/// pub timestamps: Timestamps<books::table>,
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct Timestamps<T> {
+    pub created_at: DateTime<Utc>,
+    /// Set by the `diesel_manage_updated_at` trigger on every update.
+    pub updated_at: DateTime<Utc>,
+    #[serde(skip)]
+    table: PhantomData<T>,
+}
+
+impl<T> Timestamps<T> {
+    pub fn new(created_at: DateTime<Utc>, updated_at: DateTime<Utc>) -> Self {
+        Timestamps {
+            created_at,
+            updated_at,
+            table: PhantomData,
+        }
+    }
+}
+
+impl<T: HasTimestamps> Selectable<Pg> for Timestamps<T> {
+    type SelectExpression = (T::CreatedAt, T::UpdatedAt);
+
+    fn construct_selection() -> Self::SelectExpression {
+        (T::CreatedAt::default(), T::UpdatedAt::default())
+    }
+}
+
+impl<T> Queryable<(Timestamptz, Timestamptz), Pg> for Timestamps<T> {
+    type Row = (DateTime<Utc>, DateTime<Utc>);
+
+    fn build((created_at, updated_at): Self::Row) -> deserialize::Result<Self> {
+        Ok(Timestamps::new(created_at, updated_at))
+    }
+}
+
+// By hand, deriving would require the table types to implement them too
+
+impl<T> Clone for Timestamps<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Timestamps<T> {}
+
+impl<T> fmt::Debug for Timestamps<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timestamps")
+            .field("created_at", &self.created_at)
+            .field("updated_at", &self.updated_at)
+            .finish()
+    }
+}
+
+impl<T> PartialEq for Timestamps<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.created_at == other.created_at && self.updated_at == other.updated_at
+    }
+}
+
+impl<T> Eq for Timestamps<T> {}
+
+impl<T> Hash for Timestamps<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.created_at.hash(state);
+        self.updated_at.hash(state);
+    }
+}
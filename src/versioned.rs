@@ -0,0 +1,72 @@
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_builder::QueryFragment;
+use diesel::sql_types::Integer;
+use diesel::Column;
+
+use crate::repo::{RepoError, RepoResult};
+use crate::schema::*;
+
+/// Tables with an integer `version` column, starting at 1 and incremented on every update. Clients
+/// send back the version they read, and the update only goes through if nobody changed the row in
+/// the meantime, so concurrent editors can't blindly overwrite each other.
+pub trait Versioned: Table + Sized {
+    type Id;
+    type Version: Column<Table = Self, SqlType = Integer> + ExpressionMethods + Default;
+
+    /// Applies `changes` if the row is still at version `expected`, and bumps the version.
+    /// Returns the new version, `RepoError::StaleObject` with the current version if the row was
+    /// changed since, or `RepoError::NotFound`.
+    fn update_with_version<C>(
+        conn: &mut PgConnection,
+        id: Self::Id,
+        expected: i32,
+        changes: C,
+    ) -> RepoResult<i32>
+    where
+        C: AsChangeset<Target = Self>,
+        C::Changeset: QueryFragment<Pg>;
+}
+
+macro_rules! versioned {
+    ($($table: ident => $id: ty),+ $(,)?) => {
+        $(
+            impl Versioned for $table::table {
+                type Id = $id;
+                type Version = $table::version;
+
+                fn update_with_version<C>(
+                    conn: &mut PgConnection,
+                    id: $id,
+                    expected: i32,
+                    changes: C,
+                ) -> RepoResult<i32>
+                where
+                    C: AsChangeset<Target = Self>,
+                    C::Changeset: QueryFragment<Pg>,
+                {
+                    let target = $table::table.find(id).filter($table::version.eq(expected));
+                    let rows = diesel::update(target)
+                        .set((changes, $table::version.eq($table::version + 1)))
+                        .execute(conn)?;
+
+                    if rows > 0 {
+                        return Ok(expected + 1);
+                    }
+
+                    let actual = $table::table
+                        .find(id)
+                        .select($table::version)
+                        .get_result::<i32>(conn)?;
+
+                    Err(RepoError::StaleObject { expected, actual })
+                }
+            }
+        )+
+    };
+}
+
+versioned!(
+    books => i32,
+    posts => i32,
+);
@@ -0,0 +1,75 @@
+mod common;
+
+use diesel::prelude::*;
+use rust_pg::audit::{self, FieldChange, Operation};
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{BookChanges, NewBook};
+use rust_pg::repo::{BookRepository, RepoResult};
+use rust_pg::schema::books;
+use serde_json::json;
+
+use common::ScratchDatabase;
+
+#[test]
+fn records_the_changed_columns_and_the_actor() {
+    let db = ScratchDatabase::create("audit");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let book = BookRepository::new(&mut conn)
+        .create(&NewBook { title: "Momo" })
+        .unwrap();
+    audit::with_actor(&mut conn, "kjell", |conn| -> RepoResult<_> {
+        BookRepository::new(conn).update(
+            book.id,
+            &BookChanges {
+                title: Some("Die unendliche Geschichte"),
+            },
+        )
+    })
+    .unwrap();
+    // Only bumps updated_at, which isn't recorded
+    diesel::update(books::table.find(book.id))
+        .set(books::title.eq(books::title))
+        .execute(&mut conn)
+        .unwrap();
+
+    let history = audit::history(&mut conn, "books", book.id).unwrap();
+    assert_eq!(
+        history.iter().map(|entry| entry.operation).collect::<Vec<_>>(),
+        [Operation::Insert, Operation::Update]
+    );
+
+    assert_eq!(history[0].actor_id, None);
+    assert_eq!(history[0].row()["title"], json!("Momo"));
+
+    let update = &history[1];
+    assert_eq!(update.actor_id.as_deref(), Some("kjell"));
+    let changes = update
+        .changes()
+        .into_iter()
+        .filter(|change| change.field != "updated_at")
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        [
+            FieldChange {
+                field: "title".to_string(),
+                old: Some(json!("Momo")),
+                new: Some(json!("Die unendliche Geschichte")),
+            },
+            FieldChange {
+                field: "version".to_string(),
+                old: Some(json!(1)),
+                new: Some(json!(2)),
+            },
+        ]
+    );
+
+    // The actor only applies inside `with_actor`
+    BookRepository::new(&mut conn).delete(book.id).unwrap();
+    let history = audit::history(&mut conn, "books", book.id).unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[2].actor_id, None);
+    assert_eq!(history[2].row()["title"], json!("Die unendliche Geschichte"));
+}
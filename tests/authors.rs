@@ -0,0 +1,97 @@
+mod common;
+
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{NewAuthor, NewBook};
+use rust_pg::repo::{AuthorFilter, AuthorRepository, BookFilter, BookRepository, RepoError};
+
+use common::ScratchDatabase;
+
+#[test]
+fn only_links_existing_authors_and_books() {
+    let db = ScratchDatabase::create("authors_add_book");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let momo = BookRepository::new(&mut conn)
+        .create(&NewBook { title: "Momo" })
+        .unwrap();
+    let deleted_book = BookRepository::new(&mut conn)
+        .create(&NewBook { title: "Jim Knopf" })
+        .unwrap();
+    BookRepository::new(&mut conn)
+        .delete(deleted_book.id)
+        .unwrap();
+
+    let mut authors = AuthorRepository::new(&mut conn);
+    let ende = authors
+        .create(&NewAuthor {
+            name: "Michael Ende",
+        })
+        .unwrap();
+    let deleted_author = authors
+        .create(&NewAuthor {
+            name: "Astrid Lindgren",
+        })
+        .unwrap();
+    authors.delete(deleted_author.id).unwrap();
+
+    assert!(matches!(
+        authors.add_book(ende.id, deleted_book.id),
+        Err(RepoError::Conflict(_))
+    ));
+    assert!(matches!(
+        authors.add_book(deleted_author.id, momo.id),
+        Err(RepoError::Conflict(_))
+    ));
+    assert!(matches!(
+        authors.add_book(ende.id, momo.id + 100),
+        Err(RepoError::Conflict(_))
+    ));
+
+    authors.add_book(ende.id, momo.id).unwrap();
+    assert!(matches!(
+        authors.add_book(ende.id, momo.id),
+        Err(RepoError::Conflict(_))
+    ));
+    let books = authors.books(ende.id).unwrap();
+    assert_eq!(books.len(), 1);
+    assert_eq!(books[0].id, momo.id);
+}
+
+#[test]
+fn name_and_title_filters_match_wildcards_literally() {
+    let db = ScratchDatabase::create("authors_filters");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let mut books = BookRepository::new(&mut conn);
+    let percent = books.create(&NewBook { title: "100% Momo" }).unwrap();
+    books
+        .create(&NewBook {
+            title: "1000 Momos",
+        })
+        .unwrap();
+    let filter = |title| BookFilter {
+        title: Some(title),
+        ..BookFilter::default()
+    };
+    let found = books.list(&filter("0%"), 1).unwrap();
+    assert_eq!(
+        found.iter().map(|book| book.id).collect::<Vec<_>>(),
+        [percent.id]
+    );
+    assert_eq!(books.list(&filter("momo"), 1).unwrap().len(), 2);
+
+    let mut authors = AuthorRepository::new(&mut conn);
+    let underscore = authors.create(&NewAuthor { name: "jim_knopf" }).unwrap();
+    authors.create(&NewAuthor { name: "Jim Knopf" }).unwrap();
+    let filter = |name| AuthorFilter {
+        name: Some(name),
+        ..AuthorFilter::default()
+    };
+    let found = authors.list(&filter("m_k"), 1).unwrap();
+    assert_eq!(
+        found.iter().map(|author| author.id).collect::<Vec<_>>(),
+        [underscore.id]
+    );
+}
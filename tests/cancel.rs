@@ -0,0 +1,52 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rust_pg::cancel::CancelHandle;
+use rust_pg::pool::{connect_pool, PoolConfig};
+
+use common::ScratchDatabase;
+
+#[test]
+fn cancels_while_the_pool_is_exhausted() {
+    let db = ScratchDatabase::create("cancel");
+    let pool_config = PoolConfig {
+        max_size: 1,
+        min_idle: Some(1),
+        connection_timeout: Duration::from_secs(5),
+        ..PoolConfig::default()
+    };
+    let pool = connect_pool(&db.config, &pool_config).unwrap();
+
+    let handle = CancelHandle::new();
+    let started = Instant::now();
+    let query = thread::spawn({
+        let handle = handle.clone();
+        let config = db.config.clone();
+        move || {
+            let mut conn = pool.get().unwrap();
+            handle.run(&config, &mut conn, |conn| {
+                diesel::sql_query("SELECT pg_sleep(30)").execute(conn)
+            })
+        }
+    });
+
+    // The only connection of the pool is busy with the query now
+    thread::sleep(Duration::from_millis(500));
+    assert!(handle.cancel());
+
+    let result = query.join().unwrap();
+    assert!(matches!(result, Err(DieselError::DatabaseError(_, _))));
+    // Well before the pool's connection timeout
+    assert!(started.elapsed() < Duration::from_secs(3));
+
+    // Later runs fail without touching the connection
+    let mut conn = db.connect();
+    let result = handle.run(&db.config, &mut conn, |_| -> QueryResult<()> {
+        panic!("ran after cancel")
+    });
+    assert!(result.is_err());
+}
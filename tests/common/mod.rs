@@ -0,0 +1,47 @@
+use std::process;
+
+use diesel::prelude::*;
+use rust_pg::config::{connect, DbConfig};
+
+/// A database created for one test from the server in `DbConfig::load`, and dropped again when
+/// this is dropped.
+pub struct ScratchDatabase {
+    admin: DbConfig,
+    pub config: DbConfig,
+}
+
+impl ScratchDatabase {
+    pub fn create(name: &str) -> Self {
+        let admin = DbConfig::load().expect("no database configured");
+        let config = DbConfig {
+            dbname: format!("rust_pg_test_{}_{}", name, process::id()),
+            ..admin.clone()
+        };
+
+        let scratch = ScratchDatabase { admin, config };
+        scratch.execute(&format!(
+            "DROP DATABASE IF EXISTS {}",
+            scratch.config.dbname
+        ));
+        scratch.execute(&format!("CREATE DATABASE {}", scratch.config.dbname));
+        scratch
+    }
+
+    pub fn connect(&self) -> PgConnection {
+        connect(&self.config).expect("connecting to the scratch database failed")
+    }
+
+    fn execute(&self, sql: &str) {
+        let mut conn = connect(&self.admin).expect("connecting to the database server failed");
+        diesel::sql_query(sql).execute(&mut conn).unwrap();
+    }
+}
+
+impl Drop for ScratchDatabase {
+    fn drop(&mut self) {
+        self.execute(&format!(
+            "DROP DATABASE IF EXISTS {} WITH (FORCE)",
+            self.config.dbname
+        ));
+    }
+}
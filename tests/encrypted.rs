@@ -0,0 +1,90 @@
+mod common;
+
+use std::path::Path;
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use rust_pg::encrypted::{keyring, rotate_keys, set_keyring, Encrypted, Keyring};
+
+use common::ScratchDatabase;
+
+diesel::table! {
+    secrets (id) {
+        id -> BigInt,
+        value -> Bytea,
+    }
+}
+
+fn load(conn: &mut PgConnection) -> Vec<(i64, Encrypted<String>)> {
+    secrets::table
+        .order(secrets::id)
+        .load(conn)
+        .unwrap()
+}
+
+#[test]
+fn rotates_rows_written_with_old_keys() {
+    let db = ScratchDatabase::create("rotate_keys");
+    let mut conn = db.connect();
+    conn.batch_execute("CREATE TABLE secrets (id bigserial PRIMARY KEY, value bytea NOT NULL)")
+        .unwrap();
+
+    // The same keyring with a new key added, like after rotating the keyring file
+    let old = Keyring::generate();
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rotate_keys.json");
+    old.save(&path).unwrap();
+    let mut new = Keyring::from_file(&path).unwrap();
+    new.add_active_key();
+    assert!(set_keyring(new).is_ok());
+
+    let values = ["a", "b", "c", "d", "e"];
+    for (i, value) in values.iter().enumerate() {
+        let value = Encrypted::<String>::new(value.to_string());
+        // Every other row with the old key, the rest with the active one
+        let envelope = match i % 2 {
+            0 => value.encrypt_with(&old).unwrap(),
+            _ => value.encrypt_with(keyring().unwrap()).unwrap(),
+        };
+        diesel::insert_into(secrets::table)
+            .values(secrets::value.eq(envelope))
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    let rotated = rotate_keys(
+        &mut conn,
+        2,
+        |conn, after, limit| {
+            secrets::table
+                .filter(secrets::id.gt(after))
+                .order(secrets::id)
+                .limit(limit)
+                .load::<(i64, Encrypted<String>)>(conn)
+        },
+        |value| value.needs_rotation(keyring().unwrap()),
+        |conn, id, value| {
+            diesel::update(secrets::table.find(id))
+                .set(secrets::value.eq(Encrypted::<String>::new(value.into_inner())))
+                .execute(conn)
+                .map(|_| ())
+        },
+    )
+    .unwrap();
+
+    assert_eq!(rotated, 3);
+    let rows = load(&mut conn);
+    assert!(rows.iter().all(|(_, value)| value.key_id() == Some(2)));
+    assert_eq!(
+        rows.iter().map(|(_, value)| value.as_str()).collect::<Vec<_>>(),
+        values
+    );
+
+    let rejected = rotate_keys(
+        &mut conn,
+        0,
+        |_, _, _| -> QueryResult<Vec<(i64, ())>> { panic!("loaded a batch of size 0") },
+        |_| true,
+        |_, _, _| Ok(()),
+    );
+    assert!(rejected.is_err());
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use diesel::prelude::*;
+use rust_pg::fuzzy::{autocomplete, SuggestionKind, TrigramExpressionMethods};
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{NewAuthor, NewBook};
+use rust_pg::repo::{AuthorRepository, BookRepository};
+use rust_pg::schema::books;
+
+use common::ScratchDatabase;
+
+#[test]
+fn tolerates_typos() {
+    let db = ScratchDatabase::create("fuzzy");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let mut books = BookRepository::new(&mut conn);
+    let pippi = books
+        .create(&NewBook {
+            title: "Pippi Långstrump",
+        })
+        .unwrap();
+    let pippi_at_sea = books
+        .create(&NewBook {
+            title: "Pippi Långstrump på de sju haven",
+        })
+        .unwrap();
+    books.create(&NewBook { title: "Momo" }).unwrap();
+    let deleted = books
+        .create(&NewBook {
+            title: "Pippi Långstrump går ombord",
+        })
+        .unwrap();
+    books.delete(deleted.id).unwrap();
+    let astrid = AuthorRepository::new(&mut conn)
+        .create(&NewAuthor {
+            name: "Astrid Lindgren",
+        })
+        .unwrap();
+
+    let suggestions = autocomplete(&mut conn, "pipi langs", 10).unwrap();
+    let ids = suggestions
+        .iter()
+        .map(|suggestion| (suggestion.kind, suggestion.id))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        [
+            (SuggestionKind::Book, pippi.id),
+            (SuggestionKind::Book, pippi_at_sea.id)
+        ]
+    );
+    assert_eq!(suggestions[0].text, "Pippi Långstrump");
+    assert!(suggestions[0].score > 0.3);
+
+    let suggestions = autocomplete(&mut conn, "lindgrn", 10).unwrap();
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!(suggestions[0].kind, SuggestionKind::Author);
+    assert_eq!(suggestions[0].id, astrid.id);
+
+    assert!(autocomplete(&mut conn, "  ", 10).unwrap().is_empty());
+    assert_eq!(autocomplete(&mut conn, "pipi langs", 1).unwrap().len(), 1);
+
+    // Similar titles, closest first
+    let titles = books::table
+        .filter(books::deleted_at.is_null())
+        .filter(books::title.fuzzy_matches("Pipi Langstrump"))
+        .order(books::title.trigram_distance("Pipi Langstrump"))
+        .select(books::title)
+        .load::<String>(&mut conn)
+        .unwrap();
+    assert_eq!(
+        titles,
+        ["Pippi Långstrump", "Pippi Långstrump på de sju haven"]
+    );
+}
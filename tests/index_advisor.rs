@@ -0,0 +1,94 @@
+mod common;
+
+use std::fs;
+
+use diesel::connection::SimpleConnection;
+use diesel::PgConnection;
+use rust_pg::index_advisor::{analyze_indexes, write_index_migrations};
+use rust_pg::migrations::run_pending_migrations;
+
+use common::ScratchDatabase;
+
+fn migrated(name: &str) -> (ScratchDatabase, PgConnection) {
+    let db = ScratchDatabase::create(name);
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+    (db, conn)
+}
+
+#[test]
+fn every_foreign_key_has_an_index() {
+    let (_db, mut conn) = migrated("indexes_fks");
+
+    let report = analyze_indexes(&mut conn).unwrap();
+
+    let missing = report
+        .unindexed_foreign_keys
+        .iter()
+        .map(|fk| fk.create_index_sql())
+        .collect::<Vec<_>>();
+    assert!(
+        missing.is_empty(),
+        "missing indexes:\n{}",
+        missing.join("\n")
+    );
+    assert!(report.redundant_indexes.is_empty());
+}
+
+#[test]
+fn finds_unindexed_foreign_keys_and_redundant_indexes() {
+    let (_db, mut conn) = migrated("indexes_found");
+    conn.batch_execute(
+        "CREATE TABLE chapters (id SERIAL PRIMARY KEY, book_id INTEGER REFERENCES books (id)); \
+         CREATE INDEX pages_page_number_idx ON pages (page_number); \
+         CREATE INDEX pages_page_number_book_id_idx ON pages (page_number, book_id); \
+         CREATE INDEX books_id_idx ON books (id)",
+    )
+    .unwrap();
+
+    let report = analyze_indexes(&mut conn).unwrap();
+
+    let fks = &report.unindexed_foreign_keys;
+    assert_eq!(fks.len(), 1);
+    assert_eq!(
+        fks[0].create_index_sql(),
+        "CREATE INDEX CONCURRENTLY chapters_book_id_idx ON chapters (book_id);"
+    );
+
+    let redundant = report
+        .redundant_indexes
+        .iter()
+        .map(|index| (index.index.as_str(), index.covered_by.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        redundant,
+        [
+            ("books_id_idx", "books_pkey"),
+            ("pages_page_number_idx", "pages_page_number_book_id_idx"),
+        ]
+    );
+}
+
+#[test]
+fn writes_a_migration_per_index() {
+    let (_db, mut conn) = migrated("indexes_write");
+    conn.batch_execute(
+        "CREATE TABLE chapters (id SERIAL PRIMARY KEY, book_id INTEGER REFERENCES books (id)); \
+         CREATE TABLE notes (id SERIAL PRIMARY KEY, page_id INTEGER REFERENCES pages (id))",
+    )
+    .unwrap();
+    let report = analyze_indexes(&mut conn).unwrap();
+    let dir = std::env::temp_dir().join(format!("index_advisor_{}", std::process::id()));
+
+    let written = write_index_migrations(&dir, &report.unindexed_foreign_keys).unwrap();
+
+    assert_eq!(written.len(), 2);
+    assert!(written[0] < written[1]);
+    let up_sql = fs::read_to_string(written[1].join("up.sql")).unwrap();
+    assert!(up_sql.ends_with("CREATE INDEX CONCURRENTLY notes_page_id_idx ON notes (page_id);\n"));
+    assert_eq!(
+        fs::read_to_string(written[1].join("metadata.toml")).unwrap(),
+        "run_in_transaction = false\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
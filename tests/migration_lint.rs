@@ -0,0 +1,163 @@
+use std::fs;
+use std::path::Path;
+
+use rust_pg::migration_lint::{lint_migrations, lint_sql, Rule};
+
+fn rules(sql: &str) -> Vec<(usize, Rule)> {
+    lint_sql("test", sql)
+        .into_iter()
+        .map(|finding| (finding.line, finding.rule))
+        .collect()
+}
+
+#[test]
+fn flags_locking_operations_on_existing_tables() {
+    let sql = "
+        ALTER TABLE books ADD COLUMN isbn TEXT NOT NULL;
+        ALTER TABLE books ALTER COLUMN title SET NOT NULL;
+        CREATE INDEX books_title_idx ON books (title);
+        ALTER TABLE pages ALTER COLUMN content TYPE varchar(100);
+        ALTER TABLE pages ADD CONSTRAINT pages_book_id_fkey FOREIGN KEY (book_id) REFERENCES books (id);
+        ALTER TABLE books RENAME COLUMN title TO name;
+    ";
+
+    assert_eq!(
+        rules(sql),
+        [
+            (2, Rule::NotNullWithoutDefault),
+            (3, Rule::SetNotNull),
+            (4, Rule::IndexNotConcurrent),
+            (5, Rule::ColumnTypeChange),
+            (6, Rule::ForeignKeyValidation),
+            (6, Rule::UnindexedForeignKey),
+            (7, Rule::RenameColumn),
+        ]
+    );
+}
+
+#[test]
+fn accepts_the_safe_variants() {
+    let sql = "
+        ALTER TABLE books ADD COLUMN isbn TEXT NOT NULL DEFAULT '';
+        CREATE INDEX CONCURRENTLY pages_book_id_idx ON pages (book_id);
+        ALTER TABLE pages ADD CONSTRAINT pages_book_id_fkey
+            FOREIGN KEY (book_id) REFERENCES books (id) NOT VALID;
+        ALTER TABLE books RENAME TO volumes;
+    ";
+
+    assert_eq!(rules(sql), []);
+}
+
+#[test]
+fn flags_columns_that_rewrite_the_table() {
+    let sql = "
+        ALTER TABLE pages ADD COLUMN search tsvector
+            GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED;
+        ALTER TABLE pages ADD COLUMN token uuid NOT NULL DEFAULT gen_random_uuid();
+        ALTER TABLE pages ADD COLUMN shuffle INTEGER DEFAULT (random() * 100)::integer;
+        ALTER TABLE pages ADD COLUMN position BIGSERIAL;
+        ALTER TABLE pages ADD COLUMN number INTEGER GENERATED ALWAYS AS IDENTITY;
+        ALTER TABLE pages
+            ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            ADD COLUMN language regconfig NOT NULL DEFAULT 'simple';
+    ";
+
+    assert_eq!(
+        rules(sql),
+        [
+            (2, Rule::TableRewrite),
+            (4, Rule::TableRewrite),
+            (5, Rule::TableRewrite),
+            (6, Rule::TableRewrite),
+            (7, Rule::TableRewrite),
+        ]
+    );
+}
+
+#[test]
+fn tables_created_in_the_same_migration_are_fine() {
+    let sql = "
+        CREATE TABLE chapters (
+            id SERIAL PRIMARY KEY,
+            book_id INTEGER NOT NULL REFERENCES books (id),
+            title TEXT
+        );
+        ALTER TABLE chapters ALTER COLUMN title SET NOT NULL;
+        CREATE INDEX chapters_book_id_idx ON chapters (book_id);
+    ";
+
+    assert_eq!(rules(sql), []);
+}
+
+#[test]
+fn skips_comments_strings_and_function_bodies() {
+    let sql = "
+        -- ALTER TABLE books RENAME COLUMN title TO name;
+        /* CREATE INDEX books_title_idx ON books (title); */
+        INSERT INTO notes VALUES ('ALTER TABLE books ALTER COLUMN title SET NOT NULL;');
+        CREATE FUNCTION f() RETURNS void AS $$
+        BEGIN
+            ALTER TABLE books ALTER COLUMN title TYPE text;
+        END;
+        $$ LANGUAGE plpgsql;
+    ";
+
+    assert_eq!(rules(sql), []);
+}
+
+#[test]
+fn allow_comments_silence_a_rule() {
+    let sql = "
+        -- books is small
+        -- lint: allow index-not-concurrent
+        CREATE INDEX books_title_idx ON books (title);
+        CREATE INDEX books_created_at_idx ON books (created_at);
+    ";
+
+    assert_eq!(rules(sql), [(5, Rule::IndexNotConcurrent)]);
+}
+
+#[test]
+fn composite_primary_keys_only_index_their_first_column() {
+    let sql = "
+        CREATE TABLE books_authors (
+            book_id INTEGER REFERENCES books (id),
+            author_id INTEGER REFERENCES authors (id),
+            PRIMARY KEY (book_id, author_id)
+        );
+    ";
+
+    let findings = lint_sql("test", sql);
+
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].rule, Rule::UnindexedForeignKey);
+    assert!(findings[0]
+        .message
+        .starts_with("books_authors.author_id references authors"));
+}
+
+#[test]
+fn reads_the_migrations_directory() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("lint_migrations");
+    let migration = dir.join("2026-10-19-180000_change_kind");
+    fs::create_dir_all(&migration).unwrap();
+    fs::write(
+        migration.join("up.sql"),
+        "ALTER TABLE invites ALTER COLUMN kind TYPE text;\n",
+    )
+    .unwrap();
+
+    let findings = lint_migrations(&dir).unwrap();
+
+    assert!(findings.iter().any(|finding| {
+        finding.migration == "2026-10-19-180000_change_kind"
+            && finding.rule == Rule::ColumnTypeChange
+    }));
+}
+
+#[test]
+fn our_migrations_pass() {
+    let findings = lint_migrations("migrations").unwrap();
+
+    assert!(findings.is_empty(), "{:#?}", findings);
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use diesel::connection::SimpleConnection;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::BigInt;
+use diesel_migrations::{FileBasedMigrations, MigrationHarness};
+use rust_pg::migrations::{
+    run_pending_migrations, with_migration_lock, MigrationResult, MIGRATIONS,
+};
+
+use common::ScratchDatabase;
+
+fn advisory_locks(conn: &mut PgConnection) -> i64 {
+    diesel::select(sql::<BigInt>(
+        "(SELECT count(*) FROM pg_locks WHERE locktype = 'advisory' \
+          AND database = (SELECT oid FROM pg_database WHERE datname = current_database()))",
+    ))
+    .get_result(conn)
+    .unwrap()
+}
+
+#[test]
+fn unlocks_after_errors_and_panics() {
+    let db = ScratchDatabase::create("migration_lock");
+    let mut conn = db.connect();
+    let mut other = db.connect();
+
+    let locked = with_migration_lock(&mut conn, |_| Ok(advisory_locks(&mut other))).unwrap();
+    assert_eq!(locked, 1);
+    assert_eq!(advisory_locks(&mut other), 0);
+
+    let result: MigrationResult<()> =
+        with_migration_lock(&mut conn, |_| Err("migration failed".into()));
+    assert_eq!(result.unwrap_err().to_string(), "migration failed");
+    assert_eq!(advisory_locks(&mut other), 0);
+
+    let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+        with_migration_lock(&mut conn, |_| -> MigrationResult<()> { panic!("migration panicked") })
+    }));
+    assert!(panicked.is_err());
+    assert_eq!(advisory_locks(&mut other), 0);
+}
+
+/// A migrations directory with one migration building an index concurrently.
+fn concurrent_index_migration() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("migration_lock");
+    let migration = dir.join("2026-10-19-180000_create_books_title_idx");
+    fs::create_dir_all(&migration).unwrap();
+    fs::write(
+        migration.join("up.sql"),
+        "CREATE INDEX CONCURRENTLY books_title_idx ON books (title);\n",
+    )
+    .unwrap();
+    fs::write(
+        migration.join("down.sql"),
+        "DROP INDEX CONCURRENTLY books_title_idx;\n",
+    )
+    .unwrap();
+    fs::write(migration.join("metadata.toml"), "run_in_transaction = false\n").unwrap();
+    dir
+}
+
+#[test]
+fn concurrent_migrators_take_turns_over_concurrent_index_builds() {
+    let db = ScratchDatabase::create("migration_lock_concurrently");
+    db.connect()
+        .batch_execute("CREATE TABLE books (id serial PRIMARY KEY, title text NOT NULL)")
+        .unwrap();
+    let dir = concurrent_index_migration();
+
+    let migrators = (0..2)
+        .map(|i| {
+            let mut conn = db.connect();
+            let dir = dir.clone();
+            thread::spawn(move || {
+                // The second one waits for the lock while the first builds the index
+                thread::sleep(Duration::from_millis(i * 200));
+                with_migration_lock(&mut conn, |conn| {
+                    thread::sleep(Duration::from_millis(500));
+                    let migrations = FileBasedMigrations::from_path(&dir)?;
+                    Ok(conn.run_pending_migrations(migrations)?.len())
+                })
+                .map_err(|e| e.to_string())
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut applied = migrators
+        .into_iter()
+        .map(|migrator| migrator.join().unwrap())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    applied.sort();
+    assert_eq!(applied, [0, 1]);
+}
+
+/// Like webservers started together with `--migrate`, over our own migrations, which build the
+/// foreign key indexes concurrently.
+#[test]
+fn instances_migrating_at_once_both_succeed() {
+    let db = ScratchDatabase::create("migration_lock_boot");
+
+    let instances = (0..2)
+        .map(|_| {
+            let mut conn = db.connect();
+            thread::spawn(move || {
+                run_pending_migrations(&mut conn)
+                    .map(|applied| applied.len())
+                    .map_err(|e| e.to_string())
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let applied = instances
+        .into_iter()
+        .map(|instance| instance.join().unwrap())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert!(applied.contains(&0));
+    assert!(!db.connect().has_pending_migration(MIGRATIONS).unwrap());
+}
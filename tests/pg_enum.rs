@@ -0,0 +1,93 @@
+mod common;
+
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::{Array, Text};
+use rust_pg::diesel_pg_enum::{add_values_sql, AddValuesError, PgEnum};
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{InviteKind, PostStatus};
+use rust_pg::schema::sql_types;
+
+use common::ScratchDatabase;
+
+fn labels(conn: &mut PgConnection, type_name: &str) -> Vec<String> {
+    diesel::select(sql::<Array<Text>>(&format!(
+        "enum_range(NULL::{})::text[]",
+        type_name
+    )))
+    .get_result(conn)
+    .unwrap()
+}
+
+#[test]
+fn round_trips_every_variant() {
+    let db = ScratchDatabase::create("pg_enum");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    for kind in [InviteKind::Email, InviteKind::Link] {
+        let label = diesel::select(
+            sql::<Text>("")
+                .bind::<sql_types::InviteKind, _>(kind)
+                .sql("::text"),
+        )
+        .get_result::<String>(&mut conn)
+        .unwrap();
+        assert_eq!(label, kind.as_pg_str());
+
+        let read = diesel::select(kind.into_sql::<sql_types::InviteKind>())
+            .get_result::<InviteKind>(&mut conn)
+            .unwrap();
+        assert_eq!(read, kind);
+    }
+
+    for status in [
+        PostStatus::Draft,
+        PostStatus::Scheduled,
+        PostStatus::Published,
+        PostStatus::Archived,
+    ] {
+        let read = diesel::select(status.into_sql::<sql_types::PostStatus>())
+            .get_result::<PostStatus>(&mut conn)
+            .unwrap();
+        assert_eq!(read, status);
+    }
+
+    // Declared in the same order as in Postgres
+    assert_eq!(
+        labels(&mut conn, InviteKind::TYPE_NAME),
+        InviteKind::VARIANTS
+    );
+    assert_eq!(
+        labels(&mut conn, PostStatus::TYPE_NAME),
+        PostStatus::VARIANTS
+    );
+}
+
+#[test]
+fn rejects_unknown_labels() {
+    let db = ScratchDatabase::create("pg_enum_unknown");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+    diesel::sql_query("ALTER TYPE invite_kind ADD VALUE 'qr_code'")
+        .execute(&mut conn)
+        .unwrap();
+
+    let result = diesel::select(sql::<sql_types::InviteKind>("'qr_code'::invite_kind"))
+        .get_result::<InviteKind>(&mut conn);
+    let error = result.unwrap_err().to_string();
+    assert!(
+        error.contains("Unrecognized invite_kind variant: qr_code"),
+        "{}",
+        error
+    );
+
+    assert_eq!(InviteKind::from_pg_str("Email"), None);
+    assert_eq!(
+        add_values_sql::<InviteKind>(&["qr_code"]),
+        Err(AddValuesError::UnknownVariant {
+            type_name: "invite_kind",
+            value: "qr_code".to_string(),
+        })
+    );
+}
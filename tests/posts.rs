@@ -0,0 +1,104 @@
+mod common;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel_migrations::MigrationHarness;
+use rust_pg::migrations::{run_pending_migrations, MIGRATIONS};
+use rust_pg::repo::{slugify, PostRepository, RepoError};
+use rust_pg::schema::posts;
+
+use common::ScratchDatabase;
+
+#[test]
+fn concurrent_creates_get_different_slugs() {
+    let db = ScratchDatabase::create("post_slugs");
+    let mut first = db.connect();
+    run_pending_migrations(&mut first).unwrap();
+    let mut second = db.connect();
+
+    let (started, wait_for_start) = mpsc::channel();
+    let racing = thread::spawn(move || {
+        wait_for_start.recv().unwrap();
+        // Doesn't see the uncommitted post, so it tries the same slug and waits for it to commit
+        PostRepository::new(&mut second).create("Hello", "second").unwrap()
+    });
+
+    let created = first
+        .transaction(|conn| {
+            let post = PostRepository::new(conn).create("Hello", "first")?;
+            started.send(()).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            Ok::<_, RepoError>(post)
+        })
+        .unwrap();
+
+    assert_eq!(created.slug, "hello");
+    assert_eq!(racing.join().unwrap().slug, "hello-2");
+}
+
+#[test]
+fn only_published_posts_are_public() {
+    let db = ScratchDatabase::create("post_visibility");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+    let mut posts = PostRepository::new(&mut conn);
+
+    let draft = posts.create("Draft", "not yet").unwrap();
+    assert!(matches!(
+        posts.get_published(draft.id),
+        Err(RepoError::NotFound)
+    ));
+    assert!(matches!(
+        posts.get_published_by_slug(&draft.slug),
+        Err(RepoError::NotFound)
+    ));
+
+    posts.publish(draft.id).unwrap();
+    assert_eq!(posts.get_published(draft.id).unwrap().id, draft.id);
+    assert_eq!(posts.get_published_by_slug(&draft.slug).unwrap().id, draft.id);
+}
+
+#[test]
+fn existing_posts_get_slugs_like_new_ones() {
+    let db = ScratchDatabase::create("post_slug_backfill");
+    let mut conn = db.connect();
+    while conn
+        .pending_migrations(MIGRATIONS)
+        .unwrap()
+        .first()
+        .is_some_and(|next| next.name().to_string().as_str() < "2026-10-19-160000")
+    {
+        conn.run_next_migration(MIGRATIONS).unwrap();
+    }
+
+    let titles = [
+        "Hello, World!",
+        "Pippi Långstrump",
+        "ÅÄÖ Crème Brûlée",
+        "Straße und STRASSE",
+        "ŸVONNE Ñandú",
+        "日本語",
+        "  -- ",
+    ];
+    for title in titles {
+        diesel::sql_query("INSERT INTO posts (title, body) VALUES ($1, '')")
+            .bind::<Text, _>(title)
+            .execute(&mut conn)
+            .unwrap();
+    }
+    run_pending_migrations(&mut conn).unwrap();
+
+    let posts = posts::table
+        .select((posts::id, posts::title, posts::slug))
+        .order(posts::id)
+        .load::<(i32, String, String)>(&mut conn)
+        .unwrap();
+    assert_eq!(posts.len(), titles.len());
+    for (id, title, slug) in posts {
+        assert_eq!(slug, format!("{}-{}", slugify(&title), id), "{}", title);
+    }
+}
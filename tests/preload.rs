@@ -0,0 +1,78 @@
+mod common;
+
+use diesel::prelude::*;
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{Author, Book, BookChanges, NewAuthor, NewBook, NewPage, Page, PageChanges};
+use rust_pg::preload::LoadWith;
+use rust_pg::repo::{AuthorRepository, BookRepository, PageRepository};
+use rust_pg::schema::books;
+
+use common::ScratchDatabase;
+
+#[test]
+fn preloads_active_children_by_primary_key() {
+    let db = ScratchDatabase::create("preload");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let book = BookRepository::new(&mut conn)
+        .create(&NewBook { title: "Momo" })
+        .unwrap();
+    let pages = (1..=3)
+        .map(|page_number| {
+            PageRepository::new(&mut conn)
+                .create(&NewPage {
+                    page_number,
+                    content: "",
+                    book_id: book.id,
+                })
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    // Moves the first page to the end of the table, so a scan finds it last
+    PageRepository::new(&mut conn)
+        .update(
+            pages[0].id,
+            &PageChanges {
+                content: Some("Die grauen Herren"),
+                ..PageChanges::default()
+            },
+        )
+        .unwrap();
+    PageRepository::new(&mut conn).delete(pages[1].id).unwrap();
+
+    let loaded = Book::load_with::<Page>(&mut conn, vec![book]).unwrap();
+    let page_ids = loaded[0].1.iter().map(|page| page.id).collect::<Vec<_>>();
+    assert_eq!(page_ids, [pages[0].id, pages[2].id]);
+
+    let author = AuthorRepository::new(&mut conn)
+        .create(&NewAuthor { name: "Michael Ende" })
+        .unwrap();
+    let titles = ["Momo", "Die unendliche Geschichte", "Jim Knopf"];
+    let books = titles
+        .iter()
+        .map(|title| {
+            let book = BookRepository::new(&mut conn)
+                .create(&NewBook { title })
+                .unwrap();
+            AuthorRepository::new(&mut conn)
+                .add_book(author.id, book.id)
+                .unwrap();
+            book
+        })
+        .collect::<Vec<_>>();
+    BookRepository::new(&mut conn)
+        .update(
+            books[0].id,
+            &BookChanges {
+                title: Some("Momo oder Die seltsame Geschichte"),
+            },
+        )
+        .unwrap();
+    BookRepository::new(&mut conn).delete(books[1].id).unwrap();
+
+    let loaded = Author::load_with::<Book>(&mut conn, vec![author]).unwrap();
+    let book_ids = loaded[0].1.iter().map(|book| book.id).collect::<Vec<_>>();
+    assert_eq!(book_ids, [books[0].id, books[2].id]);
+    assert_eq!(books::table.count().get_result::<i64>(&mut conn), Ok(4));
+}
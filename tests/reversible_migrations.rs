@@ -0,0 +1,15 @@
+mod common;
+
+use rust_pg::migrations::verify_reversible;
+
+use common::ScratchDatabase;
+
+#[test]
+fn down_sql_reverts_up_sql() {
+    let db = ScratchDatabase::create("reversible");
+    let mut conn = db.connect();
+
+    if let Err(e) = verify_reversible(&mut conn) {
+        panic!("{}", e);
+    }
+}
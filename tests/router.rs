@@ -0,0 +1,75 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rust_pg::config::DbConfig;
+use rust_pg::pool::{connect_pool, connect_pool_lazily, PoolConfig};
+use rust_pg::router::{replica_lag, DbRouter};
+
+use common::ScratchDatabase;
+
+fn current_database(conn: &mut PgConnection) -> QueryResult<String> {
+    diesel::select(sql::<Text>("current_database()")).get_result(conn)
+}
+
+// A second database stands in for the replica, which one served a read tells them apart
+#[test]
+fn reads_go_to_the_primary_for_the_sticky_window_after_a_write() {
+    let primary = ScratchDatabase::create("router_primary");
+    let replica = ScratchDatabase::create("router_replica");
+    let pool_config = PoolConfig::default();
+    let router = DbRouter::new(
+        primary.config.clone(),
+        connect_pool(&primary.config, &pool_config).unwrap(),
+    )
+    .with_replica(
+        replica.config.clone(),
+        connect_pool(&replica.config, &pool_config).unwrap(),
+    )
+    .sticky_window(Duration::from_millis(500));
+    let primary_name = current_database(&mut primary.connect()).unwrap();
+    let replica_name = current_database(&mut replica.connect()).unwrap();
+
+    let mut session = router.session();
+    assert_eq!(session.read(current_database).unwrap().unwrap(), replica_name);
+
+    assert_eq!(session.write(current_database).unwrap().unwrap(), primary_name);
+    assert_eq!(session.read(current_database).unwrap().unwrap(), primary_name);
+    // Other sessions didn't write
+    let mut other = router.session();
+    assert_eq!(other.read(current_database).unwrap().unwrap(), replica_name);
+
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(session.read(current_database).unwrap().unwrap(), replica_name);
+}
+
+#[test]
+fn reads_fall_back_to_the_primary_without_a_reachable_replica() {
+    let primary = ScratchDatabase::create("router_fallback");
+    let pool_config = PoolConfig::default();
+    let unreachable = DbConfig::from_url("postgres://postgres@localhost:1/replica").unwrap();
+    let router = DbRouter::new(
+        primary.config.clone(),
+        connect_pool(&primary.config, &pool_config).unwrap(),
+    )
+    .with_replica(
+        unreachable.clone(),
+        connect_pool_lazily(&unreachable, &pool_config).unwrap(),
+    );
+    let primary_name = current_database(&mut primary.connect()).unwrap();
+
+    let mut session = router.session();
+    for _ in 0..2 {
+        assert_eq!(session.read(current_database).unwrap().unwrap(), primary_name);
+    }
+}
+
+#[test]
+fn a_primary_has_no_lag() {
+    let db = ScratchDatabase::create("router_lag");
+    assert_eq!(replica_lag(&mut db.connect()).unwrap(), Some(Duration::ZERO));
+}
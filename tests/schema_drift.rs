@@ -0,0 +1,51 @@
+mod common;
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::schema_drift::{check_schema, Drift};
+
+use common::ScratchDatabase;
+
+fn migrated(name: &str) -> (ScratchDatabase, PgConnection) {
+    let db = ScratchDatabase::create(name);
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+    (db, conn)
+}
+
+#[test]
+fn schema_rs_matches_the_migrations() {
+    let (_db, mut conn) = migrated("drift_matches");
+
+    let drift = check_schema(&mut conn).unwrap();
+
+    let diff = drift.iter().map(Drift::to_string).collect::<Vec<_>>();
+    assert!(
+        drift.is_empty(),
+        "schema.rs (-) differs from the migrations (+), run `diesel print-schema`:\n{}",
+        diff.join("\n")
+    );
+}
+
+#[test]
+fn reports_changed_columns() {
+    let (_db, mut conn) = migrated("drift_columns");
+    conn.batch_execute(
+        "ALTER TABLE items ALTER COLUMN num_plays TYPE int8, ADD COLUMN rating int4; \
+         ALTER TABLE books ALTER COLUMN title DROP NOT NULL",
+    )
+    .unwrap();
+
+    let drift = check_schema(&mut conn).unwrap();
+
+    let diff = drift.iter().map(Drift::to_string).collect::<Vec<_>>();
+    assert_eq!(
+        diff,
+        [
+            "~ column books.title: not null -> null",
+            "~ column items.num_plays: integer -> bigint",
+            "+ column items.rating integer",
+        ]
+    );
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use diesel::prelude::*;
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{NewBook, NewPage};
+use rust_pg::repo::{BookRepository, PageRepository, PostRepository};
+use rust_pg::schema::pages;
+use rust_pg::search::{config, config_exists, search_books, search_posts};
+
+use common::ScratchDatabase;
+
+fn add_page(conn: &mut PgConnection, book_id: i32, content: &str, language: &str) -> i32 {
+    let page = PageRepository::new(conn)
+        .create(&NewPage {
+            page_number: 1,
+            content,
+            book_id,
+        })
+        .unwrap();
+    diesel::update(pages::table.find(page.id))
+        .set(pages::language.eq(config(language)))
+        .execute(conn)
+        .unwrap();
+    page.id
+}
+
+#[test]
+fn ranks_pages_and_stems_by_their_language() {
+    let db = ScratchDatabase::create("search_pages");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let momo = BookRepository::new(&mut conn)
+        .create(&NewBook { title: "Momo" })
+        .unwrap();
+    let emil = BookRepository::new(&mut conn)
+        .create(&NewBook {
+            title: "Emil und die Detektive",
+        })
+        .unwrap();
+    let once = add_page(&mut conn, momo.id, "Die Kinder hörten Momo zu", "german");
+    let twice = add_page(
+        &mut conn,
+        emil.id,
+        "Die Kinder jagen den Dieb, alle Kinder Berlins helfen",
+        "german",
+    );
+    add_page(&mut conn, emil.id, "The children chase the thief", "english");
+    let simple = add_page(&mut conn, momo.id, "Kinder", "simple");
+
+    // Stemmed, "Kind" finds "Kinder" in German pages only, best match first
+    let found = search_books(&mut conn, "Kind", "german").unwrap();
+    let pages = found
+        .iter()
+        .flat_map(|book| book.children.iter().map(|page| page.page_id))
+        .collect::<Vec<_>>();
+    assert_eq!(pages, [twice, once]);
+    assert_eq!(found[0].item.id, emil.id);
+    assert!(found[0].children[0].rank > found[1].children[0].rank);
+    assert!(found[0].children[0].headline.contains("<b>Kinder</b>"));
+
+    // 'simple' only lowercases
+    assert!(search_books(&mut conn, "Kind", "simple").unwrap().is_empty());
+    let found = search_books(&mut conn, "kinder", "simple").unwrap();
+    assert_eq!(found[0].children[0].page_id, simple);
+
+    let found = search_books(&mut conn, "chasing", "english").unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].item.id, emil.id);
+
+    assert!(config_exists(&mut conn, "german").unwrap());
+    assert!(!config_exists(&mut conn, "klingon").unwrap());
+}
+
+#[test]
+fn ranks_title_matches_first_and_only_finds_published_posts() {
+    let db = ScratchDatabase::create("search_posts");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let mut posts = PostRepository::new(&mut conn);
+    let in_body = posts
+        .create("Neue Bücher", "Ein Diesel Motor auf dem Cover")
+        .unwrap();
+    let in_title = posts.create("Diesel", "Über das Cover").unwrap();
+    let draft = posts.create("Diesel Entwurf", "Diesel, Diesel").unwrap();
+    posts.publish(in_body.id).unwrap();
+    posts.publish(in_title.id).unwrap();
+
+    let found = search_posts(&mut conn, "diesel", "simple").unwrap();
+    let ids = found.iter().map(|found| found.post.id).collect::<Vec<_>>();
+    assert_eq!(ids, [in_title.id, in_body.id]);
+    assert!(!ids.contains(&draft.id));
+    assert!(found[1].headline.contains("<b>Diesel</b>"));
+
+    // Websearch syntax
+    let found = search_posts(&mut conn, "diesel -motor", "simple").unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].post.id, in_title.id);
+}
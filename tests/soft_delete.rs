@@ -0,0 +1,32 @@
+mod common;
+
+use std::time::Duration;
+
+use diesel::prelude::*;
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::NewBook;
+use rust_pg::repo::BookRepository;
+use rust_pg::schema::books;
+use rust_pg::soft_delete::purge;
+
+use common::ScratchDatabase;
+
+#[test]
+fn purges_only_rows_deleted_longer_than_the_retention() {
+    let db = ScratchDatabase::create("purge");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let book = BookRepository::new(&mut conn)
+        .create(&NewBook { title: "Momo" })
+        .unwrap();
+    BookRepository::new(&mut conn).delete(book.id).unwrap();
+
+    // Too long to subtract from now, which keeps everything instead of overflowing
+    assert_eq!(purge(&mut conn, Duration::MAX).unwrap().books, 0);
+    assert_eq!(purge(&mut conn, Duration::from_secs(3600)).unwrap().books, 0);
+    assert_eq!(books::table.count().get_result::<i64>(&mut conn), Ok(1));
+
+    assert_eq!(purge(&mut conn, Duration::ZERO).unwrap().books, 1);
+    assert_eq!(books::table.count().get_result::<i64>(&mut conn), Ok(0));
+}
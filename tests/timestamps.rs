@@ -0,0 +1,43 @@
+mod common;
+
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{BookChanges, NewBook};
+use rust_pg::repo::BookRepository;
+
+use common::ScratchDatabase;
+
+#[test]
+fn updates_bump_updated_at() {
+    let db = ScratchDatabase::create("timestamps");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let mut books = BookRepository::new(&mut conn);
+    let created = books.create(&NewBook { title: "Momo" }).unwrap();
+    assert_eq!(created.timestamps.created_at, created.timestamps.updated_at);
+
+    let updated = books
+        .update(
+            created.id,
+            &BookChanges {
+                title: Some("Momo oder Die seltsame Geschichte"),
+            },
+        )
+        .unwrap();
+    assert_eq!(updated.timestamps.created_at, created.timestamps.created_at);
+    assert!(updated.timestamps.updated_at > created.timestamps.updated_at);
+
+    // Every table with an updated_at column has the trigger
+    let untriggered = diesel::select(sql::<Text>(
+        "coalesce(string_agg(c.table_name, ', '), '') FROM information_schema.columns c \
+         WHERE c.table_schema = 'public' AND c.column_name = 'updated_at' \
+         AND NOT EXISTS (SELECT FROM pg_trigger t WHERE t.tgname = 'set_updated_at' \
+                         AND t.tgrelid = c.table_name::regclass)",
+    ))
+    .get_result::<String>(&mut conn)
+    .unwrap();
+    assert_eq!(untriggered, "");
+}
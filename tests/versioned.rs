@@ -0,0 +1,58 @@
+mod common;
+
+use rust_pg::migrations::run_pending_migrations;
+use rust_pg::models::{BookChanges, NewBook, PostChanges};
+use rust_pg::repo::{BookRepository, PostRepository, RepoError};
+
+use common::ScratchDatabase;
+
+#[test]
+fn stale_versions_conflict() {
+    let db = ScratchDatabase::create("versioned");
+    let mut conn = db.connect();
+    run_pending_migrations(&mut conn).unwrap();
+
+    let mut books = BookRepository::new(&mut conn);
+    let book = books.create(&NewBook { title: "Momo" }).unwrap();
+    assert_eq!(book.version, 1);
+
+    let changes = BookChanges {
+        title: Some("Momo oder Die seltsame Geschichte"),
+    };
+    let updated = books.update_with_version(book.id, 1, &changes).unwrap();
+    assert_eq!(updated.version, 2);
+
+    // Another editor who also read version 1
+    let result = books.update_with_version(
+        book.id,
+        1,
+        &BookChanges {
+            title: Some("Jim Knopf"),
+        },
+    );
+    assert!(matches!(
+        result,
+        Err(RepoError::StaleObject {
+            expected: 1,
+            actual: 2
+        })
+    ));
+    assert_eq!(books.get(book.id).unwrap().title, updated.title);
+
+    assert!(matches!(
+        books.update_with_version(book.id + 1, 1, &changes),
+        Err(RepoError::NotFound)
+    ));
+
+    let mut posts = PostRepository::new(&mut conn);
+    let post = posts.create("Momo", "Die grauen Herren").unwrap();
+    let changes = PostChanges {
+        title: Some("Momo"),
+        body: Some("Die Zeit-Diebe"),
+    };
+    posts.edit(post.id, post.version, &changes).unwrap();
+    assert!(matches!(
+        posts.edit(post.id, post.version, &changes),
+        Err(RepoError::StaleObject { .. })
+    ));
+}